@@ -1,14 +1,37 @@
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
 use std::path::Path;
-use std::{env, fs};
 
-/// Represents the output data for GitHub Actions
-#[derive(Debug, Clone)]
+use crate::downloader::DownloadStats;
+use crate::github::ReleaseCheck;
+use crate::history::{format_utc, BuildEvent};
+use crate::manifest::Manifest;
+use crate::progress::{format_bytes, format_duration};
+
+/// Represents the output data for GitHub Actions and the other CI sinks
+#[derive(Debug, Clone, Serialize)]
 pub struct ActionOutput {
     pub update_available: bool,
     pub version: String,
     pub checksum: String,
     pub artifact_path: String,
+    pub manifest_path: String,
+    pub previous_version: String,
+    pub version_change: String,
+    pub rollback: bool,
+    pub prerelease: bool,
+    pub reason: String,
+    /// When the CDN promoted the build, formatted as UTC
+    pub promote_time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_event: Option<BuildEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadStats>,
 }
 
 impl ActionOutput {
@@ -19,6 +42,7 @@ impl ActionOutput {
             version,
             checksum,
             artifact_path: artifact_path.display().to_string(),
+            ..Self::no_update()
         }
     }
 
@@ -29,12 +53,118 @@ impl ActionOutput {
             version: String::new(),
             checksum: String::new(),
             artifact_path: String::new(),
+            manifest_path: String::new(),
+            previous_version: String::new(),
+            version_change: String::new(),
+            rollback: false,
+            prerelease: false,
+            reason: String::new(),
+            promote_time: String::new(),
+            build_event: None,
+            download: None,
+        }
+    }
+
+    /// Records the path of the build manifest
+    pub fn with_manifest(mut self, manifest_path: &Path) -> Self {
+        self.manifest_path = manifest_path.display().to_string();
+        self
+    }
+
+    /// Records how the version relates to the previous release
+    pub fn with_release_check(mut self, check: &ReleaseCheck) -> Self {
+        self.previous_version = check.previous_version.clone().unwrap_or_default();
+        self.version_change = check.change.to_string();
+        self.rollback = check.change.is_rollback();
+        self.prerelease = check.prerelease;
+        self.reason = check.reason.clone();
+        self
+    }
+
+    /// Records when the build was promoted, in milliseconds since the Unix
+    /// epoch as reported by the versions document
+    pub fn with_promote_time(mut self, promote_time_ms: u64) -> Self {
+        self.promote_time = format_utc(promote_time_ms / 1000);
+        self
+    }
+
+    /// Records how the build relates to earlier observations
+    pub fn with_build_event(mut self, event: Option<BuildEvent>) -> Self {
+        self.build_event = event;
+        self
+    }
+
+    /// Records the transfer statistics of the download
+    pub fn with_download_stats(mut self, stats: &DownloadStats) -> Self {
+        self.download = Some(stats.clone());
+        self
+    }
+
+    /// Builds the Markdown body for the GitHub release
+    ///
+    /// The checksum line is what `should_create_release` looks for in the
+    /// latest release, so it must stay in the body.
+    pub fn release_notes(&self) -> String {
+        let mut notes = String::from("Automated release created for Revision update.\n");
+        if self.rollback {
+            notes.push_str(&format!(
+                "\n**Rollback** from {} to an older build.\n",
+                self.previous_version
+            ));
         }
+        if self.build_event == Some(BuildEvent::Repromoted) {
+            notes.push_str("\n**Re-promotion** of a previously observed build.\n");
+        }
+        if !self.promote_time.is_empty() {
+            notes.push_str(&format!("\nPromoted: {}\n", self.promote_time));
+        }
+        notes.push_str(&format!("\nChecksum (SHA-256): {}\n", self.checksum));
+        notes
+    }
+
+    /// Returns the step outputs as name/value pairs in the order they are written
+    pub fn outputs(&self) -> Vec<(&'static str, String)> {
+        let mut outputs = vec![("update_available", self.update_available.to_string())];
+        if self.update_available {
+            outputs.extend([
+                ("version", self.version.clone()),
+                ("checksum", self.checksum.clone()),
+                ("artifact_path", self.artifact_path.clone()),
+                ("manifest_path", self.manifest_path.clone()),
+                ("release_notes", self.release_notes()),
+            ]);
+        }
+        outputs.extend([
+            ("previous_version", self.previous_version.clone()),
+            ("version_change", self.version_change.clone()),
+            ("rollback", self.rollback.to_string()),
+            ("prerelease", self.prerelease.to_string()),
+            ("reason", self.reason.clone()),
+            ("promote_time", self.promote_time.clone()),
+            (
+                "build_event",
+                self.build_event
+                    .map(|event| event.to_string())
+                    .unwrap_or_default(),
+            ),
+        ]);
+        if let Some(stats) = &self.download {
+            outputs.extend([
+                ("pieces_fetched", stats.pieces_fetched.to_string()),
+                (
+                    "pieces_reused",
+                    (stats.pieces_cached + stats.pieces_reused).to_string(),
+                ),
+                ("bytes_downloaded", stats.bytes_downloaded.to_string()),
+                ("bytes_saved", stats.bytes_saved.to_string()),
+                ("download_duration_ms", stats.duration_ms.to_string()),
+            ]);
+        }
+        outputs
     }
 }
 
-/// Sets GitHub Actions output using both the deprecated ::set-output format
-/// and the new GITHUB_OUTPUT environment file format
+/// Appends the step outputs to the `$GITHUB_OUTPUT` file
 ///
 /// # Arguments
 ///
@@ -46,52 +176,202 @@ impl ActionOutput {
 ///
 /// # Notes
 ///
-/// This function uses both output methods for compatibility:
-/// - Prints to stdout using the deprecated ::set-output format
-/// - Writes to $GITHUB_OUTPUT file if the environment variable is set
+/// Outside of GitHub Actions (no `GITHUB_OUTPUT` variable) this only logs.
+/// The file is appended to so outputs written by earlier commands in the
+/// same step are preserved.
 pub fn set_github_actions_output(output: &ActionOutput) -> Result<()> {
-    if output.update_available {
-        // Set output using deprecated format (for older runners)
-        println!("::set-output name=update_available::true");
-        println!("::set-output name=version::{}", output.version);
-        println!("::set-output name=checksum::{}", output.checksum);
-        println!("::set-output name=artifact_path::{}", output.artifact_path);
-
-        // Set output using new format (for newer runners)
-        if let Ok(output_file) = env::var("GITHUB_OUTPUT") {
-            let content = format!(
-                "update_available=true\nversion={}\nchecksum={}\nartifact_path={}\n",
-                output.version, output.checksum, output.artifact_path
-            );
-            fs::write(&output_file, content).with_context(|| {
-                format!("Failed to write to GITHUB_OUTPUT file: {}", output_file)
-            })?;
-            log::debug!("Wrote output to GITHUB_OUTPUT file: {}", output_file);
-        }
+    if let Ok(output_file) = env::var("GITHUB_OUTPUT") {
+        write_outputs(Path::new(&output_file), &output.outputs())?;
+        log::debug!("Wrote output to GITHUB_OUTPUT file: {}", output_file);
+    }
 
+    if output.update_available {
         log::info!(
             "Set GitHub Actions output: update available, version={}, checksum={}",
             output.version,
             output.checksum
         );
     } else {
-        // Set output using deprecated format
-        println!("::set-output name=update_available::false");
-
-        // Set output using new format
-        if let Ok(output_file) = env::var("GITHUB_OUTPUT") {
-            fs::write(&output_file, "update_available=false\n").with_context(|| {
-                format!("Failed to write to GITHUB_OUTPUT file: {}", output_file)
-            })?;
-            log::debug!("Wrote output to GITHUB_OUTPUT file: {}", output_file);
-        }
-
         log::info!("Set GitHub Actions output: no update available");
     }
 
     Ok(())
 }
 
+/// Appends `name=value` pairs to a GitHub Actions environment file
+fn write_outputs(path: &Path, outputs: &[(&str, String)]) -> Result<()> {
+    let content: String = outputs
+        .iter()
+        .map(|(name, value)| format_output(name, value))
+        .collect();
+    append_to_file(path, &content)
+        .with_context(|| format!("Failed to write to GITHUB_OUTPUT file: {}", path.display()))
+}
+
+/// Formats a single output, using the heredoc syntax for multiline values
+fn format_output(name: &str, value: &str) -> String {
+    if !value.contains('\n') && !value.contains('\r') {
+        return format!("{}={}\n", name, value);
+    }
+
+    let delimiter = heredoc_delimiter(value);
+    let value = value.strip_suffix('\n').unwrap_or(value);
+    format!("{}<<{}\n{}\n{}\n", name, delimiter, value, delimiter)
+}
+
+/// Returns a random heredoc delimiter that does not occur in `value`
+///
+/// Values such as the release notes carry text from the CDN; a predictable
+/// delimiter would let that text end the value early and inject outputs.
+fn heredoc_delimiter(value: &str) -> String {
+    loop {
+        // Every RandomState is keyed from the operating system's random source
+        let random = RandomState::new().build_hasher().finish();
+        let delimiter = format!("ghadelimiter_{:016x}", random);
+        if !value.contains(&delimiter) {
+            return delimiter;
+        }
+    }
+}
+
+fn append_to_file(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(content.as_bytes())
+}
+
+/// Appends a Markdown job summary to `$GITHUB_STEP_SUMMARY`
+///
+/// # Arguments
+///
+/// * `output` - The release decision and version details
+/// * `manifest` - Manifest of the downloaded build, rendered as a file table
+///
+/// # Notes
+///
+/// Does nothing when `GITHUB_STEP_SUMMARY` is not set.
+pub fn write_step_summary(output: &ActionOutput, manifest: &Manifest) -> Result<()> {
+    let Ok(summary_file) = env::var("GITHUB_STEP_SUMMARY") else {
+        return Ok(());
+    };
+
+    append_to_file(
+        Path::new(&summary_file),
+        &render_step_summary(output, manifest),
+    )
+    .with_context(|| format!("Failed to write job summary: {}", summary_file))?;
+    log::debug!("Wrote job summary to {}", summary_file);
+    Ok(())
+}
+
+/// Renders the job summary as Markdown
+fn render_step_summary(output: &ActionOutput, manifest: &Manifest) -> String {
+    let decision = if output.update_available {
+        "New release"
+    } else {
+        "No release"
+    };
+
+    let mut summary = format!(
+        "## {}.{} {}\n\n| | |\n|---|---|\n",
+        manifest.repo, manifest.build, manifest.version
+    );
+    let mut row = |label: &str, value: &str| {
+        if !value.is_empty() {
+            summary.push_str(&format!("| {} | {} |\n", label, escape_cell(value)));
+        }
+    };
+    row("Decision", decision);
+    row("Reason", &output.reason);
+    row("Version", &manifest.version);
+    row("Previous version", &output.previous_version);
+    row("Version change", &output.version_change);
+    row("Promoted", &output.promote_time);
+    if let Some(event) = output.build_event {
+        row("Build event", event.as_str());
+    }
+    if !output.checksum.is_empty() {
+        row("Checksum (SHA-256)", &format!("`{}`", output.checksum));
+    }
+    if output.prerelease {
+        row("Pre-release", "yes");
+    }
+    if let Some(stats) = &output.download {
+        row(
+            "Download",
+            &format!(
+                "{} in {} ({}/{} pieces fetched, {} reused)",
+                format_bytes(stats.bytes_downloaded),
+                format_duration(std::time::Duration::from_millis(stats.duration_ms)),
+                stats.pieces_fetched,
+                stats.pieces_total,
+                format_bytes(stats.bytes_saved)
+            ),
+        );
+    }
+
+    summary.push_str("\n### Files\n\n| File | Size | Version | SHA-256 | Signed by |\n");
+    summary.push_str("|---|---:|---|---|---|\n");
+    for file in &manifest.files {
+        summary.push_str(&format!(
+            "| {} | {} | {} | `{}` | {} |\n",
+            escape_cell(&file.name),
+            file.size,
+            escape_cell(file.file_version.as_deref().unwrap_or("")),
+            file.sha256,
+            escape_cell(
+                file.signature
+                    .as_ref()
+                    .map(|signature| signature.signer_subject.as_str())
+                    .unwrap_or("")
+            )
+        ));
+    }
+
+    summary
+}
+
+/// Escapes a value for use in a Markdown table cell
+fn escape_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// Severity of a workflow annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Annotation {
+    Warning,
+    Error,
+}
+
+/// Logs `message` and, when running under GitHub Actions, emits it as a
+/// workflow annotation so it shows up on the run summary
+///
+/// The annotation goes to stderr, where the runner picks up workflow
+/// commands as well, so stdout stays clean for `--json` and other output
+/// meant for parsing.
+pub fn annotate(level: Annotation, message: &str) {
+    match level {
+        Annotation::Warning => log::warn!("{}", message),
+        Annotation::Error => log::error!("{}", message),
+    }
+
+    if env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true") {
+        eprintln!("{}", format_annotation(level, message));
+    }
+}
+
+/// Formats a `::warning::`/`::error::` workflow command
+fn format_annotation(level: Annotation, message: &str) -> String {
+    let command = match level {
+        Annotation::Warning => "warning",
+        Annotation::Error => "error",
+    };
+    let message = message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    format!("::{}::{}", command, message)
+}
+
 /// Logs the reason for the release decision
 ///
 /// # Arguments
@@ -115,6 +395,8 @@ pub fn log_release_decision(should_create: bool, reason: &str, version: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::manifest::{ManifestFile, MANIFEST_SCHEMA_VERSION};
+    use std::fs;
     use std::path::PathBuf;
     use tempfile::tempdir;
 
@@ -140,6 +422,29 @@ mod tests {
         assert_eq!(output.version, "");
         assert_eq!(output.checksum, "");
         assert_eq!(output.artifact_path, "");
+        assert!(!output.rollback);
+    }
+
+    #[test]
+    fn test_action_output_with_release_check() {
+        let check = ReleaseCheck {
+            should_create: true,
+            reason: "Rollback".to_string(),
+            previous_version: Some("1.0.0.2".to_string()),
+            change: crate::version::VersionChange::Downgrade,
+            prerelease: true,
+        };
+        let output = ActionOutput::update_available(
+            "1.0.0.1".to_string(),
+            "abc123".to_string(),
+            &PathBuf::from("/test"),
+        )
+        .with_release_check(&check);
+
+        assert!(output.rollback);
+        assert!(output.prerelease);
+        assert_eq!(output.version_change, "downgrade");
+        assert_eq!(output.previous_version, "1.0.0.2");
     }
 
     #[test]
@@ -155,6 +460,9 @@ mod tests {
         let temp_dir = tempdir().unwrap();
         let output_file = temp_dir.path().join("github_output");
 
+        // Outputs of earlier commands must survive
+        fs::write(&output_file, "earlier=1\n").unwrap();
+
         // Set the environment variable
         env::set_var("GITHUB_OUTPUT", output_file.to_str().unwrap());
 
@@ -170,13 +478,131 @@ mod tests {
         // Check that file was created and has content
         assert!(output_file.exists());
         let content = fs::read_to_string(&output_file).unwrap();
+        assert!(content.starts_with("earlier=1\n"));
         assert!(content.contains("update_available=true"));
-        assert!(content.contains("version=1.0.0"));
+        assert!(content.contains("\nversion=1.0.0\n"));
+        assert!(content.contains("release_notes<<ghadelimiter_"));
+        assert!(!content.contains("::set-output"));
 
         // Clean up
         env::remove_var("GITHUB_OUTPUT");
     }
 
+    #[test]
+    fn test_format_output_multiline() {
+        assert_eq!(format_output("a", "b"), "a=b\n");
+
+        let output = format_output("a", "x\ny\n");
+        let (delimiter, rest) = output
+            .strip_prefix("a<<")
+            .and_then(|rest| rest.split_once('\n'))
+            .unwrap();
+        assert!(delimiter.starts_with("ghadelimiter_"));
+        assert_eq!(rest, format!("x\ny\n{}\n", delimiter));
+
+        // CDN-controlled text cannot guess the delimiter to inject outputs
+        let injected = "x\nEOF\nupdate_available=false\n";
+        assert_ne!(format_output("a", injected), format_output("a", injected));
+        assert!(!format_output("a", injected).contains("<<EOF"));
+    }
+
+    #[test]
+    fn test_format_annotation_escapes_message() {
+        assert_eq!(
+            format_annotation(Annotation::Warning, "50% done\nnext"),
+            "::warning::50%25 done%0Anext"
+        );
+        assert_eq!(
+            format_annotation(Annotation::Error, "boom"),
+            "::error::boom"
+        );
+    }
+
+    #[test]
+    fn test_render_step_summary() {
+        let manifest = Manifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: "1.0.0.1".to_string(),
+            files: vec![ManifestFile {
+                name: "a|b.exe".to_string(),
+                size: 42,
+                sha256: "deadbeef".to_string(),
+                file_version: Some("1.0.0.1".to_string()),
+                signature: None,
+                signature_error: None,
+            }],
+        };
+        let mut output = ActionOutput::update_available(
+            "1.0.0.1".to_string(),
+            "abc123".to_string(),
+            &PathBuf::from("/test"),
+        );
+        output.reason = "Version changed".to_string();
+
+        let summary = render_step_summary(&output, &manifest);
+        assert!(summary.contains("| Decision | New release |"));
+        assert!(summary.contains("| Reason | Version changed |"));
+        assert!(summary.contains("`abc123`"));
+        assert!(summary.contains("| a\\|b.exe | 42 | 1.0.0.1 | `deadbeef` |  |"));
+    }
+
+    #[test]
+    fn test_promote_time_and_build_event() {
+        let output = ActionOutput::update_available(
+            "1.0.0.1".to_string(),
+            "abc123".to_string(),
+            &PathBuf::from("/test"),
+        )
+        .with_promote_time(1_699_990_000_000)
+        .with_build_event(Some(BuildEvent::Repromoted));
+
+        let notes = output.release_notes();
+        assert!(notes.contains("**Re-promotion**"));
+        assert!(notes.contains("Promoted: 2023-11-14 19:26:40 UTC"));
+        let outputs = output.outputs();
+        assert!(outputs.contains(&("promote_time", "2023-11-14 19:26:40 UTC".to_string())));
+        assert!(outputs.contains(&("build_event", "repromoted".to_string())));
+
+        let notes = output.with_build_event(None).release_notes();
+        assert!(!notes.contains("Re-promotion"));
+    }
+
+    #[test]
+    fn test_download_stats_outputs() {
+        let stats = DownloadStats {
+            pieces_total: 10,
+            pieces_fetched: 3,
+            pieces_reused: 7,
+            bytes_downloaded: 2048,
+            bytes_saved: 1024 * 1024,
+            duration_ms: 65_000,
+            ..Default::default()
+        };
+        let output = ActionOutput::no_update().with_download_stats(&stats);
+
+        let outputs = output.outputs();
+        assert!(outputs.contains(&("pieces_fetched", "3".to_string())));
+        assert!(outputs.contains(&("pieces_reused", "7".to_string())));
+        assert!(outputs.contains(&("bytes_downloaded", "2048".to_string())));
+        assert!(outputs.contains(&("download_duration_ms", "65000".to_string())));
+        assert!(!ActionOutput::no_update()
+            .outputs()
+            .iter()
+            .any(|(name, _)| *name == "bytes_downloaded"));
+
+        let manifest = Manifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: "1.0.0.1".to_string(),
+            files: Vec::new(),
+        };
+        assert!(render_step_summary(&output, &manifest)
+            .contains("| Download | 2.0 KiB in 1m05s (3/10 pieces fetched, 1.0 MiB reused) |"));
+    }
+
     #[test]
     fn test_log_release_decision() {
         // This function only logs, so we just test it doesn't panic
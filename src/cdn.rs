@@ -0,0 +1,518 @@
+use anyhow::{bail, Context, Result};
+use bytes::{Bytes, BytesMut};
+use reqwest::header::{RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, Response, StatusCode, Url};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::file_ops::AtomicFile;
+use crate::throttle::{parse_retry_after, RateLimiter};
+
+/// Root of Jagex's CDN; every upstream document and piece lives below it
+pub const DEFAULT_CDN_URL: &str = "https://jagex.akamaized.net/direct6/";
+
+/// User-Agent sent to the CDN unless overridden
+pub const DEFAULT_USER_AGENT: &str = "osrs-archive/1.0";
+
+const MAX_IDLE_CONNECTIONS: usize = 8;
+const HTTP_TIMEOUT_SECS: u64 = 300;
+/// Read size for bodies served from a local mirror
+const FILE_CHUNK_LEN: usize = 64 * 1024;
+/// How often a request is retried when the CDN answers with `Retry-After`
+const MAX_RETRY_AFTER_ATTEMPTS: usize = 3;
+/// Longest `Retry-After` delay that is waited out instead of failing
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Location of the CDN that config documents and pieces are fetched from
+///
+/// The root is either the upstream CDN, another HTTP mirror, or a local
+/// directory (`file://`) laid out like the CDN. Absolute upstream URLs found
+/// in remote documents (the catalog's `baseUrl`, the metafile location) are
+/// rebased onto the root whatever their scheme, and other hosts are refused,
+/// so a mirror never leaks requests to Jagex or anywhere else.
+///
+/// A CDN can additionally record every fetched resource into a snapshot
+/// directory that mirrors the CDN paths, and a snapshot can be replayed
+/// with [`Cdn::replay`] without any network access.
+///
+/// HTTP requests can be throttled with a bandwidth limit shared by all
+/// clones of a `Cdn` and a fixed delay before each request. `429` and `503`
+/// responses carrying `Retry-After` are retried after the requested delay.
+#[derive(Debug, Clone)]
+pub struct Cdn {
+    root: Url,
+    http_client: Client,
+    record_dir: Option<PathBuf>,
+    offline: bool,
+    user_agent: String,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    request_delay: Duration,
+}
+
+impl Cdn {
+    /// Creates a CDN client rooted at `root`, or at [`DEFAULT_CDN_URL`]
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - An `http(s)://` or `file://` URL, or a local directory path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the root is not a valid URL or existing directory,
+    /// or if the HTTP client cannot be configured.
+    pub fn new(root: Option<&str>) -> Result<Self> {
+        let root = parse_root(root.unwrap_or(DEFAULT_CDN_URL))?;
+        let http_client = Client::builder()
+            .timeout(std::time::Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .pool_max_idle_per_host(MAX_IDLE_CONNECTIONS)
+            .user_agent(DEFAULT_USER_AGENT)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let cdn = Self {
+            root,
+            http_client,
+            record_dir: None,
+            offline: false,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            rate_limiter: None,
+            request_delay: Duration::ZERO,
+        };
+        if !cdn.is_upstream() {
+            log::info!("Using CDN mirror: {}", cdn.root);
+        }
+
+        Ok(cdn)
+    }
+
+    /// Creates a CDN that serves exclusively from a recorded snapshot
+    ///
+    /// Locations outside the snapshot are rejected instead of being fetched.
+    pub fn replay(snapshot_dir: &Path) -> Result<Self> {
+        let mut cdn = Self::new(Some(&snapshot_dir.to_string_lossy()))?;
+        cdn.offline = true;
+        log::info!("Replaying CDN snapshot: {}", snapshot_dir.display());
+        Ok(cdn)
+    }
+
+    /// Records every fetched resource below `snapshot_dir`, using the same
+    /// relative paths as on the CDN
+    pub fn recording(mut self, snapshot_dir: &Path) -> Self {
+        log::info!("Recording CDN snapshot to {}", snapshot_dir.display());
+        self.record_dir = Some(snapshot_dir.to_path_buf());
+        self
+    }
+
+    /// Sends `user_agent` instead of [`DEFAULT_USER_AGENT`]
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Limits the combined download rate of all requests to `bytes_per_second`
+    pub fn with_rate_limit(mut self, bytes_per_second: u64) -> Self {
+        log::info!("Limiting CDN downloads to {} bytes/s", bytes_per_second);
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(bytes_per_second)));
+        self
+    }
+
+    /// Waits `delay` before every HTTP request
+    pub fn with_request_delay(mut self, delay: Duration) -> Self {
+        self.request_delay = delay;
+        self
+    }
+
+    /// Returns the CDN root URL
+    pub fn root(&self) -> &Url {
+        &self.root
+    }
+
+    /// Returns true if fetched resources are recorded into a snapshot
+    pub fn is_recording(&self) -> bool {
+        self.record_dir.is_some()
+    }
+
+    /// Returns true if this CDN is the upstream Jagex CDN
+    pub fn is_upstream(&self) -> bool {
+        self.root.as_str() == DEFAULT_CDN_URL
+    }
+
+    /// Resolves `location` against the CDN root
+    ///
+    /// Relative locations are joined onto the root. Absolute URLs on the
+    /// upstream CDN host are rebased onto the root, over `http` as well as
+    /// `https`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for invalid locations. With a mirror or snapshot as
+    /// root, absolute URLs that are neither below the root nor below
+    /// [`DEFAULT_CDN_URL`] are rejected rather than fetched.
+    pub fn resolve(&self, location: &str) -> Result<Url> {
+        let url = match Url::parse(location) {
+            Ok(url) => match upstream_relative_path(&url) {
+                Some(relative) => self.root.join(&relative),
+                None => Ok(url),
+            },
+            Err(_) => self.root.join(location.trim_start_matches('/')),
+        };
+
+        let url = url.with_context(|| format!("Invalid CDN location: {}", location))?;
+        if self.offline && self.relative_path(&url).is_none() {
+            bail!("{} is not part of the replayed snapshot", url);
+        }
+        if !self.is_upstream() && self.relative_path(&url).is_none() {
+            bail!("Refusing to fetch {} from outside the CDN mirror", url);
+        }
+
+        Ok(url)
+    }
+
+    /// Returns the path of `url` relative to the CDN root, if it is below it
+    fn relative_path(&self, url: &Url) -> Option<String> {
+        url.as_str()
+            .strip_prefix(self.root.as_str())
+            .filter(|relative| !relative.is_empty() && !relative.split('/').any(|s| s == ".."))
+            .map(str::to_string)
+    }
+
+    /// Fetches the resource at `url` over HTTP or from the local filesystem
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for non-success HTTP statuses, unreadable files, and
+    /// unsupported URL schemes.
+    pub async fn fetch(&self, url: &Url) -> Result<Bytes> {
+        let mut body = self.open(url).await?;
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.next_chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        body.commit_recording().await?;
+        Ok(data.freeze())
+    }
+
+    /// Starts fetching the resource at `url` without reading its body
+    ///
+    /// The body is read chunk by chunk with [`CdnBody::next_chunk`], so large
+    /// resources never have to be held in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for non-success HTTP statuses, unreadable files, and
+    /// unsupported URL schemes.
+    pub async fn open(&self, url: &Url) -> Result<CdnBody> {
+        let (source, rate_limiter) = match url.scheme() {
+            "http" | "https" => {
+                let response = self.send(url).await?;
+                if !response.status().is_success() {
+                    bail!("HTTP error {}: {}", response.status(), url);
+                }
+                (BodySource::Http(response), self.rate_limiter.clone())
+            }
+            "file" => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| anyhow::anyhow!("Invalid file URL: {}", url))?;
+                let file = File::open(&path)
+                    .await
+                    .with_context(|| format!("Failed to read mirror file: {}", path.display()))?;
+                (BodySource::File(file), None)
+            }
+            scheme => bail!("Unsupported CDN URL scheme {}: {}", scheme, url),
+        };
+
+        let recording = match &self.record_dir {
+            Some(record_dir) => self.start_recording(record_dir, url).await?,
+            None => None,
+        };
+
+        Ok(CdnBody {
+            url: url.clone(),
+            source,
+            rate_limiter,
+            recording,
+        })
+    }
+
+    /// Creates the snapshot file a fetched resource is recorded into
+    ///
+    /// The file only appears once [`CdnBody::commit_recording`] is called,
+    /// so an interrupted or unverified transfer is never replayed.
+    async fn start_recording(&self, record_dir: &Path, url: &Url) -> Result<Option<AtomicFile>> {
+        let Some(relative) = self.relative_path(url) else {
+            log::warn!("Not recording {}: outside of the CDN root", url);
+            return Ok(None);
+        };
+
+        let path = record_dir.join(relative);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        AtomicFile::create(&path).await.map(Some)
+    }
+
+    /// Sends a GET request, waiting out `Retry-After` on 429 and 503
+    async fn send(&self, url: &Url) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            if !self.request_delay.is_zero() {
+                tokio::time::sleep(self.request_delay).await;
+            }
+
+            let response = self
+                .http_client
+                .get(url.clone())
+                .header(USER_AGENT, &self.user_agent)
+                .send()
+                .await
+                .with_context(|| format!("Failed to send request to {}", url))?;
+
+            let status = response.status();
+            let retryable = matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            );
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, SystemTime::now()));
+
+            match retry_after {
+                Some(delay) if retryable && attempt < MAX_RETRY_AFTER_ATTEMPTS => {
+                    if delay > MAX_RETRY_AFTER {
+                        bail!(
+                            "HTTP error {}: {} (Retry-After of {}s exceeds {}s)",
+                            status,
+                            url,
+                            delay.as_secs(),
+                            MAX_RETRY_AFTER.as_secs()
+                        );
+                    }
+                    log::warn!(
+                        "{} answered {}, retrying after {}s",
+                        url,
+                        status,
+                        delay.as_secs_f32()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+}
+
+/// Body of a resource opened with [`Cdn::open`]
+///
+/// HTTP bodies are throttled by the CDN's rate limit, and every chunk is
+/// copied into the snapshot directory when the CDN is recording. The copy
+/// is kept only if [`CdnBody::commit_recording`] is called once the body is
+/// complete and verified; otherwise it is discarded when the body is dropped.
+pub struct CdnBody {
+    url: Url,
+    source: BodySource,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    recording: Option<AtomicFile>,
+}
+
+enum BodySource {
+    Http(Response),
+    File(File),
+}
+
+impl CdnBody {
+    /// Reads the next chunk of the body, or `None` once it is complete
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails or the snapshot file cannot be
+    /// written.
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        let chunk = match &mut self.source {
+            BodySource::Http(response) => response
+                .chunk()
+                .await
+                .with_context(|| format!("Failed to read response body from {}", self.url))?,
+            BodySource::File(file) => {
+                let mut buffer = BytesMut::with_capacity(FILE_CHUNK_LEN);
+                let read = file
+                    .read_buf(&mut buffer)
+                    .await
+                    .with_context(|| format!("Failed to read mirror file: {}", self.url))?;
+                (read > 0).then(|| buffer.freeze())
+            }
+        };
+
+        if let Some(chunk) = &chunk {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire(chunk.len()).await;
+            }
+            if let Some(recording) = &mut self.recording {
+                recording.write_all(chunk).await?;
+            }
+        }
+
+        Ok(chunk)
+    }
+
+    /// Moves the recorded copy of the body into the snapshot
+    ///
+    /// Call only after the whole body was read and verified. Does nothing
+    /// if the CDN is not recording.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot file cannot be written.
+    pub async fn commit_recording(&mut self) -> Result<()> {
+        if let Some(recording) = self.recording.take() {
+            let path = recording.path().to_path_buf();
+            recording.commit().await?;
+            log::debug!("Recorded {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Parses a CDN root given as a URL or a local directory path
+///
+/// The returned URL always ends with `/` so relative locations join below it.
+fn parse_root(root: &str) -> Result<Url> {
+    let mut url = match Url::parse(root) {
+        Ok(url) if url.scheme().len() > 1 => url,
+        _ => {
+            let path = Path::new(root)
+                .canonicalize()
+                .with_context(|| format!("CDN mirror directory not found: {}", root))?;
+            Url::from_directory_path(&path)
+                .map_err(|_| anyhow::anyhow!("Invalid CDN mirror directory: {}", root))?
+        }
+    };
+
+    if !matches!(url.scheme(), "http" | "https" | "file") {
+        bail!("Unsupported CDN URL scheme: {}", url);
+    }
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+
+    Ok(url)
+}
+
+/// Returns the path of `url` relative to [`DEFAULT_CDN_URL`] if it is on
+/// the upstream CDN host, whatever its scheme
+///
+/// Upstream URLs outside the CDN root yield `None`.
+fn upstream_relative_path(url: &Url) -> Option<String> {
+    let upstream = Url::parse(DEFAULT_CDN_URL).ok()?;
+    if url.host_str() != upstream.host_str() {
+        return None;
+    }
+    let relative = url.path().strip_prefix(upstream.path())?;
+    Some(match url.query() {
+        Some(query) => format!("{}?{}", relative, query),
+        None => relative.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_relative_and_upstream_locations() {
+        let cdn = Cdn::new(Some("http://mirror.local/cache")).unwrap();
+        assert_eq!(cdn.root().as_str(), "http://mirror.local/cache/");
+        assert!(!cdn.is_upstream());
+
+        assert_eq!(
+            cdn.resolve("osrs-win/alias.json").unwrap().as_str(),
+            "http://mirror.local/cache/osrs-win/alias.json"
+        );
+        assert_eq!(
+            cdn.resolve("https://jagex.akamaized.net/direct6/osrs-win/metafile/x.json")
+                .unwrap()
+                .as_str(),
+            "http://mirror.local/cache/osrs-win/metafile/x.json"
+        );
+        assert_eq!(
+            cdn.resolve("http://jagex.akamaized.net/direct6/osrs-win/pieces/ab/x.solidpiece")
+                .unwrap()
+                .as_str(),
+            "http://mirror.local/cache/osrs-win/pieces/ab/x.solidpiece"
+        );
+        assert_eq!(
+            cdn.resolve("http://mirror.local/cache/osrs-win/a.json")
+                .unwrap()
+                .as_str(),
+            "http://mirror.local/cache/osrs-win/a.json"
+        );
+
+        // Nothing outside the mirror is fetched
+        assert!(cdn.resolve("https://other.example/a.json").is_err());
+        assert!(cdn
+            .resolve("https://jagex.akamaized.net/other/a.json")
+            .is_err());
+    }
+
+    #[test]
+    fn test_default_root_is_upstream() {
+        let cdn = Cdn::new(None).unwrap();
+        assert!(cdn.is_upstream());
+        assert_eq!(
+            cdn.resolve("osrs-win/osrs-win.json").unwrap().as_str(),
+            "https://jagex.akamaized.net/direct6/osrs-win/osrs-win.json"
+        );
+        assert_eq!(
+            cdn.resolve("https://other.example/a.json")
+                .unwrap()
+                .as_str(),
+            "https://other.example/a.json"
+        );
+    }
+
+    #[test]
+    fn test_parse_root_rejects_unknown_schemes_and_missing_dirs() {
+        assert!(parse_root("ftp://example.com/").is_err());
+        assert!(parse_root("/definitely/not/a/mirror").is_err());
+    }
+
+    #[test]
+    fn test_replay_rejects_locations_outside_snapshot() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cdn = Cdn::replay(temp_dir.path()).unwrap();
+        assert!(cdn.resolve("osrs-win/alias.json").is_ok());
+        assert!(cdn
+            .resolve("https://jagex.akamaized.net/direct6/osrs-win/alias.json")
+            .is_ok());
+        assert!(cdn.resolve("https://other.example/a.json").is_err());
+        assert!(cdn.resolve("../outside.json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_directory_mirror() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp_dir.path().join("osrs-win")).unwrap();
+        std::fs::write(
+            temp_dir.path().join("osrs-win").join("alias.json"),
+            b"token",
+        )
+        .unwrap();
+
+        let cdn = Cdn::new(Some(temp_dir.path().to_str().unwrap())).unwrap();
+        assert_eq!(cdn.root().scheme(), "file");
+
+        let url = cdn.resolve("osrs-win/alias.json").unwrap();
+        assert_eq!(cdn.fetch(&url).await.unwrap().as_ref(), b"token");
+
+        let missing = cdn.resolve("osrs-win/missing.json").unwrap();
+        assert!(cdn.fetch(&missing).await.is_err());
+    }
+}
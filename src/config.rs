@@ -1,12 +1,15 @@
-use anyhow::{bail, Result};
+use anyhow::{Context, Result};
 use jsonwebtoken::Algorithm::RS256;
 use jsonwebtoken::{DecodingKey, Validation};
+use reqwest::Url;
 use serde_json::Value;
 use std::collections::HashSet;
 
-const VERSIONS_URL: &str = "https://jagex.akamaized.net/direct6/<repo>/<repo>.json";
-const ALIASES_URL: &str = "https://jagex.akamaized.net/direct6/<repo>/alias.json";
-const CATALOG_URL: &str = "https://jagex.akamaized.net/direct6/<repo>/catalog/<id>/catalog.json";
+use crate::cdn::Cdn;
+
+const VERSIONS_PATH: &str = "<repo>/<repo>.json";
+const ALIASES_PATH: &str = "<repo>/alias.json";
+const CATALOG_PATH: &str = "<repo>/catalog/<id>/catalog.json";
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -75,9 +78,9 @@ impl Config {
         }
     }
 
-    pub async fn load_versions(&mut self) -> Result<&mut Self> {
-        let url = &self.parse_url(VERSIONS_URL);
-        let json = Self::get_config_json(&url).await?;
+    pub async fn load_versions(&mut self, cdn: &Cdn) -> Result<&mut Self> {
+        let url = self.parse_url(VERSIONS_PATH);
+        let json = Self::get_config_json(cdn, &url).await?;
         let json = &json["environments"][&self.build];
 
         let version = Version {
@@ -91,9 +94,9 @@ impl Config {
         Ok(self)
     }
 
-    pub async fn load_alias(&mut self) -> Result<&mut Self> {
-        let url = &self.parse_url(ALIASES_URL);
-        let json = Self::get_config_json(&url).await?;
+    pub async fn load_alias(&mut self, cdn: &Cdn) -> Result<&mut Self> {
+        let url = self.parse_url(ALIASES_PATH);
+        let json = Self::get_config_json(cdn, &url).await?;
 
         let alias = json[format!("{}.{}", &self.repo, &self.build).as_str()]
             .as_str()
@@ -104,14 +107,14 @@ impl Config {
         Ok(self)
     }
 
-    pub async fn load_catalog(&mut self) -> Result<&mut Self> {
-        let url = &self.parse_url(CATALOG_URL);
+    pub async fn load_catalog(&mut self, cdn: &Cdn) -> Result<&mut Self> {
+        let url = self.parse_url(CATALOG_PATH);
         let url = url.replace("<id>", &self.alias);
-        let json = Self::get_config_json(&url).await?;
+        let json = Self::get_config_json(cdn, &url).await?;
         let config = &json["config"]["remote"];
 
         let catalog = Catalog {
-            base_url: config["baseUrl"].as_str().unwrap().to_string(),
+            base_url: config["baseUrl"].as_str().unwrap_or_default().to_string(),
             delta_format: config["deltaFormat"].as_str().unwrap().to_string(),
             flags: config["flags"].as_str().unwrap().to_string(),
             piece_format: config["pieceFormat"].as_str().unwrap().to_string(),
@@ -124,9 +127,9 @@ impl Config {
         Ok(self)
     }
 
-    pub async fn load_metafile(&mut self) -> Result<&mut Self> {
+    pub async fn load_metafile(&mut self, cdn: &Cdn) -> Result<&mut Self> {
         let url = self.parse_url(self.catalog.meta_file.as_str());
-        let json = Self::get_config_json(&url).await?;
+        let json = Self::get_config_json(cdn, &url).await?;
 
         let mut files = Vec::<MetafileEntry>::new();
         for e in json["files"].as_array().unwrap() {
@@ -168,24 +171,37 @@ impl Config {
         Ok(self)
     }
 
-    pub async fn load_all(&mut self) -> Result<&mut Self> {
-        self.load_versions().await?;
-        self.load_alias().await?;
-        self.load_catalog().await?;
-        self.load_metafile().await?;
+    pub async fn load_all(&mut self, cdn: &Cdn) -> Result<&mut Self> {
+        self.load_versions(cdn).await?;
+        self.load_alias(cdn).await?;
+        self.load_catalog(cdn).await?;
+        self.load_metafile(cdn).await?;
         Ok(self)
     }
 
-    async fn get_config_json(url: &str) -> Result<Value> {
-        let http = reqwest::Client::new();
-        let response = http.get(url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            bail!("Failed to fetch config, status code: {}", status);
-        }
+    /// Returns the URL pieces are downloaded below
+    ///
+    /// Uses the catalog's `baseUrl` when present, otherwise the repository
+    /// directory on the CDN. Either way the result is rebased onto `cdn`.
+    pub fn piece_base_url(&self, cdn: &Cdn) -> Result<Url> {
+        let base = if self.catalog.base_url.is_empty() {
+            format!("{}/", self.repo)
+        } else if self.catalog.base_url.ends_with('/') {
+            self.catalog.base_url.clone()
+        } else {
+            format!("{}/", self.catalog.base_url)
+        };
+        cdn.resolve(&base)
+    }
 
-        let raw = response.text().await?;
+    async fn get_config_json(cdn: &Cdn, location: &str) -> Result<Value> {
+        let url = cdn.resolve(location)?;
+        let body = cdn
+            .fetch(&url)
+            .await
+            .with_context(|| format!("Failed to fetch config: {}", url))?;
+        let raw = String::from_utf8(body.to_vec())
+            .with_context(|| format!("Config is not valid UTF-8: {}", url))?;
 
         let mut validation = Validation::new(RS256);
         validation.insecure_disable_signature_validation();
@@ -205,3 +221,49 @@ impl Config {
         url.replace("<repo>", &self.repo)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FixtureBuild, MockCdn};
+
+    async fn load(signed: bool) -> Config {
+        let build = FixtureBuild::new(&[("osclient.exe", b"data")]);
+        let server = MockCdn::start(&build, signed).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+
+        let mut config = Config::new(&build.repo, &build.build);
+        config.load_all(&cdn).await.unwrap();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_load_all_from_signed_and_unsigned_documents() {
+        for signed in [true, false] {
+            let config = load(signed).await;
+            assert_eq!(config.version.version, "225.1");
+            assert_eq!(config.alias, "catalog-1");
+            assert_eq!(config.catalog.piece_format, "solidpieces");
+            assert_eq!(config.metafile.id, "metafile-1");
+            assert_eq!(config.metafile.files[0].name, "osclient.exe");
+            assert_eq!(config.metafile.pieces.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_piece_base_url_is_rebased_onto_mirror() {
+        let config = load(true).await;
+        let cdn = Cdn::new(Some("http://mirror.local/")).unwrap();
+        assert_eq!(
+            config.piece_base_url(&cdn).unwrap().as_str(),
+            "http://mirror.local/osrs-win/"
+        );
+
+        let mut config = config;
+        config.catalog.base_url.clear();
+        assert_eq!(
+            config.piece_base_url(&cdn).unwrap().as_str(),
+            "http://mirror.local/osrs-win/"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::file_ops::AtomicFile;
+use crate::piece::DigestAlgorithm;
+
+/// File in the output directory describing the build extracted there
+pub const BUILD_INDEX_FILE: &str = ".build-index.json";
+
+/// Layout of an extracted build: which pieces it was assembled from and
+/// which files they were split into
+///
+/// Written after every download so the next download can rebuild unchanged
+/// pieces from the extracted files instead of fetching them again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildIndex {
+    pub metafile_id: String,
+    pub version: String,
+    pub digest_algorithm: String,
+    pub files: Vec<IndexedFile>,
+    pub pieces: Vec<IndexedPiece>,
+    /// Metafile padding in the combined piece data, which belongs to no file
+    #[serde(default)]
+    pub pads: Vec<IndexedPad>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedPad {
+    /// Offset in the combined piece data
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedPiece {
+    /// Hex digest of the decompressed piece
+    pub digest: String,
+    /// Decompressed size
+    pub size: u64,
+}
+
+impl BuildIndex {
+    /// Describes a build from its config and the downloaded pieces
+    pub fn new(config: &Config, digest: DigestAlgorithm, pieces: Vec<IndexedPiece>) -> Self {
+        Self {
+            metafile_id: config.metafile.id.clone(),
+            version: config.metafile.version.clone(),
+            digest_algorithm: digest.as_str().to_string(),
+            files: config
+                .metafile
+                .files
+                .iter()
+                .map(|file| IndexedFile {
+                    name: file.name.clone(),
+                    size: file.size,
+                })
+                .collect(),
+            pieces,
+            pads: config
+                .metafile
+                .padding
+                .iter()
+                .map(|pad| IndexedPad {
+                    offset: pad.offset,
+                    size: pad.size,
+                })
+                .collect(),
+        }
+    }
+
+    /// Total size of the padding in the combined piece data
+    pub fn padding(&self) -> u64 {
+        self.pads.iter().map(|pad| pad.size).sum()
+    }
+
+    /// Offset of each file in the combined piece data
+    ///
+    /// Files follow each other, skipping any padding that starts where the
+    /// next file would.
+    pub fn file_offsets(&self) -> Vec<u64> {
+        let mut pads = self.pads.clone();
+        pads.sort_by_key(|pad| pad.offset);
+        let mut pads = pads.into_iter().peekable();
+        let mut position = 0;
+
+        self.files
+            .iter()
+            .map(|file| {
+                while let Some(pad) = pads.next_if(|pad| pad.offset <= position) {
+                    position += pad.size;
+                }
+                let offset = position;
+                position += file.size;
+                offset
+            })
+            .collect()
+    }
+
+    /// Reads the index of the build extracted in `dir`, if there is one
+    pub fn load(dir: &Path) -> Option<Self> {
+        let path = dir.join(BUILD_INDEX_FILE);
+        let json = std::fs::read(&path).ok()?;
+        serde_json::from_slice(&json)
+            .map_err(|e| log::warn!("Ignoring invalid build index {}: {}", path.display(), e))
+            .ok()
+    }
+
+    /// Writes the index into `dir`
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(BUILD_INDEX_FILE);
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize build index")?;
+        let mut file = AtomicFile::create(&path).await?;
+        file.write_all(&json).await?;
+        file.commit()
+            .await
+            .with_context(|| format!("Failed to write build index: {}", path.display()))
+    }
+}
+
+/// A previously extracted build that unchanged pieces can be rebuilt from
+#[derive(Debug)]
+pub struct PreviousBuild {
+    root: PathBuf,
+    index: BuildIndex,
+    digest: DigestAlgorithm,
+    /// Offset and size of each piece in the concatenated file data
+    pieces: HashMap<String, (u64, u64)>,
+}
+
+impl PreviousBuild {
+    /// Loads the build extracted in `root`
+    ///
+    /// Returns `None` if there is no index, or it used another digest
+    /// algorithm so its piece digests cannot be compared.
+    pub fn load(root: &Path, digest: DigestAlgorithm) -> Option<Self> {
+        let index = BuildIndex::load(root)?;
+        if index.digest_algorithm != digest.as_str() {
+            log::info!(
+                "Previous build uses {} piece digests, not reusing it",
+                index.digest_algorithm
+            );
+            return None;
+        }
+
+        let mut pieces = HashMap::new();
+        let mut offset = 0;
+        for piece in &index.pieces {
+            pieces
+                .entry(piece.digest.clone())
+                .or_insert((offset, piece.size));
+            offset += piece.size;
+        }
+
+        log::info!(
+            "Found previous build {} ({} pieces) to reuse",
+            index.version,
+            index.pieces.len()
+        );
+        Some(Self {
+            root: root.to_path_buf(),
+            index,
+            digest,
+            pieces,
+        })
+    }
+
+    /// Returns true if the previous build contained a piece with `digest`
+    pub fn contains(&self, digest: &str) -> bool {
+        self.pieces.contains_key(digest)
+    }
+
+    /// Rebuilds the piece with `digest` from the extracted files
+    ///
+    /// Returns `None` if the piece is unknown, the files are missing or
+    /// changed, or the rebuilt data does not match the digest.
+    pub fn piece(&self, digest: &str) -> Option<Vec<u8>> {
+        let (offset, size) = *self.pieces.get(digest)?;
+        let data = self
+            .read_range(offset, size)
+            .map_err(|e| log::debug!("Failed to rebuild piece {}: {}", digest, e))
+            .ok()?;
+
+        if self.digest.hex_digest(&data) != digest {
+            log::debug!("Previous build files no longer match piece {}", digest);
+            return None;
+        }
+        Some(data)
+    }
+
+    /// Reads `size` bytes at `offset` of the combined piece data
+    ///
+    /// Padding is not stored in any file and reads as zeros.
+    fn read_range(&self, offset: u64, size: u64) -> std::io::Result<Vec<u8>> {
+        let total =
+            self.index.files.iter().map(|file| file.size).sum::<u64>() + self.index.padding();
+        let wanted_end = offset + size;
+        if wanted_end > total {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "previous build is shorter than its index",
+            ));
+        }
+
+        let mut data = vec![0; size as usize];
+        for (file, file_start) in self.index.files.iter().zip(self.index.file_offsets()) {
+            let file_end = file_start + file.size;
+            if file_end > offset && file_start < wanted_end {
+                let start = offset.max(file_start);
+                let end = wanted_end.min(file_end);
+
+                let mut handle = File::open(self.root.join(&file.name))?;
+                handle.seek(SeekFrom::Start(start - file_start))?;
+                handle.read_exact(&mut data[(start - offset) as usize..(end - offset) as usize])?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Content-addressed store of decompressed pieces shared between builds
+#[derive(Debug, Clone)]
+pub struct PieceCache {
+    dir: PathBuf,
+}
+
+impl PieceCache {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Directory the pieces are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, digest: &str) -> PathBuf {
+        self.dir.join(&digest[..2.min(digest.len())]).join(digest)
+    }
+
+    /// Returns the cached piece with `digest` if present and intact
+    pub async fn get(&self, digest: &str, algorithm: DigestAlgorithm) -> Option<Vec<u8>> {
+        let data = tokio::fs::read(self.path(digest)).await.ok()?;
+        if algorithm.hex_digest(&data) != digest {
+            log::warn!("Ignoring corrupt cached piece {}", digest);
+            return None;
+        }
+        Some(data)
+    }
+
+    /// Stores a verified piece
+    pub async fn put(&self, digest: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(digest);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut file = AtomicFile::create(&path).await?;
+        file.write_all(data).await?;
+        file.commit()
+            .await
+            .with_context(|| format!("Failed to write cached piece: {}", path.display()))
+    }
+
+    /// Stores a verified piece that was written to `source`
+    pub async fn put_file(&self, digest: &str, source: &Path) -> Result<()> {
+        let path = self.path(digest);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let mut file = AtomicFile::create(&path).await?;
+        file.copy_from(source).await?;
+        file.commit()
+            .await
+            .with_context(|| format!("Failed to write cached piece: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn previous_build(dir: &Path) -> PreviousBuild {
+        std::fs::write(dir.join("a.bin"), b"abcdef").unwrap();
+        std::fs::write(dir.join("b.bin"), b"ghij").unwrap();
+
+        let digest = DigestAlgorithm::Sha256;
+        let index = BuildIndex {
+            metafile_id: "m".to_string(),
+            version: "1".to_string(),
+            digest_algorithm: digest.as_str().to_string(),
+            files: vec![
+                IndexedFile {
+                    name: "a.bin".to_string(),
+                    size: 6,
+                },
+                IndexedFile {
+                    name: "b.bin".to_string(),
+                    size: 4,
+                },
+            ],
+            pieces: [b"abcd".as_slice(), b"efgh", b"ij"]
+                .iter()
+                .map(|data| IndexedPiece {
+                    digest: digest.hex_digest(data),
+                    size: data.len() as u64,
+                })
+                .collect(),
+            pads: Vec::new(),
+        };
+        index.save(dir).await.unwrap();
+        PreviousBuild::load(dir, digest).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_pieces_spanning_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let previous = previous_build(temp_dir.path()).await;
+
+        for data in [b"abcd".as_slice(), b"efgh", b"ij"] {
+            let digest = DigestAlgorithm::Sha256.hex_digest(data);
+            assert!(previous.contains(&digest));
+            assert_eq!(previous.piece(&digest).unwrap(), data);
+        }
+        assert!(previous.piece("00").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_pieces_across_padding() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("a.bin"), b"abc").unwrap();
+        std::fs::write(dir.join("b.bin"), b"def").unwrap();
+
+        // Combined data: "abc" [2 bytes padding] "def" [1 byte padding]
+        let digest = DigestAlgorithm::Sha256;
+        let pieces: [&[u8]; 2] = [b"abc\0", b"\0def\0"];
+        let index = BuildIndex {
+            metafile_id: "m".to_string(),
+            version: "1".to_string(),
+            digest_algorithm: digest.as_str().to_string(),
+            files: vec![
+                IndexedFile {
+                    name: "a.bin".to_string(),
+                    size: 3,
+                },
+                IndexedFile {
+                    name: "b.bin".to_string(),
+                    size: 3,
+                },
+            ],
+            pieces: pieces
+                .iter()
+                .map(|data| IndexedPiece {
+                    digest: digest.hex_digest(data),
+                    size: data.len() as u64,
+                })
+                .collect(),
+            pads: vec![
+                IndexedPad { offset: 8, size: 1 },
+                IndexedPad { offset: 3, size: 2 },
+            ],
+        };
+        assert_eq!(index.file_offsets(), vec![0, 5]);
+        assert_eq!(index.padding(), 3);
+        index.save(dir).await.unwrap();
+
+        let previous = PreviousBuild::load(dir, digest).unwrap();
+        for data in pieces {
+            assert_eq!(previous.piece(&digest.hex_digest(data)).unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_detects_modified_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let previous = previous_build(temp_dir.path()).await;
+        std::fs::write(temp_dir.path().join("b.bin"), b"GHIJ").unwrap();
+
+        let digest = DigestAlgorithm::Sha256.hex_digest(b"efgh");
+        assert!(previous.piece(&digest).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_previous_build_requires_matching_digest_algorithm() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        previous_build(temp_dir.path()).await;
+        assert!(PreviousBuild::load(temp_dir.path(), DigestAlgorithm::Sha1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_piece_cache_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache = PieceCache::new(temp_dir.path());
+        let digest = DigestAlgorithm::Sha256.hex_digest(b"piece");
+
+        assert!(cache.get(&digest, DigestAlgorithm::Sha256).await.is_none());
+        cache.put(&digest, b"piece").await.unwrap();
+        assert_eq!(
+            cache.get(&digest, DigestAlgorithm::Sha256).await.unwrap(),
+            b"piece"
+        );
+
+        cache.put(&digest, b"corrupt").await.unwrap();
+        assert!(cache.get(&digest, DigestAlgorithm::Sha256).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_piece_cache_put_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let cache = PieceCache::new(&cache_dir);
+        let digest = DigestAlgorithm::Sha256.hex_digest(b"piece");
+        let source = temp_dir.path().join("piece.bin");
+        std::fs::write(&source, b"piece").unwrap();
+
+        cache.put_file(&digest, &source).await.unwrap();
+        assert_eq!(
+            cache.get(&digest, DigestAlgorithm::Sha256).await.unwrap(),
+            b"piece"
+        );
+
+        // A failed copy leaves neither the piece nor a partial file behind
+        let other = DigestAlgorithm::Sha256.hex_digest(b"other");
+        let missing = temp_dir.path().join("missing.bin");
+        assert!(cache.put_file(&other, &missing).await.is_err());
+        let shard = cache_dir.join(&other[..2]);
+        assert_eq!(std::fs::read_dir(&shard).unwrap().count(), 0);
+    }
+}
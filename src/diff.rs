@@ -0,0 +1,575 @@
+use anyhow::{bail, Context, Result};
+use octocrab::Octocrab;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cdn::Cdn;
+use crate::delta::BuildIndex;
+use crate::downloader::Downloader;
+use crate::github::download_release_asset;
+use crate::manifest::{Manifest, ManifestFile};
+
+/// Prefix selecting a GitHub release as a diff source
+pub const RELEASE_PREFIX: &str = "tag:";
+
+/// One side of a build comparison
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildSource {
+    /// A build downloaded from the CDN, given as `repo.build`
+    Remote { repo: String, build: String },
+    /// A directory containing an extracted build
+    Directory(PathBuf),
+    /// A build manifest JSON file
+    Manifest(PathBuf),
+    /// The manifest attached to a GitHub release, given as `tag:<tag>`
+    Release(String),
+}
+
+impl BuildSource {
+    /// Parses a source given on the command line
+    ///
+    /// `tag:<tag>` selects a release; an existing directory or file is read
+    /// as an extracted build or a manifest; anything else must be `repo.build`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        if let Some(tag) = spec.strip_prefix(RELEASE_PREFIX) {
+            if tag.is_empty() {
+                bail!("Missing release tag in {:?}", spec);
+            }
+            return Ok(Self::Release(tag.to_string()));
+        }
+
+        let path = Path::new(spec);
+        if path.is_dir() {
+            return Ok(Self::Directory(path.to_path_buf()));
+        }
+        if path.is_file() {
+            return Ok(Self::Manifest(path.to_path_buf()));
+        }
+
+        match spec.split_once('.') {
+            Some((repo, build)) if !repo.is_empty() && !build.is_empty() => Ok(Self::Remote {
+                repo: repo.to_string(),
+                build: build.to_string(),
+            }),
+            _ => bail!(
+                "{:?} is not a directory, manifest, tag:<tag> or repo.build",
+                spec
+            ),
+        }
+    }
+
+    /// Returns true if loading this source needs GitHub access
+    pub fn needs_github(&self) -> bool {
+        matches!(self, Self::Release(_))
+    }
+}
+
+impl fmt::Display for BuildSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote { repo, build } => write!(f, "{}.{}", repo, build),
+            Self::Directory(path) | Self::Manifest(path) => write!(f, "{}", path.display()),
+            Self::Release(tag) => write!(f, "{}{}", RELEASE_PREFIX, tag),
+        }
+    }
+}
+
+/// GitHub repository that release sources are looked up in
+#[derive(Debug, Clone, Copy)]
+pub struct ReleaseRepository<'a> {
+    pub github: &'a Octocrab,
+    pub token: &'a str,
+    /// User-Agent sent when downloading release assets
+    pub user_agent: &'a str,
+    pub owner: &'a str,
+    pub repo: &'a str,
+    /// Name of the manifest asset attached to releases
+    pub manifest_name: &'a str,
+}
+
+/// Contents of a build, as far as its source describes them
+#[derive(Debug, Clone)]
+pub struct BuildSnapshot {
+    pub label: String,
+    pub version: String,
+    pub files: Vec<ManifestFile>,
+    /// Hex digests of the metafile pieces, if known
+    pub pieces: Option<Vec<String>>,
+}
+
+impl BuildSnapshot {
+    /// Loads the build described by `source`
+    ///
+    /// Remote builds are downloaded into a temporary directory. In a
+    /// directory source, the `generated` files, relative to the directory,
+    /// are not part of the build.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the build cannot be downloaded or read, or if a
+    /// release source is given without a GitHub repository.
+    pub async fn load(
+        source: &BuildSource,
+        cdn: &Cdn,
+        releases: Option<&ReleaseRepository<'_>>,
+        generated: &[&str],
+    ) -> Result<Self> {
+        let label = source.to_string();
+        match source {
+            BuildSource::Remote { repo, build } => {
+                let temp_dir =
+                    tempfile::tempdir().context("Failed to create temporary directory")?;
+                Downloader::new(repo.clone(), temp_dir.path().to_path_buf(), cdn.clone())
+                    .download_build(build)
+                    .await
+                    .with_context(|| format!("Failed to download {}", label))?;
+                Self::from_directory(label, temp_dir.path(), &[])
+            }
+            BuildSource::Directory(dir) => Self::from_directory(label, dir, generated),
+            BuildSource::Manifest(path) => Ok(Self::from_manifest(label, Manifest::load(path)?)),
+            BuildSource::Release(tag) => {
+                let releases =
+                    releases.context("A GitHub token is required to compare against a release")?;
+                let json = download_release_asset(
+                    releases.github,
+                    releases.token,
+                    releases.user_agent,
+                    releases.owner,
+                    releases.repo,
+                    tag,
+                    releases.manifest_name,
+                )
+                .await?
+                .with_context(|| {
+                    format!("Release {} has no asset {}", tag, releases.manifest_name)
+                })?;
+                let manifest = Manifest::from_json(&json)
+                    .with_context(|| format!("Invalid manifest in release {}", tag))?;
+                Ok(Self::from_manifest(label, manifest))
+            }
+        }
+    }
+
+    /// Describes an extracted build, using its build index for the version
+    /// and pieces when present
+    pub fn from_directory(label: String, dir: &Path, generated: &[&str]) -> Result<Self> {
+        let index = BuildIndex::load(dir);
+        let version = index
+            .as_ref()
+            .map(|index| index.version.clone())
+            .unwrap_or_default();
+        let manifest = Manifest::from_directory(dir, "", "", &version, generated)
+            .with_context(|| format!("Failed to read build in {}", dir.display()))?;
+
+        Ok(Self {
+            label,
+            version,
+            files: manifest.files,
+            pieces: index.map(|index| index.pieces.into_iter().map(|piece| piece.digest).collect()),
+        })
+    }
+
+    /// Describes a build from its manifest; manifests do not list pieces
+    pub fn from_manifest(label: String, manifest: Manifest) -> Self {
+        Self {
+            label,
+            version: manifest.version,
+            files: manifest.files,
+            pieces: None,
+        }
+    }
+}
+
+/// A file that was added, removed or modified
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileChange {
+    pub name: String,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub old_sha256: Option<String>,
+    pub new_sha256: Option<String>,
+}
+
+/// An executable whose file version changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChangeEntry {
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Piece-level difference between two builds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PieceChanges {
+    pub added: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// Differences between two builds
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildDiff {
+    pub from: String,
+    pub to: String,
+    pub from_version: String,
+    pub to_version: String,
+    pub added: Vec<FileChange>,
+    pub removed: Vec<FileChange>,
+    pub modified: Vec<FileChange>,
+    pub versions: Vec<VersionChangeEntry>,
+    /// Only present if both sides list their pieces
+    pub pieces: Option<PieceChanges>,
+}
+
+impl BuildDiff {
+    /// Compares the build `from` against the build `to`
+    pub fn compare(from: &BuildSnapshot, to: &BuildSnapshot) -> Self {
+        let mut files: BTreeMap<&str, (Option<&ManifestFile>, Option<&ManifestFile>)> =
+            BTreeMap::new();
+        for file in &from.files {
+            files.entry(&file.name).or_default().0 = Some(file);
+        }
+        for file in &to.files {
+            files.entry(&file.name).or_default().1 = Some(file);
+        }
+
+        let mut diff = Self {
+            from: from.label.clone(),
+            to: to.label.clone(),
+            from_version: from.version.clone(),
+            to_version: to.version.clone(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new(),
+            versions: Vec::new(),
+            pieces: None,
+        };
+
+        for (name, (old, new)) in files {
+            let change = FileChange {
+                name: name.to_string(),
+                old_size: old.map(|file| file.size),
+                new_size: new.map(|file| file.size),
+                old_sha256: old.map(|file| file.sha256.clone()),
+                new_sha256: new.map(|file| file.sha256.clone()),
+            };
+            match (old, new) {
+                (None, Some(_)) => diff.added.push(change),
+                (Some(_), None) => diff.removed.push(change),
+                (Some(old), Some(new)) if old.sha256 != new.sha256 => diff.modified.push(change),
+                _ => {}
+            }
+
+            let old_version = old.and_then(|file| file.file_version.clone());
+            let new_version = new.and_then(|file| file.file_version.clone());
+            if old_version != new_version {
+                diff.versions.push(VersionChangeEntry {
+                    name: name.to_string(),
+                    old: old_version,
+                    new: new_version,
+                });
+            }
+        }
+
+        if let (Some(old), Some(new)) = (&from.pieces, &to.pieces) {
+            let old: HashSet<&String> = old.iter().collect();
+            let new: HashSet<&String> = new.iter().collect();
+            diff.pieces = Some(PieceChanges {
+                added: new.difference(&old).count(),
+                removed: old.difference(&new).count(),
+                unchanged: new.intersection(&old).count(),
+            });
+        }
+
+        diff
+    }
+
+    /// Returns true if no files differ
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// Renders the diff as a Markdown section for a release body
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!(
+            "### Changes since {}\n\n",
+            version_or_label(&self.from_version, &self.from)
+        );
+        if self.is_empty() {
+            markdown.push_str("No file changes.\n");
+        } else {
+            markdown.push_str("| Change | File | Size |\n|---|---|---|\n");
+            for (kind, changes) in [
+                ("Added", &self.added),
+                ("Removed", &self.removed),
+                ("Modified", &self.modified),
+            ] {
+                for change in changes {
+                    markdown.push_str(&format!(
+                        "| {} | `{}` | {} |\n",
+                        kind,
+                        change.name,
+                        size_change(change)
+                    ));
+                }
+            }
+        }
+
+        if !self.versions.is_empty() {
+            markdown.push_str("\n**Executable versions**\n\n");
+            for version in &self.versions {
+                markdown.push_str(&format!(
+                    "- `{}`: {} → {}\n",
+                    version.name,
+                    version.old.as_deref().unwrap_or("none"),
+                    version.new.as_deref().unwrap_or("none")
+                ));
+            }
+        }
+
+        if let Some(pieces) = &self.pieces {
+            markdown.push_str(&format!(
+                "\nPieces: {} added, {} removed, {} unchanged\n",
+                pieces.added, pieces.removed, pieces.unchanged
+            ));
+        }
+        markdown
+    }
+}
+
+impl fmt::Display for BuildDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Build diff: {} -> {}",
+            version_or_label(&self.from_version, &self.from),
+            version_or_label(&self.to_version, &self.to)
+        )?;
+
+        for (title, marker, changes) in [
+            ("Added", '+', &self.added),
+            ("Removed", '-', &self.removed),
+            ("Modified", '~', &self.modified),
+        ] {
+            if changes.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n{} ({}):", title, changes.len())?;
+            for change in changes {
+                write!(f, "  {} {}  {}", marker, change.name, size_change(change))?;
+                if let (Some(old), Some(new)) = (&change.old_sha256, &change.new_sha256) {
+                    write!(f, "  sha256 {} -> {}", short_hash(old), short_hash(new))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if self.is_empty() {
+            writeln!(f, "\nNo file changes.")?;
+        }
+
+        if !self.versions.is_empty() {
+            writeln!(f, "\nExecutable versions:")?;
+            for version in &self.versions {
+                writeln!(
+                    f,
+                    "  {}  {} -> {}",
+                    version.name,
+                    version.old.as_deref().unwrap_or("none"),
+                    version.new.as_deref().unwrap_or("none")
+                )?;
+            }
+        }
+
+        if let Some(pieces) = &self.pieces {
+            writeln!(
+                f,
+                "\nPieces: {} added, {} removed, {} unchanged",
+                pieces.added, pieces.removed, pieces.unchanged
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn version_or_label(version: &str, label: &str) -> String {
+    if version.is_empty() {
+        label.to_string()
+    } else {
+        format!("{} ({})", label, version)
+    }
+}
+
+fn size_change(change: &FileChange) -> String {
+    match (change.old_size, change.new_size) {
+        (Some(old), Some(new)) => format!("{} -> {} bytes", old, new),
+        (Some(size), None) | (None, Some(size)) => format!("{} bytes", size),
+        (None, None) => String::new(),
+    }
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FixtureBuild, MockCdn};
+
+    fn file(name: &str, size: u64, sha256: &str, version: Option<&str>) -> ManifestFile {
+        ManifestFile {
+            name: name.to_string(),
+            size,
+            sha256: sha256.to_string(),
+            file_version: version.map(str::to_string),
+            signature: None,
+            signature_error: None,
+        }
+    }
+
+    fn snapshot(label: &str, files: Vec<ManifestFile>, pieces: Option<&[&str]>) -> BuildSnapshot {
+        BuildSnapshot {
+            label: label.to_string(),
+            version: String::new(),
+            files,
+            pieces: pieces.map(|pieces| pieces.iter().map(|piece| piece.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_parse_build_sources() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manifest = temp_dir.path().join("build.manifest.json");
+        std::fs::write(&manifest, b"{}").unwrap();
+
+        assert_eq!(
+            BuildSource::parse("osrs-win.production").unwrap(),
+            BuildSource::Remote {
+                repo: "osrs-win".to_string(),
+                build: "production".to_string()
+            }
+        );
+        assert_eq!(
+            BuildSource::parse("tag:225.1").unwrap(),
+            BuildSource::Release("225.1".to_string())
+        );
+        assert_eq!(
+            BuildSource::parse(temp_dir.path().to_str().unwrap()).unwrap(),
+            BuildSource::Directory(temp_dir.path().to_path_buf())
+        );
+        assert_eq!(
+            BuildSource::parse(manifest.to_str().unwrap()).unwrap(),
+            BuildSource::Manifest(manifest)
+        );
+        assert!(BuildSource::parse("tag:").is_err());
+        assert!(BuildSource::parse("production").is_err());
+    }
+
+    #[test]
+    fn test_compare_files_versions_and_pieces() {
+        let from = snapshot(
+            "old",
+            vec![
+                file("osclient.exe", 10, "aa", Some("1.0.0.1")),
+                file("removed.txt", 3, "bb", None),
+                file("same.txt", 4, "cc", None),
+            ],
+            Some(&["p1", "p2", "p3"]),
+        );
+        let to = snapshot(
+            "new",
+            vec![
+                file("added.dll", 5, "dd", Some("2.0.0.0")),
+                file("osclient.exe", 12, "ee", Some("1.0.0.2")),
+                file("same.txt", 4, "cc", None),
+            ],
+            Some(&["p1", "p4"]),
+        );
+
+        let diff = BuildDiff::compare(&from, &to);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "added.dll");
+        assert_eq!(diff.removed[0].name, "removed.txt");
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].old_size, Some(10));
+        assert_eq!(diff.modified[0].new_size, Some(12));
+        assert_eq!(
+            diff.versions,
+            vec![
+                VersionChangeEntry {
+                    name: "added.dll".to_string(),
+                    old: None,
+                    new: Some("2.0.0.0".to_string()),
+                },
+                VersionChangeEntry {
+                    name: "osclient.exe".to_string(),
+                    old: Some("1.0.0.1".to_string()),
+                    new: Some("1.0.0.2".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            diff.pieces,
+            Some(PieceChanges {
+                added: 1,
+                removed: 2,
+                unchanged: 1,
+            })
+        );
+
+        let text = diff.to_string();
+        assert!(text.contains("  + added.dll  5 bytes"));
+        assert!(text.contains("  ~ osclient.exe  10 -> 12 bytes  sha256 aa -> ee"));
+        assert!(text.contains("osclient.exe  1.0.0.1 -> 1.0.0.2"));
+
+        let markdown = diff.to_markdown();
+        assert!(markdown.contains("| Removed | `removed.txt` | 3 bytes |"));
+        assert!(markdown.contains("Pieces: 1 added, 2 removed, 1 unchanged"));
+    }
+
+    #[test]
+    fn test_compare_identical_builds_without_pieces() {
+        let build = snapshot("build", vec![file("a.txt", 1, "aa", None)], None);
+        let diff = BuildDiff::compare(&build, &build);
+        assert!(diff.is_empty());
+        assert!(diff.pieces.is_none());
+        assert!(diff.to_string().contains("No file changes."));
+    }
+
+    #[tokio::test]
+    async fn test_compare_remote_build_with_directory() {
+        let build = FixtureBuild::new(&[("a.txt", b"new contents"), ("b.txt", b"same")]);
+        let server = MockCdn::start(&build, true).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+
+        let previous = tempfile::tempdir().unwrap();
+        std::fs::write(previous.path().join("a.txt"), b"old").unwrap();
+        std::fs::write(previous.path().join("b.txt"), b"same").unwrap();
+
+        let from = BuildSnapshot::load(
+            &BuildSource::Directory(previous.path().to_path_buf()),
+            &cdn,
+            None,
+            &[],
+        )
+        .await
+        .unwrap();
+        let source = BuildSource::parse(&format!("{}.{}", build.repo, build.build)).unwrap();
+        let to = BuildSnapshot::load(&source, &cdn, None, &[]).await.unwrap();
+
+        assert_eq!(to.version, build.version);
+        assert_eq!(to.pieces.as_ref().unwrap().len(), build.piece_data().len());
+        let diff = BuildDiff::compare(&from, &to);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].name, "a.txt");
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+        let error = BuildSnapshot::load(&BuildSource::Release("1".to_string()), &cdn, None, &[])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("GitHub token"));
+    }
+}
@@ -1,21 +1,75 @@
+use crate::cdn::Cdn;
 use crate::config::{Config, MetafileEntry};
+use crate::delta::{BuildIndex, IndexedPiece, PieceCache, PreviousBuild, BUILD_INDEX_FILE};
+use crate::file_ops::AtomicFile;
+use crate::piece::{DecodedPiece, PieceDecoder, PieceFormat};
+use crate::progress::{Progress, ProgressMode, ProgressReporter};
+use crate::verify::verify_build;
 use anyhow::{bail, Context, Result};
 use base64::engine::general_purpose;
 use base64::Engine;
-use flate2::read::GzDecoder;
 use futures_util::future::try_join_all;
-use reqwest::{Client, Url};
-use sha2::{Digest, Sha256};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::create_dir_all;
-use std::io::Read;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tempfile::TempDir;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-const BASE_DOWNLOAD_URL: &str = "https://jagex.akamaized.net/direct6";
 const MAX_CONCURRENT_DOWNLOADS: usize = 8;
-const HTTP_TIMEOUT_SECS: u64 = 300;
+
+/// Transfer statistics of a build download
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadStats {
+    pub pieces_total: usize,
+    /// Decompressed size of the build according to the metafile
+    #[serde(default)]
+    pub bytes_expected: u64,
+    /// Pieces downloaded from the CDN
+    pub pieces_fetched: usize,
+    /// Pieces found in the piece cache
+    pub pieces_cached: usize,
+    /// Pieces rebuilt from the previous build extracted in the output directory
+    #[serde(default)]
+    pub pieces_reused: usize,
+    /// Compressed bytes received from the CDN
+    pub bytes_downloaded: u64,
+    /// Decompressed size of all pieces
+    pub bytes_decompressed: u64,
+    /// Decompressed size of the pieces that did not have to be downloaded
+    #[serde(default)]
+    pub bytes_saved: u64,
+    pub files_extracted: usize,
+    pub duration_ms: u64,
+}
+
+/// Result of a build download
+#[derive(Debug, Clone)]
+pub struct DownloadResult {
+    pub config: Config,
+    pub stats: DownloadStats,
+}
+
+/// Where the data of a piece came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PieceSource {
+    Downloaded,
+    Cache,
+    PreviousBuild,
+}
+
+/// Outcome of processing a single piece
+#[derive(Debug, Clone, Copy)]
+struct PieceOutcome {
+    source: PieceSource,
+    downloaded: u64,
+    decompressed: u64,
+}
 
 /// A downloader that handles OSRS client archive downloads
 ///
@@ -26,20 +80,24 @@ const HTTP_TIMEOUT_SECS: u64 = 300;
 ///
 /// ```rust,no_run
 /// use std::path::PathBuf;
+/// use osrs_archive::cdn::Cdn;
 /// use osrs_archive::downloader::Downloader;
 ///
 /// # async fn example() -> anyhow::Result<()> {
 /// let output_dir = PathBuf::from("./downloads");
-/// let downloader = Downloader::new("osrs".to_string(), output_dir)?;
+/// let downloader = Downloader::new("osrs".to_string(), output_dir, Cdn::new(None)?);
 /// downloader.download_build("live").await?;
 /// # Ok(())
 /// # }
 /// ```
 #[derive(Debug)]
 pub struct Downloader {
-    http_client: Client,
+    cdn: Cdn,
     repo: String,
     output_dir: PathBuf,
+    work_dir: Option<PathBuf>,
+    piece_cache: Option<PieceCache>,
+    progress: ProgressMode,
 }
 
 impl Downloader {
@@ -49,23 +107,39 @@ impl Downloader {
     ///
     /// * `repo` - The repository name (e.g., "osrs", "osrs3")  
     /// * `output_dir` - The directory where downloaded files will be extracted
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the HTTP client cannot be configured.
-    pub fn new(repo: String, output_dir: PathBuf) -> Result<Self> {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(HTTP_TIMEOUT_SECS))
-            .pool_max_idle_per_host(MAX_CONCURRENT_DOWNLOADS)
-            .user_agent("osrs-archive/1.0")
-            .build()
-            .context("Failed to create HTTP client")?;
-
-        Ok(Self {
-            http_client,
+    /// * `cdn` - CDN or local mirror to fetch config documents and pieces from
+    pub fn new(repo: String, output_dir: PathBuf, cdn: Cdn) -> Self {
+        Self {
+            cdn,
             repo,
             output_dir,
-        })
+            work_dir: None,
+            piece_cache: None,
+            progress: ProgressMode::Off,
+        }
+    }
+
+    /// Shows download progress as a bar or periodic log lines
+    pub fn with_progress(mut self, mode: ProgressMode) -> Self {
+        self.progress = mode;
+        self
+    }
+
+    /// Creates the staging directory below `dir` instead of next to the
+    /// output directory
+    ///
+    /// The staging directory is renamed into place, so `dir` should be on the
+    /// same filesystem as the output directory.
+    pub fn with_work_dir(mut self, dir: &Path) -> Self {
+        self.work_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Looks up pieces in, and stores downloaded pieces into, a cache
+    /// directory shared between builds
+    pub fn with_piece_cache(mut self, dir: &Path) -> Self {
+        self.piece_cache = Some(PieceCache::new(dir));
+        self
     }
 
     /// Downloads and extracts a client build
@@ -73,10 +147,24 @@ impl Downloader {
     /// This is the main entry point for downloading a complete client build.
     /// It handles the entire process from configuration loading to final cleanup.
     ///
+    /// The build is assembled and verified in a fresh staging directory,
+    /// which then replaces the output directory, so a failed download never
+    /// leaves a partial build behind and files of earlier builds never mix
+    /// with the new one.
+    ///
+    /// Only pieces that changed since the build previously extracted in the
+    /// output directory are downloaded; unchanged pieces are rebuilt from its
+    /// files or taken from the piece cache.
+    ///
     /// # Arguments
     ///
     /// * `build` - The build identifier (e.g., "live", "beta")
     ///
+    /// # Returns
+    ///
+    /// Returns the remote configuration the build was downloaded from together
+    /// with transfer statistics.
+    ///
     /// # Errors
     ///
     /// Returns an error if any step of the download process fails, including:
@@ -85,189 +173,373 @@ impl Downloader {
     /// - Piece downloads
     /// - File extraction
     /// - Cleanup operations
-    pub async fn download_build(&self, build: &str) -> Result<()> {
+    /// - Verification of the extracted files
+    /// - An output directory that holds anything but a previous build, or
+    ///   contains the piece cache
+    /// - Moving the staged build into the output directory
+    pub async fn download_build(&self, build: &str) -> Result<DownloadResult> {
+        let started = Instant::now();
         log::info!("Downloading client {}.{}...", self.repo, build);
 
+        check_replaceable(&self.output_dir)?;
+        if let Some(cache) = &self.piece_cache {
+            let output_dir = std::path::absolute(&self.output_dir)
+                .with_context(|| format!("Invalid directory: {}", self.output_dir.display()))?;
+            let cache_dir = std::path::absolute(cache.dir())
+                .with_context(|| format!("Invalid directory: {}", cache.dir().display()))?;
+            if cache_dir.starts_with(&output_dir) {
+                bail!(
+                    "The piece cache {} must not be inside the output directory {}, which is replaced by every download",
+                    cache_dir.display(),
+                    output_dir.display()
+                );
+            }
+        }
+
         let mut config = Config::new(&self.repo, build);
         config
-            .load_all()
+            .load_all(&self.cdn)
             .await
             .context("Failed to load configuration")?;
         log::info!("Loaded remote config data.");
 
-        self.ensure_output_directory()
-            .context("Failed to create output directory")?;
+        let staging = self
+            .create_staging_directory()
+            .context("Failed to create staging directory")?;
+        let staging_dir = staging.path();
 
-        let piece_urls = self
-            .generate_piece_urls(&config.metafile.pieces)
+        let piece_base = config.piece_base_url(&self.cdn)?;
+        log::debug!("Downloading pieces from {}", piece_base);
+        let format = PieceFormat::from_config(&config)?;
+        let piece_urls = Self::generate_piece_urls(&piece_base, &config.metafile.pieces, format)
             .context("Failed to generate piece URLs")?;
         log::info!("Found {} pieces to download.", piece_urls.len());
 
-        self.download_and_process_pieces(&piece_urls)
+        if !config.catalog.delta_format.is_empty() {
+            log::info!(
+                "Delta format {:?} is not supported, reusing unchanged pieces instead",
+                config.catalog.delta_format
+            );
+        }
+        // Reused pieces are never fetched, so a recorded snapshot would lack them
+        let previous = if self.cdn.is_recording() {
+            log::info!("Recording the CDN, downloading every piece instead of reusing pieces");
+            None
+        } else {
+            PreviousBuild::load(&self.output_dir, format.digest)
+        };
+
+        let bytes_expected = config
+            .metafile
+            .files
+            .iter()
+            .map(|file| file.size)
+            .sum::<u64>()
+            + config
+                .metafile
+                .padding
+                .iter()
+                .map(|pad| pad.size)
+                .sum::<u64>();
+        let progress = Arc::new(Progress::new(piece_urls.len(), bytes_expected));
+        let reporter = ProgressReporter::start(progress.clone(), self.progress);
+        let (mut stats, pieces) = self
+            .download_and_process_pieces(
+                staging_dir,
+                &piece_urls,
+                format,
+                previous.as_ref(),
+                &progress,
+            )
             .await
             .context("Failed to download pieces")?;
+        reporter.finish();
+        stats.bytes_expected = bytes_expected;
 
         let combined_path = self
-            .combine_piece_files(&piece_urls)
+            .combine_piece_files(staging_dir, &piece_urls)
             .await
             .context("Failed to combine piece files")?;
 
-        self.extract_files_from_archive(&combined_path, &config.metafile.files)
-            .await
-            .context("Failed to extract files")?;
+        let index = BuildIndex::new(&config, format.digest, pieces);
+        self.extract_files_from_archive(
+            staging_dir,
+            &combined_path,
+            &config.metafile.files,
+            &index,
+        )
+        .await
+        .context("Failed to extract files")?;
 
-        self.cleanup_temporary_files(&combined_path)
+        self.cleanup_temporary_files(staging_dir, &combined_path)
             .await
             .context("Failed to cleanup temporary files")?;
+        index.save(staging_dir).await?;
 
-        log::info!("Download complete!");
-        Ok(())
+        let verification = verify_build(staging_dir, &index, &[])?;
+        if !verification.is_valid() {
+            bail!("Extracted build failed verification:\n{}", verification);
+        }
+        log::info!("Verified {} extracted files.", verification.files_checked);
+
+        promote_directory(staging_dir, &self.output_dir)
+            .context("Failed to move the build into the output directory")?;
+
+        stats.files_extracted = config.metafile.files.len();
+        stats.duration_ms = started.elapsed().as_millis() as u64;
+        log::info!(
+            "Download complete! {} pieces fetched, {} reused, {} bytes downloaded, {} bytes saved",
+            stats.pieces_fetched,
+            stats.pieces_cached + stats.pieces_reused,
+            stats.bytes_downloaded,
+            stats.bytes_saved
+        );
+        Ok(DownloadResult { config, stats })
     }
 
-    /// Ensures the output directory exists, creating it if necessary
-    fn ensure_output_directory(&self) -> Result<()> {
-        if !self.output_dir.exists() {
-            fs::create_dir_all(&self.output_dir).with_context(|| {
-                format!("Failed to create directory: {}", self.output_dir.display())
-            })?;
-            log::warn!(
-                "Created missing output directory: {}",
-                self.output_dir.display()
-            );
-        }
-        Ok(())
+    /// Creates a fresh staging directory in the work directory, or next to
+    /// the output directory so it can be renamed into place
+    fn create_staging_directory(&self) -> Result<TempDir> {
+        let parent = match &self.work_dir {
+            Some(work_dir) => work_dir.clone(),
+            None => parent_directory(&self.output_dir)?,
+        };
+        fs::create_dir_all(&parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+
+        let staging = tempfile::Builder::new()
+            .prefix(".staging-")
+            .tempdir_in(&parent)
+            .with_context(|| format!("Failed to create directory in {}", parent.display()))?;
+        log::debug!("Staging build in {}", staging.path().display());
+        Ok(staging)
     }
 
-    /// Generates piece URLs from digest strings
-    fn generate_piece_urls(&self, pieces: &[String]) -> Result<Vec<Url>> {
+    /// Generates piece URLs below `base` from digest strings
+    fn generate_piece_urls(base: &Url, pieces: &[String], format: PieceFormat) -> Result<Vec<Url>> {
         pieces
             .iter()
             .map(|digest| {
                 let digest_bytes = general_purpose::STANDARD
                     .decode(digest.as_str())
                     .with_context(|| format!("Failed to decode base64 digest: {}", digest))?;
+                if digest_bytes.len() != format.digest.output_len() {
+                    bail!(
+                        "Piece digest {} is {} bytes, expected {} for {}",
+                        digest,
+                        digest_bytes.len(),
+                        format.digest.output_len(),
+                        format.digest
+                    );
+                }
                 let digest_hex_str = hex::encode(&digest_bytes);
 
-                let piece_url = format!(
-                    "{BASE_DOWNLOAD_URL}/{}/pieces/{}/{}.solidpiece",
-                    self.repo,
+                let piece_path = format!(
+                    "pieces/{}/{}.solidpiece",
                     digest_hex_str
                         .get(0..2)
                         .ok_or_else(|| anyhow::anyhow!("Invalid digest hex string"))?,
                     digest_hex_str
                 );
 
-                piece_url
-                    .parse()
-                    .with_context(|| format!("Failed to parse piece URL: {}", piece_url))
+                base.join(&piece_path)
+                    .with_context(|| format!("Failed to build piece URL: {}", piece_path))
             })
             .collect()
     }
 
     /// Downloads and processes all piece files concurrently
-    async fn download_and_process_pieces(&self, piece_urls: &[Url]) -> Result<()> {
+    ///
+    /// Returns the transfer statistics and the digest and decompressed size
+    /// of every piece in order.
+    async fn download_and_process_pieces(
+        &self,
+        staging_dir: &Path,
+        piece_urls: &[Url],
+        format: PieceFormat,
+        previous: Option<&PreviousBuild>,
+        progress: &Progress,
+    ) -> Result<(DownloadStats, Vec<IndexedPiece>)> {
+        let mut stats = DownloadStats {
+            pieces_total: piece_urls.len(),
+            ..Default::default()
+        };
+        let mut pieces = Vec::with_capacity(piece_urls.len());
+
         // Process downloads in batches to avoid overwhelming the server
         for chunk in piece_urls.chunks(MAX_CONCURRENT_DOWNLOADS) {
             let futures: Vec<_> = chunk
                 .iter()
-                .map(|url| self.download_and_process_single_piece(url))
+                .map(|url| async move {
+                    let outcome = self
+                        .download_and_process_single_piece(staging_dir, url, format, previous)
+                        .await?;
+                    progress.piece_done(outcome.downloaded, outcome.decompressed);
+                    Ok::<_, anyhow::Error>(outcome)
+                })
                 .collect();
 
-            try_join_all(futures)
+            let outcomes = try_join_all(futures)
                 .await
                 .context("Failed to download piece batch")?;
+
+            for (url, outcome) in chunk.iter().zip(outcomes) {
+                match outcome.source {
+                    PieceSource::Downloaded => stats.pieces_fetched += 1,
+                    PieceSource::Cache => stats.pieces_cached += 1,
+                    PieceSource::PreviousBuild => stats.pieces_reused += 1,
+                }
+                if outcome.source != PieceSource::Downloaded {
+                    stats.bytes_saved += outcome.decompressed;
+                }
+                stats.bytes_downloaded += outcome.downloaded;
+                stats.bytes_decompressed += outcome.decompressed;
+                pieces.push(IndexedPiece {
+                    digest: Self::piece_digest(url).to_string(),
+                    size: outcome.decompressed,
+                });
+            }
         }
 
-        Ok(())
+        Ok((stats, pieces))
+    }
+
+    /// Returns the hex digest a piece URL is named after
+    fn piece_digest(piece_url: &Url) -> &str {
+        piece_url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.strip_suffix(".solidpiece"))
+            .unwrap_or_default()
+    }
+
+    /// Looks up a piece in the piece cache and the previous build
+    ///
+    /// Nothing is reused while the CDN is recording, so that the snapshot
+    /// holds every piece.
+    async fn reusable_piece(
+        &self,
+        piece_url: &Url,
+        format: PieceFormat,
+        previous: Option<&PreviousBuild>,
+    ) -> Option<(Vec<u8>, PieceSource)> {
+        if self.cdn.is_recording() {
+            return None;
+        }
+        let digest = Self::piece_digest(piece_url);
+        if let Some(cache) = &self.piece_cache {
+            if let Some(data) = cache.get(digest, format.digest).await {
+                return Some((data, PieceSource::Cache));
+            }
+        }
+        previous
+            .and_then(|previous| previous.piece(digest))
+            .map(|data| (data, PieceSource::PreviousBuild))
     }
 
     /// Downloads and processes a single piece file
-    async fn download_and_process_single_piece(&self, piece_url: &Url) -> Result<()> {
+    async fn download_and_process_single_piece(
+        &self,
+        staging_dir: &Path,
+        piece_url: &Url,
+        format: PieceFormat,
+        previous: Option<&PreviousBuild>,
+    ) -> Result<PieceOutcome> {
         let file_name = Self::extract_filename_from_url(piece_url);
-        log::info!("Downloading piece: {}", file_name);
+        let piece_path = staging_dir.join(&file_name);
+        if let Some((data, source)) = self.reusable_piece(piece_url, format, previous).await {
+            log::debug!("Reusing unchanged piece: {}", file_name);
+            Self::write_piece_to_file(&data, &piece_path).await?;
+            return Ok(PieceOutcome {
+                source,
+                downloaded: 0,
+                decompressed: data.len() as u64,
+            });
+        }
 
-        let response = self
-            .http_client
-            .get(piece_url.clone())
-            .send()
-            .await
-            .with_context(|| format!("Failed to send request to {}", piece_url))?;
+        log::debug!("Downloading piece: {}", file_name);
+        let (downloaded, piece) = self
+            .stream_piece_to_file(piece_url, format, &piece_path)
+            .await?;
+        log::debug!("Checksum for file {} verified!", file_name);
 
-        if !response.status().is_success() {
-            bail!("HTTP error {}: {}", response.status(), piece_url);
+        if let Some(cache) = &self.piece_cache {
+            cache
+                .put_file(Self::piece_digest(piece_url), &piece_path)
+                .await?;
         }
 
-        let bytes = response
-            .bytes()
-            .await
-            .context("Failed to read response bytes")?;
+        Ok(PieceOutcome {
+            source: PieceSource::Downloaded,
+            downloaded,
+            decompressed: piece.size,
+        })
+    }
 
-        log::info!("Decompressing piece: {}", file_name);
-        let decompressed_bytes = Self::decompress_piece_data(&bytes, piece_url)?;
+    /// Streams a piece body through the decoder into `piece_path`
+    ///
+    /// Returns the number of body bytes received and the digest and size of
+    /// the decompressed piece. Only one chunk of the body and its
+    /// decompressed data are held in memory at a time. The piece file and
+    /// the recorded body only appear once the digest is verified.
+    async fn stream_piece_to_file(
+        &self,
+        piece_url: &Url,
+        format: PieceFormat,
+        piece_path: &Path,
+    ) -> Result<(u64, DecodedPiece)> {
+        let mut body = self.cdn.open(piece_url).await?;
+        let mut decoder = PieceDecoder::new(format);
+        let mut file = AtomicFile::create(piece_path).await?;
 
-        self.write_piece_to_file(&decompressed_bytes, &file_name)
-            .await?;
+        let mut downloaded = 0;
+        while let Some(chunk) = body.next_chunk().await? {
+            downloaded += chunk.len() as u64;
+            let data = decoder
+                .feed(&chunk)
+                .with_context(|| format!("Failed to decode piece {}", piece_url))?;
+            file.write_all(&data).await?;
+        }
 
-        Self::verify_piece_checksum(&decompressed_bytes, &file_name, piece_url)?;
-        log::info!("Checksum for file {} verified!", file_name);
+        let (data, piece) = decoder
+            .finish()
+            .with_context(|| format!("Failed to decode piece {}", piece_url))?;
+        file.write_all(&data).await?;
 
-        Ok(())
+        let file_name = Self::extract_filename_from_url(piece_url);
+        Self::verify_piece_checksum(&piece.digest, &file_name, piece_url, format)?;
+        file.commit().await?;
+        body.commit_recording().await?;
+
+        Ok((downloaded, piece))
     }
 
     /// Extracts filename from piece URL
     fn extract_filename_from_url(piece_url: &Url) -> String {
         piece_url
             .path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
             .unwrap_or("unknown")
             .to_string()
     }
 
-    /// Decompresses piece data using gzip
-    fn decompress_piece_data(bytes: &[u8], piece_url: &Url) -> Result<Vec<u8>> {
-        if bytes.len() < 6 {
-            bail!("Invalid piece data: too short");
-        }
-
-        let bytes = &bytes[6..]; // Skip first 6 bytes
-        let mut decoder = GzDecoder::new(bytes);
-        let mut decompressed_bytes = Vec::new();
-
-        decoder
-            .read_to_end(&mut decompressed_bytes)
-            .with_context(|| format!("Failed to decompress piece data for {}", piece_url))?;
-
-        Ok(decompressed_bytes)
-    }
-
-    /// Writes piece data to file
-    async fn write_piece_to_file(&self, data: &[u8], file_name: &str) -> Result<()> {
-        let file_path = self.output_dir.join(file_name);
-        if file_path.exists() {
-            tokio::fs::remove_file(&file_path).await.with_context(|| {
-                format!("Failed to remove existing file: {}", file_path.display())
-            })?;
-        }
-
-        let mut file = File::create(&file_path)
-            .await
-            .with_context(|| format!("Failed to create file: {}", file_path.display()))?;
-        file.write_all(data)
-            .await
-            .with_context(|| format!("Failed to write to file: {}", file_path.display()))?;
-
-        Ok(())
+    /// Writes verified piece data to file
+    async fn write_piece_to_file(data: &[u8], file_path: &Path) -> Result<()> {
+        let mut file = AtomicFile::create(file_path).await?;
+        file.write_all(data).await?;
+        file.commit().await
     }
 
-    /// Verifies piece checksum
-    fn verify_piece_checksum(data: &[u8], file_name: &str, piece_url: &Url) -> Result<()> {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let checksum = format!("{:x}", hasher.finalize());
-
+    /// Verifies the digest of a decoded piece against its URL
+    fn verify_piece_checksum(
+        checksum: &str,
+        file_name: &str,
+        piece_url: &Url,
+        format: PieceFormat,
+    ) -> Result<()> {
         let expected_digest = piece_url
             .path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
             .ok_or_else(|| anyhow::anyhow!("Invalid piece URL: no filename"))?
             .strip_suffix(".solidpiece")
             .ok_or_else(|| {
@@ -276,8 +548,9 @@ impl Downloader {
 
         if checksum != expected_digest {
             bail!(
-                "Checksum mismatch for {}! Expected {}, got {}",
+                "Checksum mismatch for {}! Expected {} {}, got {}",
                 file_name,
+                format.digest,
                 expected_digest,
                 checksum
             );
@@ -287,10 +560,10 @@ impl Downloader {
     }
 
     /// Combines all piece files into a single file
-    async fn combine_piece_files(&self, piece_urls: &[Url]) -> Result<PathBuf> {
+    async fn combine_piece_files(&self, staging_dir: &Path, piece_urls: &[Url]) -> Result<PathBuf> {
         log::info!("Combining piece files...");
 
-        let combined_path = self.output_dir.join("combined_file");
+        let combined_path = staging_dir.join("combined_file");
         if combined_path.exists() {
             tokio::fs::remove_file(&combined_path)
                 .await
@@ -303,7 +576,7 @@ impl Downloader {
 
         let pieces_paths: Vec<PathBuf> = piece_urls
             .iter()
-            .map(|url| self.output_dir.join(Self::extract_filename_from_url(url)))
+            .map(|url| staging_dir.join(Self::extract_filename_from_url(url)))
             .collect();
 
         let mut total_size = 0;
@@ -334,19 +607,35 @@ impl Downloader {
     }
 
     /// Extracts individual files from the combined archive
+    ///
+    /// Each file is read at its offset in the index, skipping the padding in
+    /// between. Fails if the combined file is shorter than the listed files,
+    /// or has more bytes left over than the metafile padding accounts for.
     async fn extract_files_from_archive(
         &self,
+        staging_dir: &Path,
         combined_path: &Path,
         file_list: &[MetafileEntry],
+        index: &BuildIndex,
     ) -> Result<()> {
         let mut source_file = File::open(combined_path).await.with_context(|| {
             format!("Failed to open combined file: {}", combined_path.display())
         })?;
+        let combined_size = source_file
+            .metadata()
+            .await
+            .context("Failed to read combined file metadata")?
+            .len();
+        let mut consumed = 0;
 
-        for file in file_list.iter() {
+        for (file, offset) in file_list.iter().zip(index.file_offsets()) {
             let file_name = &file.name;
             let file_size = file.size as usize;
 
+            source_file
+                .seek(SeekFrom::Start(offset))
+                .await
+                .with_context(|| format!("Failed to seek to file: {}", file_name))?;
             let mut file_output = vec![0u8; file_size];
             source_file
                 .read_exact(&mut file_output)
@@ -355,42 +644,53 @@ impl Downloader {
                     format!("Failed to read {} bytes for file: {}", file_size, file_name)
                 })?;
 
-            let output_file_path = self.output_dir.join(file_name);
+            let output_file_path = staging_dir.join(file_name);
             if let Some(parent) = output_file_path.parent() {
                 create_dir_all(parent)
                     .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
             }
 
-            let mut output = File::create(&output_file_path).await.with_context(|| {
-                format!(
-                    "Failed to create output file: {}",
-                    output_file_path.display()
-                )
-            })?;
-            output.write_all(&file_output).await.with_context(|| {
+            let mut output = AtomicFile::create(&output_file_path).await?;
+            output.write_all(&file_output).await?;
+            output.commit().await.with_context(|| {
                 format!(
                     "Failed to write output file: {}",
                     output_file_path.display()
                 )
             })?;
 
+            consumed += file.size;
             log::info!("File {} extracted from combined file.", file_name);
         }
 
+        let remaining = combined_size - consumed;
+        let padding = index.padding();
+        if remaining != padding {
+            bail!(
+                "Combined file has {} bytes left after extraction, metafile padding is {}",
+                remaining,
+                padding
+            );
+        }
+
         Ok(())
     }
 
     /// Cleans up temporary files created during the download process
-    async fn cleanup_temporary_files(&self, combined_path: &Path) -> Result<()> {
+    async fn cleanup_temporary_files(
+        &self,
+        staging_dir: &Path,
+        combined_path: &Path,
+    ) -> Result<()> {
         log::info!("Cleaning up files...");
 
         let mut cleaned_count = 0;
 
         // Remove all .solidpiece files
-        let mut entries = fs::read_dir(&self.output_dir).with_context(|| {
+        let mut entries = fs::read_dir(staging_dir).with_context(|| {
             format!(
-                "Failed to read output directory: {}",
-                self.output_dir.display()
+                "Failed to read staging directory: {}",
+                staging_dir.display()
             )
         })?;
 
@@ -421,8 +721,576 @@ impl Downloader {
     }
 }
 
+/// Returns the directory containing `dir`, which need not exist yet
+fn parent_directory(dir: &Path) -> Result<PathBuf> {
+    let dir = std::path::absolute(dir)
+        .with_context(|| format!("Invalid directory: {}", dir.display()))?;
+    dir.parent()
+        .map(Path::to_path_buf)
+        .with_context(|| format!("{} has no parent directory", dir.display()))
+}
+
+/// Checks that `target` may be replaced by a downloaded build
+///
+/// Only a missing or empty directory, or one holding a build downloaded
+/// earlier (recognised by its [`BUILD_INDEX_FILE`]), is replaced, so
+/// pointing the output directory at an unrelated tree never deletes it.
+///
+/// # Errors
+///
+/// Returns an error if `target` is a file or a non-empty directory without
+/// a build index.
+fn check_replaceable(target: &Path) -> Result<()> {
+    if !target.exists() || target.join(BUILD_INDEX_FILE).is_file() {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(target)
+        .with_context(|| format!("Failed to read output directory: {}", target.display()))?;
+    if entries.next().is_some() {
+        bail!(
+            "Refusing to replace {}: it is not empty and holds no downloaded build ({} is missing)",
+            target.display(),
+            BUILD_INDEX_FILE
+        );
+    }
+    Ok(())
+}
+
+/// Replaces `target` with the directory `staging`
+///
+/// The previous contents of `target` are first renamed aside and only
+/// deleted once `staging` is in place; if that rename fails they are
+/// restored. Both directories must be on the same filesystem.
+///
+/// # Errors
+///
+/// Returns an error if `target` is not replaceable (see
+/// [`check_replaceable`]) or either rename fails.
+fn promote_directory(staging: &Path, target: &Path) -> Result<()> {
+    let target = std::path::absolute(target)
+        .with_context(|| format!("Invalid directory: {}", target.display()))?;
+    check_replaceable(&target)?;
+    let name = target
+        .file_name()
+        .with_context(|| format!("Invalid output directory: {}", target.display()))?;
+    let backup = target.with_file_name(format!(".{}.previous", name.to_string_lossy()));
+
+    if backup.exists() {
+        fs::remove_dir_all(&backup)
+            .with_context(|| format!("Failed to remove {}", backup.display()))?;
+    }
+    let had_previous = target.exists();
+    if had_previous {
+        fs::rename(&target, &backup)
+            .with_context(|| format!("Failed to move aside {}", target.display()))?;
+    }
+
+    if let Err(e) = fs::rename(staging, &target) {
+        if had_previous {
+            if let Err(restore) = fs::rename(&backup, &target) {
+                log::error!("Failed to restore {}: {}", target.display(), restore);
+            }
+        }
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to move {} to {}",
+                staging.display(),
+                target.display()
+            )
+        });
+    }
+
+    if had_previous {
+        fs::remove_dir_all(&backup)
+            .with_context(|| format!("Failed to remove {}", backup.display()))?;
+    }
+    log::info!("Moved the build into {}", target.display());
+    Ok(())
+}
+
 /// Convenience function that maintains backwards compatibility
-pub async fn download(repo: &str, build: &str, output_dir: &PathBuf) -> Result<()> {
-    let downloader = Downloader::new(repo.to_string(), output_dir.clone())?;
+pub async fn download(
+    repo: &str,
+    build: &str,
+    output_dir: &Path,
+    cdn: Cdn,
+) -> Result<DownloadResult> {
+    let downloader = Downloader::new(repo.to_string(), output_dir.to_path_buf(), cdn);
     downloader.download_build(build).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Fault, FixtureBuild, MockCdn};
+    use std::time::Duration;
+
+    fn fixture() -> FixtureBuild {
+        FixtureBuild::new(&[
+            ("osclient.exe", b"not really an executable, but long enough"),
+            ("data/readme.txt", b"hello"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_download_from_replayed_snapshot() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let build = fixture();
+        build.write_snapshot(snapshot.path());
+
+        let cdn = Cdn::replay(snapshot.path()).unwrap();
+        let result = download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .unwrap();
+
+        assert_eq!(result.config.version.version, "225.1");
+        assert_eq!(result.stats.pieces_total, build.piece_data().len());
+        assert_eq!(result.stats.pieces_fetched, build.piece_data().len());
+        assert_eq!(result.stats.files_extracted, 2);
+        for (name, data) in &build.files {
+            assert_eq!(&fs::read(output.path().join(name)).unwrap(), data);
+        }
+        assert!(!output.path().join("combined_file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let mirror = tempfile::tempdir().unwrap();
+        let recorded = tempfile::tempdir().unwrap();
+        let build = fixture();
+        build.write_snapshot(mirror.path());
+
+        let cdn = Cdn::new(Some(mirror.path().to_str().unwrap()))
+            .unwrap()
+            .recording(recorded.path());
+        let output = tempfile::tempdir().unwrap();
+        download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .unwrap();
+
+        for (relative, _) in build.resources() {
+            assert!(recorded.path().join(&relative).is_file(), "{}", relative);
+        }
+
+        let output = tempfile::tempdir().unwrap();
+        let cdn = Cdn::replay(recorded.path()).unwrap();
+        download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(output.path().join("data/readme.txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_over_previous_build_then_replay() {
+        let mirror = tempfile::tempdir().unwrap();
+        let recorded = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let build = fixture();
+        build.write_snapshot(mirror.path());
+
+        let mirror_cdn = Cdn::new(Some(mirror.path().to_str().unwrap())).unwrap();
+        Downloader::new(
+            build.repo.clone(),
+            output.path().to_path_buf(),
+            mirror_cdn.clone(),
+        )
+        .with_piece_cache(cache.path())
+        .download_build(&build.build)
+        .await
+        .unwrap();
+
+        // Neither the previous build nor the cache may stand in for a fetch
+        let result = Downloader::new(
+            build.repo.clone(),
+            output.path().to_path_buf(),
+            mirror_cdn.recording(recorded.path()),
+        )
+        .with_piece_cache(cache.path())
+        .download_build(&build.build)
+        .await
+        .unwrap();
+        assert_eq!(result.stats.pieces_fetched, build.piece_data().len());
+
+        let replayed = tempfile::tempdir().unwrap();
+        let cdn = Cdn::replay(recorded.path()).unwrap();
+        download(&build.repo, &build.build, replayed.path(), cdn)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(replayed.path().join("data/readme.txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_piece_is_not_recorded() {
+        let recorded = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        let piece = build.piece_paths()[0].clone();
+        server.inject(&piece, Fault::BadChecksum);
+
+        let cdn = Cdn::new(Some(&server.url()))
+            .unwrap()
+            .recording(recorded.path());
+        let error = download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Checksum mismatch"));
+        assert!(!recorded.path().join(&piece).exists());
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_piece_is_not_recorded() {
+        let recorded = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        let piece = build.piece_paths()[0].clone();
+        server.inject(&piece, Fault::Truncate(10));
+
+        let cdn = Cdn::new(Some(&server.url()))
+            .unwrap()
+            .recording(recorded.path());
+        assert!(download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .is_err());
+
+        let pieces_dir = recorded.path().join(&piece);
+        let pieces_dir = pieces_dir.parent().unwrap();
+        let recorded_pieces: Vec<_> = fs::read_dir(pieces_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(!recorded.path().join(&piece).exists());
+        assert!(
+            recorded_pieces
+                .iter()
+                .all(|name| !name.ends_with(".partial")),
+            "{:?}",
+            recorded_pieces
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_fails_on_missing_piece() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let build = fixture();
+        build.write_snapshot(snapshot.path());
+        fs::remove_file(snapshot.path().join(&build.piece_paths()[0])).unwrap();
+
+        let cdn = Cdn::replay(snapshot.path()).unwrap();
+        assert!(download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .is_err());
+    }
+
+    async fn download_from(server: &MockCdn, build: &FixtureBuild) -> Result<DownloadResult> {
+        let output = tempfile::tempdir().unwrap();
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let result = download(&build.repo, &build.build, output.path(), cdn).await?;
+        for (name, data) in &build.files {
+            assert_eq!(&fs::read(output.path().join(name)).unwrap(), data);
+        }
+        Ok(result)
+    }
+
+    #[tokio::test]
+    async fn test_download_over_http() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+
+        let result = download_from(&server, &build).await.unwrap();
+        assert_eq!(result.stats.pieces_fetched, build.piece_data().len());
+        assert!(result.stats.bytes_downloaded > 0);
+
+        let requests = server.requests();
+        for path in build.piece_paths() {
+            assert!(requests.contains(&path), "{} not requested", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_server_error() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        server.inject(&build.piece_paths()[1], Fault::Status(500));
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("500"));
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_config_error() {
+        let build = fixture();
+        let server = MockCdn::start(&build, false).await;
+        server.inject("osrs-win/alias.json", Fault::Status(503));
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("503"));
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_truncated_piece() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        server.inject(&build.piece_paths()[0], Fault::Truncate(10));
+
+        assert!(download_from(&server, &build).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_bad_checksum() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        server.inject(&build.piece_paths()[0], Fault::BadChecksum);
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Checksum mismatch"));
+    }
+
+    #[tokio::test]
+    async fn test_download_tolerates_slow_responses() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        for path in build.piece_paths() {
+            server.inject(&path, Fault::Delay(Duration::from_millis(50)));
+        }
+
+        download_from(&server, &build).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fault_applies_only_given_number_of_times() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        server.inject_times(&build.piece_paths()[0], Fault::Status(500), 1);
+
+        assert!(download_from(&server, &build).await.is_err());
+        download_from(&server, &build).await.unwrap();
+    }
+
+    fn piece_requests(server: &MockCdn) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|path| path.ends_with(".solidpiece"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_incremental_update_downloads_only_changed_pieces() {
+        let output = tempfile::tempdir().unwrap();
+        let old = FixtureBuild::new(&[
+            ("osclient.exe", &[1; 32]),
+            ("data/config.bin", &[2; 16]),
+            ("removed.txt", &[3; 16]),
+        ]);
+        let server = MockCdn::start(&old, true).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        download(&old.repo, &old.build, output.path(), cdn)
+            .await
+            .unwrap();
+        assert!(output.path().join(BUILD_INDEX_FILE).is_file());
+
+        let mut new =
+            FixtureBuild::new(&[("osclient.exe", &[1; 32]), ("data/config.bin", &[4; 16])]);
+        new.version = "225.2".to_string();
+        let server = MockCdn::start(&new, true).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let result = download(&new.repo, &new.build, output.path(), cdn)
+            .await
+            .unwrap();
+
+        assert_eq!(piece_requests(&server), vec![new.piece_paths()[2].clone()]);
+        assert_eq!(result.stats.pieces_fetched, 1);
+        assert_eq!(result.stats.pieces_reused, 2);
+        assert_eq!(result.stats.bytes_saved, 32);
+        for (name, data) in &new.files {
+            assert_eq!(&fs::read(output.path().join(name)).unwrap(), data);
+        }
+        assert!(!output.path().join("removed.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_piece_cache_is_shared_between_output_directories() {
+        let build = fixture();
+        let cache = tempfile::tempdir().unwrap();
+        let server = MockCdn::start(&build, true).await;
+
+        for expected_fetches in [build.piece_data().len(), 0] {
+            let output = tempfile::tempdir().unwrap();
+            let cdn = Cdn::new(Some(&server.url())).unwrap();
+            let result = Downloader::new(build.repo.clone(), output.path().to_path_buf(), cdn)
+                .with_piece_cache(cache.path())
+                .download_build(&build.build)
+                .await
+                .unwrap();
+            assert_eq!(result.stats.pieces_fetched, expected_fetches);
+            assert_eq!(
+                fs::read(output.path().join("data/readme.txt")).unwrap(),
+                b"hello"
+            );
+        }
+        assert_eq!(piece_requests(&server).len(), build.piece_data().len());
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_trailing_piece_data() {
+        let mut build = fixture();
+        build.trailing_bytes = b"unlisted".to_vec();
+        let server = MockCdn::start(&build, true).await;
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("8 bytes left after extraction"));
+    }
+
+    #[tokio::test]
+    async fn test_download_replaces_output_directory() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        let parent = tempfile::tempdir().unwrap();
+        let output = parent.path().join("downloads");
+        fs::create_dir(&output).unwrap();
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        download(&build.repo, &build.build, &output, cdn.clone())
+            .await
+            .unwrap();
+        fs::write(output.join("leftover.dll"), b"old").unwrap();
+        // Force the pieces to be fetched again instead of being reused
+        fs::remove_file(output.join("osclient.exe")).unwrap();
+
+        server.inject_times(&build.piece_paths()[0], Fault::Status(500), 1);
+        assert!(download(&build.repo, &build.build, &output, cdn.clone())
+            .await
+            .is_err());
+        assert!(output.join("leftover.dll").is_file());
+
+        download(&build.repo, &build.build, &output, cdn)
+            .await
+            .unwrap();
+        assert!(!output.join("leftover.dll").exists());
+        assert!(output.join("osclient.exe").is_file());
+
+        let entries: Vec<_> = fs::read_dir(parent.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["downloads"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_honours_retry_after() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        let path = build.piece_paths()[0].clone();
+        server.inject_times(&path, Fault::RetryAfter(503, "1".to_string()), 1);
+
+        let started = Instant::now();
+        download_from(&server, &build).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        let requests = server.requests();
+        assert_eq!(
+            requests.iter().filter(|request| **request == path).count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_on_excessive_retry_after() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        server.inject(
+            &build.piece_paths()[0],
+            Fault::RetryAfter(429, "3600".to_string()),
+        );
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Retry-After of 3600s"));
+    }
+
+    #[tokio::test]
+    async fn test_download_is_rate_limited_and_sends_user_agent() {
+        let build = FixtureBuild::new(&[("osclient.exe", &[7; 256])]);
+        let server = MockCdn::start(&build, true).await;
+        let output = tempfile::tempdir().unwrap();
+        let cdn = Cdn::new(Some(&server.url()))
+            .unwrap()
+            .with_user_agent("archive-bot/2.0")
+            .with_rate_limit(2_000)
+            .with_request_delay(Duration::from_millis(10));
+
+        let started = Instant::now();
+        let result = download(&build.repo, &build.build, output.path(), cdn)
+            .await
+            .unwrap();
+        let minimum = Duration::from_secs_f64(result.stats.bytes_downloaded as f64 / 2_000.0);
+        assert!(started.elapsed() >= minimum, "{:?}", started.elapsed());
+        assert!(server
+            .user_agents()
+            .iter()
+            .all(|user_agent| user_agent == "archive-bot/2.0"));
+    }
+
+    #[tokio::test]
+    async fn test_download_zstd_pieces_with_sha1_digests() {
+        let mut build = fixture();
+        build.piece_format = "zstd".to_string();
+        build.pieces_algorithm = "SHA-1".to_string();
+        let server = MockCdn::start(&build, true).await;
+
+        download_from(&server, &build).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_refuses_to_replace_unrelated_directory() {
+        let build = fixture();
+        let server = MockCdn::start(&build, true).await;
+        let output = tempfile::tempdir().unwrap();
+        fs::write(output.path().join("notes.txt"), b"keep me").unwrap();
+
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let error = download(&build.repo, &build.build, output.path(), cdn.clone())
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Refusing to replace"));
+        assert_eq!(
+            fs::read(output.path().join("notes.txt")).unwrap(),
+            b"keep me"
+        );
+        assert!(server.requests().is_empty());
+
+        let staging = tempfile::tempdir().unwrap();
+        assert!(promote_directory(staging.path(), output.path()).is_err());
+        assert!(output.path().join("notes.txt").is_file());
+
+        let empty = tempfile::tempdir().unwrap();
+        let downloader = Downloader::new(build.repo.clone(), empty.path().to_path_buf(), cdn)
+            .with_piece_cache(&empty.path().join("cache"));
+        let error = downloader.download_build(&build.build).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("must not be inside the output directory"));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_piece_format_fails_before_downloading() {
+        let mut build = fixture();
+        build.piece_format = "brotli".to_string();
+        let server = MockCdn::start(&build, true).await;
+
+        let error = download_from(&server, &build).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Unsupported piece format: \"brotli\""));
+        assert!(!server
+            .requests()
+            .iter()
+            .any(|path| path.ends_with(".solidpiece")));
+    }
+}
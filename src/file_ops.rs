@@ -1,11 +1,15 @@
 use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{fs, io};
+use tokio::io::AsyncWriteExt;
 use zip::write::SimpleFileOptions;
 use zip::ZipWriter;
 
+use crate::delta::BUILD_INDEX_FILE;
+
 /// Compresses a directory into a ZIP archive
 ///
 /// # Arguments
@@ -20,11 +24,20 @@ use zip::ZipWriter;
 /// # Notes
 ///
 /// - Skips existing ZIP files to avoid recursive compression
+/// - Skips the build index left for incremental updates
 /// - Preserves directory structure
 /// - Uses Deflate compression with Unix permissions
 pub fn zip_directory(src_dir: &Path, zip_file_path: &Path) -> Result<()> {
-    let file = File::create(zip_file_path)
-        .with_context(|| format!("Failed to create ZIP file: {}", zip_file_path.display()))?;
+    write_atomically(zip_file_path, |file| zip_directory_into(src_dir, file))?;
+
+    log::info!(
+        "Successfully created ZIP archive: {}",
+        zip_file_path.display()
+    );
+    Ok(())
+}
+
+fn zip_directory_into(src_dir: &Path, file: File) -> Result<File> {
     let mut zip = ZipWriter::new(file);
 
     let options = SimpleFileOptions::default()
@@ -46,14 +59,19 @@ pub fn zip_directory(src_dir: &Path, zip_file_path: &Path) -> Result<()> {
             .to_str()
             .with_context(|| format!("Path contains invalid UTF-8: {}", path.display()))?;
 
-        // Skip ZIP files to avoid recursive compression
+        // Skip ZIP files, including the one being written, to avoid
+        // recursive compression
         if let Some(extension) = path.extension() {
-            if extension.eq_ignore_ascii_case("zip") {
+            if extension.eq_ignore_ascii_case("zip") || extension == PARTIAL_EXTENSION {
                 log::debug!("Skipping ZIP file: {}", name);
                 continue;
             }
         }
 
+        if name == BUILD_INDEX_FILE {
+            continue;
+        }
+
         if path.is_file() {
             zip.start_file(name, options)
                 .with_context(|| format!("Failed to start ZIP entry for: {}", name))?;
@@ -70,7 +88,28 @@ pub fn zip_directory(src_dir: &Path, zip_file_path: &Path) -> Result<()> {
         }
     }
 
-    zip.finish().context("Failed to finalize ZIP archive")?;
+    zip.finish().context("Failed to finalize ZIP archive")
+}
+
+/// Compresses the listed files of a build into a ZIP archive
+///
+/// # Arguments
+///
+/// * `root` - Directory the file names are relative to
+/// * `names` - Relative file names, using `/` as separator
+/// * `zip_file_path` - Output path for the ZIP file
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or an error if a listed file is missing or
+/// compression fails.
+///
+/// # Notes
+///
+/// Only the listed files are archived, so anything else in `root` can never
+/// end up in the artifact.
+pub fn zip_files(root: &Path, names: &[String], zip_file_path: &Path) -> Result<()> {
+    write_atomically(zip_file_path, |file| zip_files_into(root, names, file))?;
 
     log::info!(
         "Successfully created ZIP archive: {}",
@@ -79,6 +118,162 @@ pub fn zip_directory(src_dir: &Path, zip_file_path: &Path) -> Result<()> {
     Ok(())
 }
 
+fn zip_files_into(root: &Path, names: &[String], file: File) -> Result<File> {
+    let mut zip = ZipWriter::new(file);
+
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o755);
+
+    for name in names {
+        let path = root.join(name);
+        zip.start_file(name.as_str(), options)
+            .with_context(|| format!("Failed to start ZIP entry for: {}", name))?;
+        let mut f = File::open(&path)
+            .with_context(|| format!("Failed to open file for compression: {}", path.display()))?;
+        io::copy(&mut f, &mut zip)
+            .with_context(|| format!("Failed to compress file: {}", path.display()))?;
+        log::debug!("Compressed file: {}", name);
+    }
+
+    zip.finish().context("Failed to finalize ZIP archive")
+}
+
+/// Extension appended to files while they are being written
+pub const PARTIAL_EXTENSION: &str = "partial";
+
+/// Returns a fresh path for writing `path` until it is complete
+///
+/// The temporary file sits next to the final one, so renaming it into place
+/// never crosses filesystems. Every call returns a different path, so
+/// concurrent writers of the same file do not interfere.
+pub fn partial_path(path: &Path) -> PathBuf {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".{}-{}.{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        PARTIAL_EXTENSION
+    ));
+    path.with_file_name(name)
+}
+
+/// Writes `path` through its [`partial_path`]
+///
+/// `write` receives the temporary file and returns it once all data is
+/// written; it is then synced to disk and renamed to `path`. On failure the
+/// temporary file is removed and `path` is left untouched.
+fn write_atomically(path: &Path, write: impl FnOnce(File) -> Result<File>) -> Result<()> {
+    let partial_path = partial_path(path);
+    let file = File::create(&partial_path)
+        .with_context(|| format!("Failed to create file: {}", partial_path.display()))?;
+
+    let result = write(file).and_then(|file| {
+        file.sync_all()
+            .with_context(|| format!("Failed to sync file: {}", partial_path.display()))?;
+        fs::rename(&partial_path, path)
+            .with_context(|| format!("Failed to move file into place: {}", path.display()))
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    result
+}
+
+/// A file that only appears under its name once it is complete
+///
+/// Data is written to the [`partial_path`] and [`AtomicFile::commit`] syncs
+/// it to disk before renaming it into place, so an interrupted run never
+/// leaves a half-written file that looks complete. Dropping an uncommitted
+/// file deletes the temporary file.
+#[derive(Debug)]
+pub struct AtomicFile {
+    path: PathBuf,
+    partial_path: PathBuf,
+    file: tokio::fs::File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for `path`, replacing a stale one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the temporary file cannot be created.
+    pub async fn create(path: &Path) -> Result<Self> {
+        let partial_path = partial_path(path);
+        let file = tokio::fs::File::create(&partial_path)
+            .await
+            .with_context(|| format!("Failed to create file: {}", partial_path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            partial_path,
+            file,
+            committed: false,
+        })
+    }
+
+    /// Returns the final path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.file
+            .write_all(data)
+            .await
+            .with_context(|| format!("Failed to write to file: {}", self.partial_path.display()))
+    }
+
+    /// Appends the contents of the file at `source`
+    pub async fn copy_from(&mut self, source: &Path) -> Result<u64> {
+        let mut source_file = tokio::fs::File::open(source)
+            .await
+            .with_context(|| format!("Failed to open file: {}", source.display()))?;
+        tokio::io::copy(&mut source_file, &mut self.file)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    source.display(),
+                    self.partial_path.display()
+                )
+            })
+    }
+
+    /// Syncs the file to disk and renames it to its final path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if syncing or renaming fails; the temporary file is
+    /// removed in that case.
+    pub async fn commit(mut self) -> Result<()> {
+        self.file
+            .flush()
+            .await
+            .with_context(|| format!("Failed to flush file: {}", self.partial_path.display()))?;
+        self.file
+            .sync_all()
+            .await
+            .with_context(|| format!("Failed to sync file: {}", self.partial_path.display()))?;
+        tokio::fs::rename(&self.partial_path, &self.path)
+            .await
+            .with_context(|| format!("Failed to move file into place: {}", self.path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
 /// Calculates the SHA256 checksum of a file
 ///
 /// # Arguments
@@ -124,10 +319,96 @@ mod tests {
     use std::path::PathBuf;
     use tempfile::tempdir;
 
+    #[tokio::test]
+    async fn test_atomic_file_appears_only_when_committed() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("piece.bin");
+
+        let mut file = AtomicFile::create(&path).await.unwrap();
+        file.write_all(b"half").await.unwrap();
+        assert!(!path.exists());
+        assert_eq!(entries(temp_dir.path()).len(), 1);
+        drop(file);
+        assert!(entries(temp_dir.path()).is_empty());
+
+        let mut file = AtomicFile::create(&path).await.unwrap();
+        file.write_all(b"complete").await.unwrap();
+        file.commit().await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"complete");
+        assert_eq!(entries(temp_dir.path()), vec!["piece.bin"]);
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_partial_paths_are_unique() {
+        let path = Path::new("/tmp/piece.bin");
+        let first = partial_path(path);
+        assert_ne!(first, partial_path(path));
+        assert_eq!(first.parent(), path.parent());
+        assert_eq!(
+            first.extension().and_then(|e| e.to_str()),
+            Some(PARTIAL_EXTENSION)
+        );
+    }
+
+    #[test]
+    fn test_zip_files_leaves_no_archive_on_failure() {
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("build.zip");
+        fs::write(&zip_path, b"previous").unwrap();
+
+        let names = vec!["missing.exe".to_string()];
+        assert!(zip_files(temp_dir.path(), &names, &zip_path).is_err());
+        assert_eq!(fs::read(&zip_path).unwrap(), b"previous");
+        assert_eq!(entries(temp_dir.path()), vec!["build.zip"]);
+    }
+
+    #[test]
+    fn test_zip_files_archives_only_listed_files() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("data")).unwrap();
+        fs::write(temp_dir.path().join("osclient.exe"), b"exe").unwrap();
+        fs::write(temp_dir.path().join("data/config.bin"), b"config").unwrap();
+        fs::write(temp_dir.path().join("stale.dll"), b"old").unwrap();
+
+        let zip_path = temp_dir.path().join("build.zip");
+        let names = vec!["osclient.exe".to_string(), "data/config.bin".to_string()];
+        zip_files(temp_dir.path(), &names, &zip_path).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut entries: Vec<String> = archive.file_names().map(str::to_string).collect();
+        entries.sort();
+        assert_eq!(entries, vec!["data/config.bin", "osclient.exe"]);
+
+        let mut contents = String::new();
+        io::Read::read_to_string(
+            &mut archive.by_name("data/config.bin").unwrap(),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, "config");
+    }
+
+    #[test]
+    fn test_zip_files_fails_on_missing_file() {
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("build.zip");
+        assert!(zip_files(temp_dir.path(), &["missing.exe".to_string()], &zip_path).is_err());
+    }
+
     #[test]
     fn test_zip_nonexistent_directory() {
-        let result = zip_directory(&PathBuf::from("nonexistent"), &PathBuf::from("test.zip"));
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("test.zip");
+        let result = zip_directory(&temp_dir.path().join("nonexistent"), &zip_path);
         assert!(result.is_err());
+        assert!(!zip_path.exists());
     }
 
     #[tokio::test]
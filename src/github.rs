@@ -1,11 +1,33 @@
 use anyhow::{Context, Result};
+use clap::ValueEnum;
+use octocrab::models::repos::Release;
 use octocrab::Octocrab;
+use std::path::Path;
+
+use crate::actions::{annotate, Annotation};
+use crate::version::VersionChange;
 
 /// Represents the result of checking if a new release should be created
 #[derive(Debug, Clone)]
 pub struct ReleaseCheck {
     pub should_create: bool,
     pub reason: String,
+    /// Tag of the latest existing release, if any
+    pub previous_version: Option<String>,
+    pub change: VersionChange,
+    /// Whether the release should be published as a pre-release
+    pub prerelease: bool,
+}
+
+/// What to do when the new build is older than the latest release
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DowngradePolicy {
+    /// Publish the rollback as a normal release
+    Allow,
+    /// Publish the rollback, flagged as a pre-release
+    Flag,
+    /// Do not publish an older build over a newer one
+    Refuse,
 }
 
 /// Checks if a new GitHub release should be created based on version and checksum
@@ -17,77 +39,446 @@ pub struct ReleaseCheck {
 /// * `repo` - Repository name
 /// * `version` - Current version string
 /// * `checksum` - Current artifact checksum
+/// * `downgrade_policy` - How to handle a version older than the latest release
 ///
 /// # Returns
 ///
 /// Returns `ReleaseCheck` indicating whether a new release should be created and why.
+///
+/// # Errors
+///
+/// Returns an error if looking up the release tagged `version` fails for a
+/// reason other than it not existing.
+///
+/// # Notes
+///
+/// The release tagged `version` is looked up before comparing with the
+/// latest release. `get_latest` skips pre-releases, so a rollback published
+/// as a pre-release would otherwise be detected and published again on
+/// every run.
 pub async fn should_create_release(
     github: &Octocrab,
     owner: &str,
     repo: &str,
     version: &str,
     checksum: &str,
+    downgrade_policy: DowngradePolicy,
 ) -> Result<ReleaseCheck> {
     log::info!(
         "Checking if release should be created for version: {}",
         version
     );
 
-    let latest_release = github.repos(owner, repo).releases().get_latest().await;
-
-    match latest_release {
+    let releases = github.repos(owner, repo);
+    let releases = releases.releases();
+    let latest_release = match releases.get_latest().await {
         Ok(release) => {
             log::info!("Latest release found: {}", release.tag_name);
+            Some(release)
+        }
+        Err(e) => {
+            log::info!("No previous releases found or API error: {}", e);
+            None
+        }
+    };
+    let previous_version = latest_release
+        .as_ref()
+        .map(|release| release.tag_name.clone());
+    let change = VersionChange::classify(previous_version.as_deref(), version);
 
-            // Check if version is different
-            if release.tag_name != version {
-                let reason = format!("Version changed from {} to {}", release.tag_name, version);
-                log::info!("{}", reason);
-                return Ok(ReleaseCheck {
-                    should_create: true,
-                    reason,
-                });
-            }
-
-            // Check if checksum is in release body (indicates same content)
-            if let Some(body) = &release.body {
-                if body.contains(checksum) {
-                    let reason = "Checksum found in release body - no content changes".to_string();
-                    log::info!("{}", reason);
-                    return Ok(ReleaseCheck {
-                        should_create: false,
-                        reason,
-                    });
-                }
-            }
-
-            // Check if release has assets
-            if release.assets.is_empty() {
-                let reason = "No assets found in latest release".to_string();
-                log::info!("{}", reason);
-                return Ok(ReleaseCheck {
-                    should_create: true,
-                    reason,
-                });
-            }
-
-            // Same version but different content (checksum not found in body)
-            let reason = "Same version but content has changed (different checksum)".to_string();
-            log::info!("{}", reason);
-            Ok(ReleaseCheck {
-                should_create: false,
-                reason,
-            })
+    if let Some(release) = find_release_by_tag(github, owner, repo, version).await? {
+        return Ok(check_existing_release(
+            &release,
+            checksum,
+            previous_version,
+            change,
+        ));
+    }
+
+    let Some(previous) = previous_version else {
+        let reason = "No previous releases found".to_string();
+        log::info!("{}", reason);
+        return Ok(ReleaseCheck {
+            should_create: true,
+            reason,
+            previous_version: None,
+            change: VersionChange::Initial,
+            prerelease: false,
+        });
+    };
+
+    if change.is_rollback() {
+        return Ok(check_rollback(&previous, version, downgrade_policy));
+    }
+
+    let reason = format!("Version changed from {} to {}", previous, version);
+    log::info!("{}", reason);
+    Ok(ReleaseCheck {
+        should_create: true,
+        reason,
+        previous_version: Some(previous),
+        change,
+        prerelease: false,
+    })
+}
+
+/// Returns the release tagged `tag`, including pre-releases, or `None` if
+/// there is none
+async fn find_release_by_tag(
+    github: &Octocrab,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+) -> Result<Option<Release>> {
+    match github.repos(owner, repo).releases().get_by_tag(tag).await {
+        Ok(release) => Ok(Some(release)),
+        Err(octocrab::Error::GitHub { source, .. })
+            if source.status_code == reqwest::StatusCode::NOT_FOUND =>
+        {
+            Ok(None)
         }
+        Err(e) => Err(e).with_context(|| format!("Failed to look up release {}", tag)),
+    }
+}
+
+/// Returns the web page of the release tagged `tag`, or `None` if there is
+/// no such release
+///
+/// # Errors
+///
+/// Returns an error if the release cannot be looked up.
+pub async fn find_release_url(
+    github: &Octocrab,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+) -> Result<Option<String>> {
+    let release = find_release_by_tag(github, owner, repo, tag).await?;
+    Ok(release.map(|release| release.html_url.to_string()))
+}
+
+/// Decides whether to publish a build whose tag already has a release
+fn check_existing_release(
+    release: &Release,
+    checksum: &str,
+    previous_version: Option<String>,
+    change: VersionChange,
+) -> ReleaseCheck {
+    // Check if checksum is in release body (indicates same content)
+    let (should_create, reason) = if release
+        .body
+        .as_deref()
+        .is_some_and(|body| body.contains(checksum))
+    {
+        (
+            false,
+            format!(
+                "Checksum found in body of release {} - no content changes",
+                release.tag_name
+            ),
+        )
+    } else if release.assets.is_empty() {
+        (
+            true,
+            format!("No assets found in release {}", release.tag_name),
+        )
+    } else {
+        (
+            false,
+            "Same version but content has changed (different checksum)".to_string(),
+        )
+    };
+    log::info!("{}", reason);
+
+    ReleaseCheck {
+        should_create,
+        reason,
+        previous_version,
+        change,
+        prerelease: release.prerelease,
+    }
+}
+
+/// Decides how to release a build that is older than the latest release
+fn check_rollback(previous: &str, version: &str, policy: DowngradePolicy) -> ReleaseCheck {
+    let (should_create, prerelease, action) = match policy {
+        DowngradePolicy::Allow => (true, false, "publishing as rollback"),
+        DowngradePolicy::Flag => (true, true, "publishing as pre-release"),
+        DowngradePolicy::Refuse => (false, false, "refusing to publish"),
+    };
+
+    let reason = format!(
+        "Rollback detected from {} to {} - {}",
+        previous, version, action
+    );
+    annotate(Annotation::Warning, &reason);
+
+    ReleaseCheck {
+        should_create,
+        reason,
+        previous_version: Some(previous.to_string()),
+        change: VersionChange::Downgrade,
+        prerelease,
+    }
+}
+
+/// Downloads an asset attached to the latest release
+///
+/// # Arguments
+///
+/// * `github` - Authenticated GitHub client
+/// * `token` - GitHub personal access token used to download the asset
+/// * `user_agent` - User-Agent sent with the download
+/// * `owner` - Repository owner (username or organization)
+/// * `repo` - Repository name
+/// * `name` - Name of the asset to download
+///
+/// # Returns
+///
+/// Returns the asset contents, or `None` if there is no release or the
+/// latest release has no asset with that name.
+pub async fn download_latest_release_asset(
+    github: &Octocrab,
+    token: &str,
+    user_agent: &str,
+    owner: &str,
+    repo: &str,
+    name: &str,
+) -> Result<Option<Vec<u8>>> {
+    let release = match github.repos(owner, repo).releases().get_latest().await {
+        Ok(release) => release,
         Err(e) => {
-            let reason = format!("No previous releases found or API error: {}", e);
-            log::info!("{}", reason);
-            Ok(ReleaseCheck {
-                should_create: true,
-                reason,
-            })
+            log::info!("No latest release to fetch {} from: {}", name, e);
+            return Ok(None);
         }
+    };
+
+    fetch_release_asset(&release, token, user_agent, name).await
+}
+
+/// Downloads an asset attached to the release tagged `tag`
+///
+/// # Arguments
+///
+/// * `github` - Authenticated GitHub client
+/// * `token` - GitHub personal access token used to download the asset
+/// * `user_agent` - User-Agent sent with the download
+/// * `owner` - Repository owner (username or organization)
+/// * `repo` - Repository name
+/// * `tag` - Tag of the release
+/// * `name` - Name of the asset to download
+///
+/// # Returns
+///
+/// Returns the asset contents, or `None` if the release has no asset with
+/// that name.
+///
+/// # Errors
+///
+/// Returns an error if there is no release with that tag or the download fails.
+pub async fn download_release_asset(
+    github: &Octocrab,
+    token: &str,
+    user_agent: &str,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+    name: &str,
+) -> Result<Option<Vec<u8>>> {
+    let release = github
+        .repos(owner, repo)
+        .releases()
+        .get_by_tag(tag)
+        .await
+        .with_context(|| format!("Failed to find release {}", tag))?;
+
+    fetch_release_asset(&release, token, user_agent, name).await
+}
+
+/// Downloads the asset called `name` of `release`, if it has one
+async fn fetch_release_asset(
+    release: &Release,
+    token: &str,
+    user_agent: &str,
+    name: &str,
+) -> Result<Option<Vec<u8>>> {
+    let Some(asset) = release.assets.iter().find(|asset| asset.name == name) else {
+        log::info!("Release {} has no asset {}", release.tag_name, name);
+        return Ok(None);
+    };
+
+    let response = reqwest::Client::new()
+        .get(asset.url.clone())
+        .header(reqwest::header::ACCEPT, "application/octet-stream")
+        .header(reqwest::header::USER_AGENT, user_agent)
+        .bearer_auth(token)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download release asset {}", name))?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "HTTP error {} downloading release asset {}",
+            response.status(),
+            name
+        );
     }
+
+    let bytes = response
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to read release asset {}", name))?;
+
+    log::info!("Downloaded {} from release {}", name, release.tag_name);
+    Ok(Some(bytes.to_vec()))
+}
+
+/// Marks the start of the block [`append_to_release_body`] maintains
+const APPENDED_BLOCK_START: &str = "<!-- release-updater:diff -->";
+/// Marks the end of the block [`append_to_release_body`] maintains
+const APPENDED_BLOCK_END: &str = "<!-- /release-updater:diff -->";
+
+/// Appends `text` to the body of the release tagged `tag`
+///
+/// The text is wrapped in marker comments; appending again replaces the
+/// earlier block instead of adding a second one.
+///
+/// # Errors
+///
+/// Returns an error if there is no release with that tag or it cannot be updated.
+pub async fn append_to_release_body(
+    github: &Octocrab,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+    text: &str,
+) -> Result<()> {
+    let releases = github.repos(owner, repo);
+    let releases = releases.releases();
+    let release = releases
+        .get_by_tag(tag)
+        .await
+        .with_context(|| format!("Failed to find release {}", tag))?;
+
+    let body = replace_appended_block(release.body.as_deref().unwrap_or_default(), text);
+    releases
+        .update(release.id.into_inner())
+        .body(&body)
+        .send()
+        .await
+        .with_context(|| format!("Failed to update release {}", tag))?;
+
+    log::info!("Appended build diff to release {}", tag);
+    Ok(())
+}
+
+/// Returns `body` with its marked block replaced by `text`, or with `text`
+/// appended in a marked block if there is none yet
+fn replace_appended_block(body: &str, text: &str) -> String {
+    let block = format!(
+        "{}\n{}\n{}",
+        APPENDED_BLOCK_START,
+        text.trim_end(),
+        APPENDED_BLOCK_END
+    );
+
+    if let Some(start) = body.find(APPENDED_BLOCK_START) {
+        if let Some(end) = body[start..].find(APPENDED_BLOCK_END) {
+            let end = start + end + APPENDED_BLOCK_END.len();
+            return format!("{}{}{}", &body[..start], block, &body[end..]);
+        }
+    }
+
+    if body.trim().is_empty() {
+        block
+    } else {
+        format!("{}\n\n{}", body.trim_end(), block)
+    }
+}
+
+/// Publishes the release tagged `tag` with `assets` attached
+///
+/// Mirrors the release step of the check-update workflow for runs that
+/// publish on their own, such as `watch`. A release that already exists
+/// with the tag (e.g. from an interrupted run) is reused, and assets with
+/// the same name are replaced.
+///
+/// # Arguments
+///
+/// * `github` - Authenticated GitHub client
+/// * `owner` - Repository owner (username or organization)
+/// * `repo` - Repository name
+/// * `tag` - Tag of the release
+/// * `check` - Release check that decided to publish the build
+/// * `notes` - Body of the release
+/// * `assets` - Files to upload, named after their file names
+///
+/// # Returns
+///
+/// Returns the URL of the published release.
+///
+/// # Errors
+///
+/// Returns an error if the release cannot be created or an asset cannot be
+/// read or uploaded.
+pub async fn publish_release(
+    github: &Octocrab,
+    owner: &str,
+    repo: &str,
+    tag: &str,
+    check: &ReleaseCheck,
+    notes: &str,
+    assets: &[&Path],
+) -> Result<String> {
+    let repos = github.repos(owner, repo);
+    let releases = repos.releases();
+
+    let name = if check.change.is_rollback() {
+        format!("Revision {} (rollback)", tag)
+    } else {
+        format!("Revision {}", tag)
+    };
+    let release = match find_release_by_tag(github, owner, repo, tag).await? {
+        Some(release) => {
+            log::info!("Release {} already exists, updating its assets", tag);
+            release
+        }
+        None => releases
+            .create(tag)
+            .name(&name)
+            .body(notes)
+            .draft(false)
+            .prerelease(check.prerelease)
+            .send()
+            .await
+            .with_context(|| format!("Failed to create release {}", tag))?,
+    };
+
+    for path in assets {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Invalid asset path: {}", path.display()))?;
+        let data = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read asset: {}", path.display()))?;
+
+        if let Some(existing) = release.assets.iter().find(|asset| asset.name == file_name) {
+            repos
+                .release_assets()
+                .delete(existing.id.into_inner())
+                .await
+                .with_context(|| format!("Failed to replace asset {} of {}", file_name, tag))?;
+        }
+        releases
+            .upload_asset(release.id.into_inner(), file_name, data.into())
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload asset {} to {}", file_name, tag))?;
+        log::info!("Uploaded {} to release {}", file_name, tag);
+    }
+
+    Ok(release.html_url.to_string())
 }
 
 /// Creates a GitHub client with the provided personal access token
@@ -95,12 +486,20 @@ pub async fn should_create_release(
 /// # Arguments
 ///
 /// * `token` - GitHub personal access token
+/// * `api_url` - API root to use instead of `https://api.github.com`, e.g.
+///   for GitHub Enterprise Server
 ///
 /// # Returns
 ///
 /// Returns an authenticated `Octocrab` client.
-pub fn create_github_client(token: &str) -> Result<Octocrab> {
-    Octocrab::builder()
+pub fn create_github_client(token: &str, api_url: Option<&str>) -> Result<Octocrab> {
+    let mut builder = Octocrab::builder();
+    if let Some(url) = api_url {
+        builder = builder
+            .base_uri(url)
+            .with_context(|| format!("Invalid GitHub API URL: {}", url))?;
+    }
+    builder
         .personal_token(token)
         .build()
         .context("Failed to create GitHub client")
@@ -109,21 +508,209 @@ pub fn create_github_client(token: &str) -> Result<Octocrab> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::MockGitHub;
 
     #[test]
     fn test_release_check_creation() {
         let check = ReleaseCheck {
             should_create: true,
             reason: "Test reason".to_string(),
+            previous_version: None,
+            change: VersionChange::Initial,
+            prerelease: false,
         };
 
         assert!(check.should_create);
         assert_eq!(check.reason, "Test reason");
     }
 
+    #[test]
+    fn test_check_rollback_policies() {
+        let check = check_rollback("1.0.0.2", "1.0.0.1", DowngradePolicy::Allow);
+        assert!(check.should_create);
+        assert!(!check.prerelease);
+        assert!(check.change.is_rollback());
+
+        let check = check_rollback("1.0.0.2", "1.0.0.1", DowngradePolicy::Flag);
+        assert!(check.should_create);
+        assert!(check.prerelease);
+
+        let check = check_rollback("1.0.0.2", "1.0.0.1", DowngradePolicy::Refuse);
+        assert!(!check.should_create);
+        assert_eq!(check.previous_version.as_deref(), Some("1.0.0.2"));
+    }
+
+    #[tokio::test]
+    async fn test_should_create_release_against_latest() {
+        let server = MockGitHub::start().await;
+        let github = server.client();
+
+        let check = should_create_release(&github, "o", "r", "225.1", "abc", DowngradePolicy::Flag)
+            .await
+            .unwrap();
+        assert!(check.should_create);
+        assert_eq!(check.change, VersionChange::Initial);
+
+        server.add_release(
+            "225.1",
+            "Checksum (SHA-256): abc",
+            false,
+            &[("a.zip", b"zip")],
+        );
+        let check = should_create_release(&github, "o", "r", "225.1", "abc", DowngradePolicy::Flag)
+            .await
+            .unwrap();
+        assert!(!check.should_create);
+
+        let check = should_create_release(&github, "o", "r", "226.1", "def", DowngradePolicy::Flag)
+            .await
+            .unwrap();
+        assert!(check.should_create);
+        assert_eq!(check.previous_version.as_deref(), Some("225.1"));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_released_as_prerelease_is_not_released_again() {
+        let server = MockGitHub::start().await;
+        let github = server.client();
+        server.add_release(
+            "226.1",
+            "Checksum (SHA-256): new",
+            false,
+            &[("a.zip", b"new")],
+        );
+
+        let check = should_create_release(&github, "o", "r", "225.1", "old", DowngradePolicy::Flag)
+            .await
+            .unwrap();
+        assert!(check.should_create);
+        assert!(check.prerelease);
+
+        // The pre-release is invisible to `releases/latest`, which still
+        // returns 226.1 on the next run
+        server.add_release(
+            "225.1",
+            "Checksum (SHA-256): old",
+            true,
+            &[("a.zip", b"old")],
+        );
+        let check = should_create_release(&github, "o", "r", "225.1", "old", DowngradePolicy::Flag)
+            .await
+            .unwrap();
+        assert!(!check.should_create, "{}", check.reason);
+        assert!(check.reason.contains("Checksum found"));
+        assert_eq!(check.previous_version.as_deref(), Some("226.1"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_release_creates_release_with_assets() {
+        let server = MockGitHub::start().await;
+        let github = server.client();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let artifact = temp_dir.path().join("build.zip");
+        let manifest = temp_dir.path().join("build.manifest.json");
+        std::fs::write(&artifact, b"zip").unwrap();
+        std::fs::write(&manifest, b"{}").unwrap();
+
+        let check = ReleaseCheck {
+            should_create: true,
+            reason: "Rollback".to_string(),
+            previous_version: Some("226.1".to_string()),
+            change: VersionChange::Downgrade,
+            prerelease: true,
+        };
+        let notes = "Checksum (SHA-256): abc";
+        publish_release(
+            &github,
+            "o",
+            "r",
+            "225.1",
+            &check,
+            notes,
+            &[&artifact, &manifest],
+        )
+        .await
+        .unwrap();
+
+        let releases = server.releases();
+        assert_eq!(releases.len(), 1);
+        let release = &releases[0];
+        assert_eq!(release.tag, "225.1");
+        assert_eq!(release.name, "Revision 225.1 (rollback)");
+        assert_eq!(release.body, notes);
+        assert!(release.prerelease);
+        let assets: Vec<_> = release
+            .assets
+            .iter()
+            .map(|(_, name, data)| (name.as_str(), data.as_slice()))
+            .collect();
+        assert_eq!(
+            assets,
+            vec![
+                ("build.zip", b"zip".as_slice()),
+                ("build.manifest.json", b"{}".as_slice())
+            ]
+        );
+
+        // Publishing again reuses the release and replaces its assets
+        std::fs::write(&artifact, b"zip 2").unwrap();
+        publish_release(&github, "o", "r", "225.1", &check, notes, &[&artifact])
+            .await
+            .unwrap();
+        let releases = server.releases();
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].assets.len(), 2);
+        assert!(releases[0]
+            .assets
+            .iter()
+            .any(|(_, name, data)| name == "build.zip" && data == b"zip 2"));
+    }
+
+    #[test]
+    fn test_replace_appended_block() {
+        let body = replace_appended_block("Notes\n", "diff 1\n");
+        assert_eq!(
+            body,
+            "Notes\n\n<!-- release-updater:diff -->\ndiff 1\n<!-- /release-updater:diff -->"
+        );
+
+        let body = replace_appended_block(&format!("{}\n\nFooter", body), "diff 2");
+        assert_eq!(
+            body,
+            "Notes\n\n<!-- release-updater:diff -->\ndiff 2\n<!-- /release-updater:diff -->\n\nFooter"
+        );
+
+        assert_eq!(
+            replace_appended_block("", "diff"),
+            "<!-- release-updater:diff -->\ndiff\n<!-- /release-updater:diff -->"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_to_release_body_twice() {
+        let server = MockGitHub::start().await;
+        let github = server.client();
+        server.add_release("225.1", "Checksum (SHA-256): abc", false, &[]);
+
+        append_to_release_body(&github, "o", "r", "225.1", "old diff")
+            .await
+            .unwrap();
+        append_to_release_body(&github, "o", "r", "225.1", "new diff")
+            .await
+            .unwrap();
+
+        let body = &server.releases()[0].body;
+        assert!(body.starts_with("Checksum (SHA-256): abc\n\n"));
+        assert!(body.contains("new diff"));
+        assert!(!body.contains("old diff"));
+        assert_eq!(body.matches(APPENDED_BLOCK_START).count(), 1);
+    }
+
     #[tokio::test]
     async fn test_create_github_client() {
-        let result = create_github_client("fake_token");
+        let result = create_github_client("fake_token", None);
         assert!(result.is_ok());
+        assert!(create_github_client("fake_token", Some("http://127.0.0.1:1/")).is_ok());
+        assert!(create_github_client("fake_token", Some("not a url")).is_err());
     }
 }
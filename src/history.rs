@@ -0,0 +1,484 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::report::DecisionSummary;
+
+/// One observation of a build, as recorded by an updater run
+///
+/// `promote_time` and `scan_time` are copied verbatim from the versions
+/// document (milliseconds since the Unix epoch); `observed_at` is in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub observed_at: u64,
+    pub repo: String,
+    pub build: String,
+    pub version_id: String,
+    pub version: String,
+    pub promote_time: u64,
+    pub scan_time: u64,
+    pub metafile_id: String,
+    /// Hex digests of the metafile pieces in order
+    pub pieces: Vec<String>,
+    /// Release tag rendered for the build
+    pub tag: String,
+    /// SHA-256 of the build manifest
+    pub manifest_sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<DecisionSummary>,
+    /// How the build relates to earlier observations, see [`classify`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<BuildEvent>,
+}
+
+/// How an observed build relates to the builds recorded before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildEvent {
+    /// Content that has not been observed before
+    New,
+    /// Previously observed content promoted again, e.g. a rollback
+    Repromoted,
+    /// Same content and promotion, scanned again by the CDN
+    Rescanned,
+    /// Identical to an earlier observation
+    Unchanged,
+}
+
+impl BuildEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildEvent::New => "new",
+            BuildEvent::Repromoted => "repromoted",
+            BuildEvent::Rescanned => "rescanned",
+            BuildEvent::Unchanged => "unchanged",
+        }
+    }
+}
+
+impl fmt::Display for BuildEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl HistoryEntry {
+    /// Records the build described by `config`, observed now
+    pub fn new(
+        config: &Config,
+        tag: &str,
+        manifest_sha256: &str,
+        decision: Option<DecisionSummary>,
+    ) -> Self {
+        Self {
+            observed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            repo: config.repo.clone(),
+            build: config.build.clone(),
+            version_id: config.version.id.clone(),
+            version: config.version.version.clone(),
+            promote_time: config.version.promote_time,
+            scan_time: config.version.scan_time,
+            metafile_id: config.metafile.id.clone(),
+            pieces: config.metafile.pieces.clone(),
+            tag: tag.to_string(),
+            manifest_sha256: manifest_sha256.to_string(),
+            decision,
+            event: None,
+        }
+    }
+
+    /// Returns true if both entries describe the same build content
+    ///
+    /// The piece digests identify the content; the metafile id and
+    /// version change when the CDN rescans a build.
+    pub fn same_content(&self, other: &HistoryEntry) -> bool {
+        !self.pieces.is_empty() && self.pieces == other.pieces
+    }
+
+    /// Returns the promote time formatted as UTC
+    pub fn promoted_at(&self) -> String {
+        format_utc(self.promote_time / 1000)
+    }
+
+    /// Returns true if `build` names this entry's version, version id, tag
+    /// or metafile id
+    pub fn matches(&self, build: &str) -> bool {
+        [
+            &self.version,
+            &self.version_id,
+            &self.tag,
+            &self.metafile_id,
+        ]
+        .iter()
+        .any(|value| value.as_str() == build)
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    /// Formats the entry as one line of `history list`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decision = match &self.decision {
+            Some(decision) if decision.should_create => "released".to_string(),
+            Some(decision) => format!("skipped: {}", decision.reason),
+            None => "-".to_string(),
+        };
+        write!(
+            f,
+            "{}  {:<12} {:<12} {:<10} id {}  promoted {}  scanned {}  {}",
+            format_utc(self.observed_at),
+            self.version,
+            self.tag,
+            self.event.map_or("-", |event| event.as_str()),
+            self.version_id,
+            self.promoted_at(),
+            format_utc(self.scan_time / 1000),
+            decision
+        )
+    }
+}
+
+/// Append-only JSON-lines file of [`HistoryEntry`] records
+///
+/// Every run appends one line, so the file can be inspected, grepped and
+/// concatenated with standard tools. Lines that fail to parse, such as one
+/// cut short by a crash, are skipped with a warning.
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    path: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Appends `entry` and syncs it to disk
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or written.
+    pub fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let mut line = serde_json::to_string(entry).context("Failed to serialize history entry")?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open history: {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .with_context(|| format!("Failed to write history: {}", self.path.display()))?;
+
+        log::info!(
+            "Recorded build {} in {}",
+            entry.version,
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Classifies `entry` against the entries of its repo and build, then
+    /// appends it
+    ///
+    /// # Returns
+    ///
+    /// Returns the event `entry` was classified as.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be read or written.
+    pub fn record(&self, mut entry: HistoryEntry) -> Result<BuildEvent> {
+        let previous = self.entries(&entry.repo, &entry.build)?;
+        let event = classify(&previous, &entry);
+        entry.event = Some(event);
+        self.append(&entry)?;
+        Ok(event)
+    }
+
+    /// Loads every entry in the order they were recorded
+    ///
+    /// A missing file is an empty history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read.
+    pub fn load(&self) -> Result<Vec<HistoryEntry>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read history: {}", self.path.display()))
+            }
+        };
+
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::warn!(
+                    "Skipping invalid line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Loads the entries of `repo`.`build` in the order they were recorded
+    pub fn entries(&self, repo: &str, build: &str) -> Result<Vec<HistoryEntry>> {
+        let mut entries = self.load()?;
+        entries.retain(|entry| entry.repo == repo && entry.build == build);
+        Ok(entries)
+    }
+}
+
+/// Returns the earliest entry naming `build`, see [`HistoryEntry::matches`]
+pub fn first_seen<'a>(entries: &'a [HistoryEntry], build: &str) -> Option<&'a HistoryEntry> {
+    entries
+        .iter()
+        .filter(|entry| entry.matches(build))
+        .min_by_key(|entry| entry.observed_at)
+}
+
+/// Classifies `current` against the `previous` observations of its build
+///
+/// The most recent observation with the same content decides: a different
+/// promote time means the build was promoted again, a different scan time
+/// only that it was rescanned. Content never seen before is a new build.
+pub fn classify(previous: &[HistoryEntry], current: &HistoryEntry) -> BuildEvent {
+    let Some(last) = previous
+        .iter()
+        .rev()
+        .find(|entry| entry.same_content(current))
+    else {
+        return BuildEvent::New;
+    };
+
+    if last.promote_time != current.promote_time {
+        BuildEvent::Repromoted
+    } else if last.scan_time != current.scan_time {
+        BuildEvent::Rescanned
+    } else {
+        BuildEvent::Unchanged
+    }
+}
+
+/// Formats seconds since the Unix epoch as `2023-11-14 22:13:20 UTC`
+pub fn format_utc(unix_secs: u64) -> String {
+    let (year, month, day) = civil_from_days(unix_secs / 86_400);
+    let seconds = unix_secs % 86_400;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Converts days since the Unix epoch into a proleptic Gregorian
+/// `(year, month, day)`
+///
+/// Uses Howard Hinnant's `civil_from_days`, which counts years from March so
+/// the leap day ends the year.
+pub fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    (year_of_era + era * 400 + u64::from(month <= 2), month, day)
+}
+
+/// Converts a proleptic Gregorian date from 1970 on into days since the
+/// Unix epoch, the inverse of [`civil_from_days`]
+pub fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(observed_at: u64, version: &str, should_create: bool) -> HistoryEntry {
+        let mut config = Config::new("osrs-win", "production");
+        config.version.id = format!("id-{}", version);
+        config.version.version = version.to_string();
+        config.version.scan_time = 1_699_999_000_000;
+        config.metafile.id = format!("metafile-{}", version);
+        config.version.promote_time = 1_699_990_000_000;
+        config.metafile.pieces = vec!["aa".to_string(), "bb".to_string()];
+
+        let decision = DecisionSummary {
+            should_create,
+            reason: if should_create {
+                "new version".to_string()
+            } else {
+                "release exists".to_string()
+            },
+            previous_version: None,
+            change: "upgrade".to_string(),
+            prerelease: false,
+        };
+        HistoryEntry {
+            observed_at,
+            ..HistoryEntry::new(&config, version, "ff", Some(decision))
+        }
+    }
+
+    #[test]
+    fn test_append_and_query() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(&temp_dir.path().join("history/builds.jsonl"));
+        assert!(store.load().unwrap().is_empty());
+
+        store.append(&entry(100, "225.1", true)).unwrap();
+        store.append(&entry(200, "225.1", false)).unwrap();
+        let mut other = entry(300, "225.2", true);
+        other.build = "beta".to_string();
+        store.append(&other).unwrap();
+
+        let entries = store.entries("osrs-win", "production").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].pieces, vec!["aa", "bb"]);
+        assert_eq!(store.load().unwrap().len(), 3);
+
+        let first = first_seen(&entries, "id-225.1").unwrap();
+        assert_eq!(first.observed_at, 100);
+        assert!(first_seen(&entries, "metafile-225.1").is_some());
+        assert!(first_seen(&entries, "225.2").is_none());
+    }
+
+    #[test]
+    fn test_invalid_lines_are_skipped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("builds.jsonl");
+        let store = HistoryStore::new(&path);
+        store.append(&entry(100, "225.1", true)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"observed_at\": 2\n\n").unwrap();
+        store.append(&entry(200, "225.2", true)).unwrap();
+
+        let entries = store.load().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].version, "225.2");
+    }
+
+    #[test]
+    fn test_display_entry() {
+        let line = entry(1_700_000_000, "225.1", false).to_string();
+        assert_eq!(
+            line,
+            "2023-11-14 22:13:20 UTC  225.1        225.1        -          id id-225.1  promoted 2023-11-14 19:26:40 UTC  scanned 2023-11-14 21:56:40 UTC  skipped: release exists"
+        );
+    }
+
+    #[test]
+    fn test_classify_events() {
+        let first = entry(100, "225.1", true);
+        assert_eq!(classify(&[], &first), BuildEvent::New);
+        let mut history = vec![first];
+
+        let same = entry(200, "225.1", false);
+        assert_eq!(classify(&history, &same), BuildEvent::Unchanged);
+
+        let mut rescanned = same.clone();
+        rescanned.scan_time += 60_000;
+        rescanned.metafile_id = "metafile-rescan".to_string();
+        assert_eq!(classify(&history, &rescanned), BuildEvent::Rescanned);
+
+        let mut next = entry(300, "225.2", true);
+        next.pieces = vec!["cc".to_string()];
+        next.promote_time += 3_600_000;
+        assert_eq!(classify(&history, &next), BuildEvent::New);
+
+        // Rolling back to 225.1 promotes known content again
+        history.push(next);
+        let mut rollback = entry(400, "225.1", true);
+        rollback.promote_time += 7_200_000;
+        assert_eq!(classify(&history, &rollback), BuildEvent::Repromoted);
+    }
+
+    #[test]
+    fn test_record_stores_event() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(&temp_dir.path().join("builds.jsonl"));
+
+        assert_eq!(
+            store.record(entry(100, "225.1", true)).unwrap(),
+            BuildEvent::New
+        );
+        assert_eq!(
+            store.record(entry(200, "225.1", false)).unwrap(),
+            BuildEvent::Unchanged
+        );
+
+        let events: Vec<_> = store.load().unwrap().iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            vec![Some(BuildEvent::New), Some(BuildEvent::Unchanged)]
+        );
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(951_825_600), "2000-02-29 12:00:00 UTC");
+        assert_eq!(format_utc(1_709_251_199), "2024-02-29 23:59:59 UTC");
+    }
+
+    #[test]
+    fn test_civil_date_conversion() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(
+            days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28),
+            1
+        );
+
+        for days in (0..200_000).step_by(7) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
@@ -6,45 +6,116 @@
 //!
 //! ## Workflow
 //!
-//! 1. Downloads OSRS client files using the configured repository and build
-//! 2. Compresses downloaded files into a ZIP archive
+//! 1. Downloads OSRS client files into a staging directory and moves them into place
+//! 2. Compresses the files listed in the metafile into a ZIP archive
 //! 3. Calculates SHA256 checksum of the archive
-//! 4. Extracts version information from PE executables
-//! 5. Checks GitHub for existing releases to determine if an update is needed
-//! 6. Sets GitHub Actions outputs based on the update status
+//! 4. Extracts version information from PE executables and renders the release tag
+//! 5. Writes a build manifest and checks executable signatures against the previous one
+//! 6. Checks GitHub for existing releases to determine if an update is needed
+//! 7. Writes CI outputs (GitHub Actions, GitLab, env-file or JSON) based on the update status
+//!    and, with `--publish`, creates the release and uploads the archive and manifest
+//! 8. Notifies webhooks, Discord and Slack about a new build (`--notify`)
+//! 9. Optionally writes a JSON report of the run (`--report`, `--json`)
+//!
+//! The `inspect-piece` subcommand decodes and validates a single piece file,
+//! `diff` compares two builds and `verify` rechecks an extracted build.
+//! `history` answers questions about past builds from the `--history` file
+//! without going online.
+//!
+//! `watch` keeps running instead of relying on a scheduled workflow: it polls
+//! the versions document and runs the workflow above whenever the build
+//! changes, publishing the release itself and optionally serving a health
+//! endpoint.
 //!
 //! ## Modules
 //!
-//! - [`actions`] - GitHub Actions output handling
+//! - [`actions`] - GitHub Actions outputs, job summary and annotations
+//! - [`cdn`] - CDN root, local mirrors and snapshot record/replay
 //! - [`config`] - Configuration management for OSRS repositories
+//! - [`delta`] - Incremental updates from a previous build and a piece cache
+//! - [`diff`] - Build-to-build comparison
 //! - [`downloader`] - File downloading and extraction logic
 //! - [`file_ops`] - File operations (ZIP creation, checksums)
 //! - [`github`] - GitHub API integration
+//! - [`history`] - Local JSON-lines history of observed builds and re-promotion detection
+//! - [`manifest`] - Build manifests (file hashes, versions, signatures)
+//! - [`notify`] - Webhook, Discord and Slack notifications about new builds
+//! - [`output`] - CI output sinks (GitHub Actions, GitLab dotenv, env-file, JSON)
+//! - [`pe_info`] - PE header, section, import and export metadata
+//! - [`piece`] - Piece headers, compression formats and digest algorithms
+//! - [`progress`] - Download progress bar and transfer statistics
+//! - [`report`] - Machine-readable JSON run report
+//! - [`signature`] - Authenticode signature inspection
+//! - [`tag`] - Release tag templating and sanitisation
+//! - [`throttle`] - Bandwidth limiting and `Retry-After` parsing
+//! - [`verify`] - Verification of extracted builds against their metafile
 //! - [`version`] - PE executable version extraction
+//! - [`watch`] - Long-running watch mode with persisted state and health endpoint
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
+use octocrab::Octocrab;
 use simple_logger::SimpleLogger;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crate::actions::{log_release_decision, set_github_actions_output, ActionOutput};
-use crate::downloader::download;
-use crate::file_ops::{calculate_checksum, safe_remove_file, zip_directory};
-use crate::github::{create_github_client, should_create_release};
-use crate::version::extract_versions_from_directory;
+use crate::actions::{annotate, log_release_decision, ActionOutput, Annotation};
+use crate::cdn::{Cdn, DEFAULT_USER_AGENT};
+use crate::diff::{BuildDiff, BuildSnapshot, BuildSource, ReleaseRepository};
+use crate::downloader::{DownloadResult, Downloader};
+use crate::file_ops::{calculate_checksum, safe_remove_file, zip_files};
+use crate::github::{
+    append_to_release_body, create_github_client, download_latest_release_asset, find_release_url,
+    publish_release, should_create_release, DowngradePolicy,
+};
+use crate::history::{first_seen, format_utc, HistoryEntry, HistoryStore};
+use crate::manifest::{check_signatures, Manifest};
+use crate::notify::{BuildNotification, NotificationTarget, Notifier, DEFAULT_TEMPLATE};
+use crate::output::{create_sink, OutputFormat};
+use crate::pe_info::write_pe_info;
+use crate::piece::{inspect_piece, DigestAlgorithm, PieceCompression, PieceFormat};
+use crate::progress::ProgressMode;
+use crate::report::{ArtifactSummary, ConfigSummary, DecisionSummary, RunReport, VersionSummary};
+use crate::tag::{TagContext, VersionSource};
+use crate::throttle::parse_rate;
+use crate::verify::verify_directory;
+use crate::version::{extract_versions_from_directory, find_executables, VersionSelection};
+use crate::watch::{WatchOptions, Watcher};
 
 pub mod actions;
+pub mod cdn;
 pub mod config;
+pub mod delta;
+pub mod diff;
 pub mod downloader;
 pub mod file_ops;
 pub mod github;
+pub mod history;
+pub mod manifest;
+pub mod notify;
+pub mod output;
+pub mod pe_info;
+pub mod piece;
+pub mod progress;
+pub mod report;
+pub mod signature;
+pub mod tag;
+#[cfg(test)]
+pub mod test_support;
+pub mod throttle;
+pub mod verify;
 pub mod version;
+pub mod watch;
 
 /// Command line arguments for the OSRS Archive Release Updater
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Repository identifier (e.g., "osrs-win", "osrs3-win")
     #[arg(long, default_value = "osrs-win")]
     repo: String,
@@ -57,13 +128,58 @@ struct Args {
     #[arg(long, default_value = "osrs-win.production.zip")]
     artifact_name: String,
 
-    /// Directory to download files to and create artifacts in
+    /// Directory to download files to and create artifacts in; it is
+    /// replaced by each download, so it must be empty or hold a previous build
     #[arg(long, default_value = "downloads/")]
     output_dir: String,
 
+    /// CDN root or local mirror (`https://...`, `file://...` or a directory);
+    /// defaults to Jagex's CDN
+    #[arg(long, env = "OSRS_CDN_URL")]
+    cdn_url: Option<String>,
+
+    /// Save every fetched config document and piece below this directory;
+    /// pieces are then always downloaded rather than reused
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Download exclusively from a snapshot written by --record
+    #[arg(long, conflicts_with_all = ["cdn_url", "record"])]
+    replay: Option<String>,
+
+    /// Limit the combined CDN download rate, in bytes per second (e.g. 500K, 2M)
+    #[arg(long, value_parser = parse_rate)]
+    rate_limit: Option<u64>,
+
+    /// Milliseconds to wait before each CDN request
+    #[arg(long, default_value_t = 0)]
+    request_delay_ms: u64,
+
+    /// User-Agent sent to the CDN and with release asset downloads
+    #[arg(long, env = "OSRS_USER_AGENT", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Keep downloaded pieces in this directory and reuse them across builds;
+    /// must be outside the output directory
+    #[arg(long, env = "OSRS_PIECE_CACHE")]
+    piece_cache: Option<PathBuf>,
+
+    /// Directory to stage downloads in before they replace the output
+    /// directory; defaults to the output directory's parent
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+
+    /// How to show download progress
+    #[arg(long, value_enum, default_value = "auto")]
+    progress: ProgressMode,
+
     /// GitHub personal access token for API access
-    #[arg(long, env = "GITHUB_TOKEN")]
-    github_token: String,
+    #[arg(long, env = "GITHUB_TOKEN", required = true)]
+    github_token: Option<String>,
+
+    /// GitHub API root, e.g. for GitHub Enterprise Server
+    #[arg(long, env = "GITHUB_API_URL")]
+    github_api_url: Option<String>,
 
     /// GitHub repository owner (username or organization)
     #[arg(long, default_value = "cozmoe0")]
@@ -72,6 +188,265 @@ struct Args {
     /// GitHub repository name
     #[arg(long, default_value = "osrs-archive")]
     github_repo: String,
+
+    /// Create the GitHub release and upload the artifact and manifest
+    /// instead of leaving that to the workflow; `watch` always publishes
+    #[arg(long, env = "OSRS_PUBLISH")]
+    publish: bool,
+
+    /// Executable whose file version identifies the build
+    #[arg(long, default_value = "osclient.exe")]
+    primary_executable: String,
+
+    /// Fallback executables tried in order when the primary has no version
+    #[arg(long, value_delimiter = ',')]
+    executable_priority: Vec<String>,
+
+    /// Only scan the top level of the output directory for executables
+    #[arg(long)]
+    no_recursive_scan: bool,
+
+    /// Where the build version comes from
+    #[arg(long, value_enum, default_value = "pe")]
+    version_source: VersionSource,
+
+    /// Release tag template, e.g. "{repo}-{build}-{file_version}" or "{metafile.version}"
+    #[arg(long, default_value = "{version}")]
+    tag_template: String,
+
+    /// How to handle a build older than the latest release
+    #[arg(long, value_enum, default_value = "flag")]
+    on_downgrade: DowngradePolicy,
+
+    /// Manifest of the previous build; defaults to the latest release's manifest asset
+    #[arg(long)]
+    previous_manifest: Option<String>,
+
+    /// Directory to write per-executable PE header/import/export JSON to
+    #[arg(long)]
+    pe_info_dir: Option<String>,
+
+    /// Write a JSON report of the run to this path
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Print the JSON run report to stdout (logs and annotations go to stderr)
+    #[arg(long)]
+    json: bool,
+
+    /// CI system to write outputs for
+    #[arg(long, value_enum, default_value = "auto")]
+    output_format: OutputFormat,
+
+    /// File written by the gitlab, env-file and json output formats
+    #[arg(long)]
+    output_file: Option<String>,
+
+    /// Notify this endpoint about new builds: a webhook URL, or
+    /// `discord=URL`, `slack=URL` or `webhook=URL`; may be repeated
+    #[arg(long = "notify", env = "OSRS_NOTIFY", hide_env_values = true, value_delimiter = ',', value_parser = NotificationTarget::parse)]
+    notify: Vec<NotificationTarget>,
+
+    /// Notification message; placeholders are {repo}, {build}, {version},
+    /// {previous_version}, {checksum}, {release_url}, {promote_time}, {changed_files},
+    /// {added}, {removed} and {modified}
+    #[arg(long, default_value = DEFAULT_TEMPLATE)]
+    notify_template: String,
+
+    /// How often a failed notification is retried
+    #[arg(long, default_value_t = 3)]
+    notify_retries: u32,
+
+    /// Append every observed build to this JSON-lines history file, which
+    /// classifies builds as new, re-promoted or rescanned; `watch` defaults
+    /// to a file next to its state file
+    #[arg(long, env = "OSRS_HISTORY")]
+    history: Option<PathBuf>,
+}
+
+/// Standalone tools; without a subcommand the updater runs
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Decode and validate a downloaded .solidpiece file
+    InspectPiece {
+        /// Path to the piece; a `<digest>.solidpiece` name is checked
+        path: String,
+
+        /// Catalog piece format the piece is expected to use
+        #[arg(long, default_value = "solidpieces")]
+        piece_format: String,
+
+        /// Digest algorithm of the piece file name
+        #[arg(long, default_value = "SHA-256")]
+        digest_algorithm: String,
+    },
+
+    /// Compare two builds: `repo.build`, a directory, a manifest or `tag:<tag>`
+    Diff {
+        /// Build to compare from
+        from: String,
+
+        /// Build to compare to
+        to: String,
+
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Append the diff to the body of the release with this tag
+        #[arg(long, value_name = "TAG")]
+        append_to_release: Option<String>,
+    },
+
+    /// Check a previously downloaded build against its metafile
+    Verify {
+        /// Output directory of the download
+        dir: String,
+    },
+
+    /// Keep running and publish a release whenever the versions document
+    /// announces a new build
+    Watch {
+        /// Seconds between checks of the versions document
+        #[arg(long, default_value_t = 300)]
+        interval_secs: u64,
+
+        /// Maximum random deviation from the interval, in seconds
+        #[arg(long, default_value_t = 30)]
+        jitter_secs: u64,
+
+        /// File the last processed build is kept in across restarts; the
+        /// build history defaults to `<state file>.history.jsonl` next to it
+        #[arg(long, default_value = "watch-state.json")]
+        state_file: PathBuf,
+
+        /// Serve a health endpoint on this address, e.g. 127.0.0.1:8080
+        #[arg(long)]
+        health_addr: Option<SocketAddr>,
+    },
+
+    /// Query the builds recorded with --history for --repo and --build
+    History {
+        #[command(subcommand)]
+        query: HistoryQuery,
+    },
+}
+
+/// Offline queries against the `--history` file
+#[derive(Debug, Subcommand)]
+enum HistoryQuery {
+    /// List recorded observations, oldest first
+    List {
+        /// Only show the most recent entries
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Print the entries as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show when a build first appeared
+    FirstSeen {
+        /// Version, version id, release tag or metafile id of the build
+        build: String,
+
+        /// Print the entry as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Args {
+    /// Returns the GitHub token; clap requires it unless a subcommand runs
+    fn github_token(&self) -> &str {
+        self.github_token.as_deref().unwrap_or_default()
+    }
+
+    /// Returns true if the updater publishes releases itself
+    ///
+    /// `watch` runs outside the workflow that would otherwise create the
+    /// release, so a build it finds would be lost without publishing.
+    fn publishes(&self) -> bool {
+        self.publish || matches!(self.command, Some(Command::Watch { .. }))
+    }
+
+    /// Returns the history file builds are recorded in
+    ///
+    /// `watch` keeps one next to its state file unless `--history` is given,
+    /// so its builds are classified without extra configuration.
+    fn history_path(&self) -> Option<PathBuf> {
+        match (&self.history, &self.command) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(Command::Watch { state_file, .. })) => {
+                Some(state_file.with_extension("history.jsonl"))
+            }
+            (None, _) => None,
+        }
+    }
+
+    /// Returns the file name of the build manifest, derived from the artifact name
+    fn manifest_name(&self) -> String {
+        let stem = self
+            .artifact_name
+            .strip_suffix(".zip")
+            .unwrap_or(&self.artifact_name);
+        format!("{}.manifest.json", stem)
+    }
+
+    /// Creates the CDN client from `--cdn-url`, `--record` and `--replay`,
+    /// throttled by `--rate-limit` and `--request-delay-ms`
+    fn cdn(&self) -> Result<Cdn> {
+        if let Some(snapshot) = &self.replay {
+            return Cdn::replay(Path::new(snapshot)).context("Invalid replay snapshot");
+        }
+
+        let mut cdn = Cdn::new(self.cdn_url.as_deref())
+            .context("Invalid CDN URL")?
+            .with_user_agent(&self.user_agent)
+            .with_request_delay(Duration::from_millis(self.request_delay_ms));
+        if let Some(rate) = self.rate_limit {
+            cdn = cdn.with_rate_limit(rate);
+        }
+        Ok(match &self.record {
+            Some(snapshot) => cdn.recording(Path::new(snapshot)),
+            None => cdn,
+        })
+    }
+
+    /// Creates the downloader for `output_dir` from the CDN, cache, staging
+    /// and progress options
+    fn downloader(&self, output_dir: &Path) -> Result<Downloader> {
+        let mut downloader =
+            Downloader::new(self.repo.clone(), output_dir.to_path_buf(), self.cdn()?)
+                .with_progress(self.progress);
+        if let Some(dir) = &self.piece_cache {
+            downloader = downloader.with_piece_cache(dir);
+        }
+        if let Some(dir) = &self.work_dir {
+            downloader = downloader.with_work_dir(dir);
+        }
+        Ok(downloader)
+    }
+
+    /// Creates the notifier for `--notify`, or `None` without targets
+    fn notifier(&self) -> Result<Option<Notifier>> {
+        if self.notify.is_empty() {
+            return Ok(None);
+        }
+        let notifier = Notifier::new(self.notify.clone(), &self.notify_template)?
+            .with_retries(self.notify_retries, Duration::from_secs(2));
+        Ok(Some(notifier))
+    }
+
+    /// Builds the executable selection policy used for version detection
+    fn version_selection(&self) -> VersionSelection {
+        VersionSelection {
+            primary: Some(self.primary_executable.clone()).filter(|name| !name.is_empty()),
+            priority: self.executable_priority.clone(),
+            recursive: !self.no_recursive_scan,
+        }
+    }
 }
 
 #[tokio::main]
@@ -79,16 +454,31 @@ async fn main() -> Result<()> {
     init_logging()?;
 
     let args = Args::parse();
-    let result = run_application(args).await;
+    if let Some(command) = &args.command {
+        return run_command(&args, command).await;
+    }
 
+    let result = run_pipeline(&args).await;
     if let Err(ref e) = result {
-        log::error!("Application failed: {:?}", e);
+        annotate(Annotation::Error, &format!("Application failed: {:?}", e));
         std::process::exit(1);
     }
 
     result
 }
 
+/// Runs the updater once and writes its report
+async fn run_pipeline(args: &Args) -> Result<()> {
+    let mut report = RunReport::new(&args.repo, &args.build);
+    let result = run_application(args, &mut report).await;
+
+    report.finish(result.as_ref().err());
+    if let Err(e) = write_report(args, &report) {
+        log::error!("Failed to write run report: {:?}", e);
+    }
+    result
+}
+
 /// Initializes logging with colors and info level
 fn init_logging() -> Result<()> {
     SimpleLogger::new()
@@ -98,77 +488,536 @@ fn init_logging() -> Result<()> {
         .context("Failed to initialize logging")
 }
 
+/// Runs a standalone subcommand
+async fn run_command(args: &Args, command: &Command) -> Result<()> {
+    match command {
+        Command::InspectPiece {
+            path,
+            piece_format,
+            digest_algorithm,
+        } => {
+            let format = PieceFormat {
+                compression: PieceCompression::from_catalog(piece_format)?,
+                digest: DigestAlgorithm::from_name(digest_algorithm)?,
+            };
+            let inspection = inspect_piece(Path::new(path), format)?;
+            print!("{}", inspection);
+            if !inspection.is_valid() {
+                anyhow::bail!("Piece {} is invalid", path);
+            }
+            Ok(())
+        }
+        Command::Diff {
+            from,
+            to,
+            json,
+            append_to_release,
+        } => run_diff(args, from, to, *json, append_to_release.as_deref()).await,
+        Command::Verify { dir } => {
+            let manifest_name = args.manifest_name();
+            let generated = [args.artifact_name.as_str(), manifest_name.as_str()];
+            let verification = verify_directory(Path::new(dir), &generated)?;
+            print!("{}", verification);
+            if !verification.is_valid() {
+                anyhow::bail!("Build in {} is invalid", dir);
+            }
+            Ok(())
+        }
+        Command::Watch {
+            interval_secs,
+            jitter_secs,
+            state_file,
+            health_addr,
+        } => {
+            if args.github_token.is_none() {
+                anyhow::bail!("--github-token or GITHUB_TOKEN is required to watch for updates");
+            }
+            let options = WatchOptions {
+                interval: Duration::from_secs(*interval_secs),
+                jitter: Duration::from_secs(*jitter_secs),
+                state_file: state_file.clone(),
+                health_addr: *health_addr,
+            };
+            let watcher = Watcher::new(&args.repo, &args.build, args.cdn()?, options)?;
+            watcher.run(|| run_pipeline(args)).await
+        }
+        Command::History { query } => run_history(args, query),
+    }
+}
+
+/// Answers a history query from the `--history` file
+fn run_history(args: &Args, query: &HistoryQuery) -> Result<()> {
+    let path = args
+        .history
+        .as_deref()
+        .context("--history or OSRS_HISTORY is required to query the build history")?;
+    let entries = HistoryStore::new(path).entries(&args.repo, &args.build)?;
+
+    match query {
+        HistoryQuery::List { limit, json } => {
+            let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+            let entries = &entries[skip..];
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(entries).context("Failed to serialize history")?
+                );
+            } else {
+                for entry in entries {
+                    println!("{}", entry);
+                }
+            }
+        }
+        HistoryQuery::FirstSeen { build, json } => {
+            let entry = first_seen(&entries, build).with_context(|| {
+                format!(
+                    "Build {} has not been recorded for {}.{}",
+                    build, args.repo, args.build
+                )
+            })?;
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(entry).context("Failed to serialize history")?
+                );
+            } else {
+                println!("{}", entry);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compares two builds and prints the differences
+async fn run_diff(
+    args: &Args,
+    from: &str,
+    to: &str,
+    json: bool,
+    append_to_release: Option<&str>,
+) -> Result<()> {
+    let from = BuildSource::parse(from)?;
+    let to = BuildSource::parse(to)?;
+    let cdn = args.cdn()?;
+
+    let needs_github = from.needs_github() || to.needs_github() || append_to_release.is_some();
+    let github = match &args.github_token {
+        Some(token) if needs_github => {
+            Some(create_github_client(token, args.github_api_url.as_deref())?)
+        }
+        _ => None,
+    };
+    let manifest_name = args.manifest_name();
+    let releases = github.as_ref().map(|github| ReleaseRepository {
+        github,
+        token: args.github_token(),
+        user_agent: &args.user_agent,
+        owner: &args.github_owner,
+        repo: &args.github_repo,
+        manifest_name: &manifest_name,
+    });
+
+    let generated = [args.artifact_name.as_str(), manifest_name.as_str()];
+    let from = BuildSnapshot::load(&from, &cdn, releases.as_ref(), &generated).await?;
+    let to = BuildSnapshot::load(&to, &cdn, releases.as_ref(), &generated).await?;
+    let diff = BuildDiff::compare(&from, &to);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).context("Failed to serialize diff")?
+        );
+    } else {
+        print!("{}", diff);
+    }
+
+    if let Some(tag) = append_to_release {
+        let github = github
+            .as_ref()
+            .context("A GitHub token is required to update a release")?;
+        append_to_release_body(
+            github,
+            &args.github_owner,
+            &args.github_repo,
+            tag,
+            &diff.to_markdown(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Writes the run report to `--report` and/or stdout
+fn write_report(args: &Args, report: &RunReport) -> Result<()> {
+    if let Some(path) = &args.report {
+        report.save(Path::new(path))?;
+    }
+    if args.json {
+        println!("{}", report.to_json()?);
+    }
+    Ok(())
+}
+
 /// Main application logic
-async fn run_application(args: Args) -> Result<()> {
+async fn run_application(args: &Args, report: &mut RunReport) -> Result<()> {
     let output_dir = PathBuf::from(&args.output_dir);
+    let notifier = args.notifier()?;
 
     // Download and package files
-    let artifact_path =
-        download_files(&args.repo, &args.build, &output_dir, &args.artifact_name).await?;
+    let started = Instant::now();
+    let downloader = args.downloader(&output_dir)?;
+    let (download, artifact_path) =
+        download_files(downloader, &args.build, &output_dir, &args.artifact_name).await?;
+    let config = download.config;
+    let download_stats = download.stats;
+    report.config = Some(ConfigSummary::from(&config));
+    report.download = Some(download_stats.clone());
+    report.record_phase("download", started);
     log::info!("Created artifact: {}", artifact_path.display());
 
     // Calculate checksum and extract version
+    let started = Instant::now();
     let checksum = calculate_checksum(&artifact_path).await?;
     log::info!("Calculated artifact checksum: {}", checksum);
+    report.artifact = Some(ArtifactSummary {
+        path: artifact_path.display().to_string(),
+        size: std::fs::metadata(&artifact_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default(),
+        sha256: checksum.clone(),
+    });
+    report.record_phase("checksum", started);
 
-    let version = extract_versions_from_directory(&output_dir)?;
-    log::info!("Extracted artifact version: {}", version);
+    let started = Instant::now();
+    let file_version = extract_versions_from_directory(&output_dir, &args.version_selection())?;
+    let tag_context = TagContext::new(&config, file_version.as_deref(), args.version_source)
+        .context("Failed to determine build version")?;
+    log::info!("Extracted artifact version: {}", tag_context.version());
+
+    let version = tag_context
+        .render(&args.tag_template)
+        .context("Failed to render release tag")?;
+    log::info!("Release tag: {}", version);
+    report.version = Some(VersionSummary {
+        source: args
+            .version_source
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default(),
+        file_version: file_version.clone(),
+        version: tag_context.version().to_string(),
+        tag: version.clone(),
+    });
+    report.record_phase("version", started);
+
+    // Record the build manifest and check executable signatures
+    let started = Instant::now();
+    let github = create_github_client(args.github_token(), args.github_api_url.as_deref())?;
+    let manifest_name = args.manifest_name();
+    let generated = [args.artifact_name.as_str(), manifest_name.as_str()];
+    let manifest =
+        Manifest::from_directory(&output_dir, &args.repo, &args.build, &version, &generated)
+            .context("Failed to build manifest")?;
+    let manifest_path = output_dir.join(&manifest_name);
+    manifest.save(&manifest_path)?;
+    let manifest_sha256 = calculate_checksum(&manifest_path).await?;
+    report.files = manifest.files.clone();
+
+    let previous_manifest = load_previous_manifest(args, &github).await?;
+    check_signatures(previous_manifest.as_ref(), &manifest)?;
+    report.record_phase("manifest", started);
+
+    if let Some(pe_info_dir) = &args.pe_info_dir {
+        let executables = find_executables(&output_dir, !args.no_recursive_scan)?;
+        write_pe_info(&output_dir, &executables, Path::new(pe_info_dir))
+            .context("Failed to write PE info")?;
+    }
 
     // Check if we should create a release
-    let github = create_github_client(&args.github_token)?;
+    let started = Instant::now();
     let release_check = should_create_release(
         &github,
         &args.github_owner,
         &args.github_repo,
         &version,
         &checksum,
+        args.on_downgrade,
     )
     .await?;
+    report.decision = Some(DecisionSummary::from(&release_check));
+    report.record_phase("release_check", started);
+
+    log::info!(
+        "Build {} was promoted {}",
+        config.version.version,
+        format_utc(config.version.promote_time / 1000)
+    );
+    let build_event = args.history_path().and_then(|path| {
+        let entry = HistoryEntry::new(&config, &version, &manifest_sha256, report.decision.clone());
+        // The history is bookkeeping; losing one entry must not fail the run
+        match HistoryStore::new(&path).record(entry) {
+            Ok(event) => {
+                log::info!("Build event: {}", event);
+                Some(event)
+            }
+            Err(e) => {
+                annotate(Annotation::Warning, &format!("{:#}", e));
+                None
+            }
+        }
+    });
+    report.build_event = build_event;
 
-    // Set GitHub Actions output and clean up if needed
+    // Write CI outputs and clean up if needed
+    let sink = create_sink(
+        args.output_format,
+        args.output_file.as_deref().map(Path::new),
+    );
     if release_check.should_create {
-        let output = ActionOutput::update_available(version.clone(), checksum, &artifact_path);
-        set_github_actions_output(&output)?;
+        let output =
+            ActionOutput::update_available(version.clone(), checksum.clone(), &artifact_path)
+                .with_manifest(&manifest_path)
+                .with_release_check(&release_check)
+                .with_promote_time(config.version.promote_time)
+                .with_build_event(build_event)
+                .with_download_stats(&download_stats);
+        sink.write(&output)?;
+        sink.write_summary(&output, &manifest)?;
         log_release_decision(true, &release_check.reason, &version);
+
+        let mut release_url = None;
+        if args.publishes() {
+            let started = Instant::now();
+            let url = publish_release(
+                &github,
+                &args.github_owner,
+                &args.github_repo,
+                &version,
+                &release_check,
+                &output.release_notes(),
+                &[&artifact_path, &manifest_path],
+            )
+            .await?;
+            report.record_phase("publish", started);
+            log::info!("Published release {}", url);
+            release_url = Some(url);
+        }
+
+        if let Some(notifier) = &notifier {
+            // Without publishing, link the release if it was created elsewhere
+            let release_url = match release_url {
+                Some(url) => Some(url),
+                None => find_release_url(&github, &args.github_owner, &args.github_repo, &version)
+                    .await
+                    .unwrap_or_else(|e| {
+                        annotate(Annotation::Warning, &format!("{:#}", e));
+                        None
+                    }),
+            };
+            let notification = BuildNotification {
+                repo: args.repo.clone(),
+                build: args.build.clone(),
+                version: version.clone(),
+                previous_version: release_check.previous_version.clone(),
+                checksum,
+                release_url: release_url.unwrap_or_default(),
+                promote_time: output.promote_time.clone(),
+                rollback: release_check.change.is_rollback(),
+                prerelease: release_check.prerelease,
+                ..Default::default()
+            }
+            .with_changes(previous_manifest.as_ref(), &manifest);
+            // A missed notification must not hold back the release
+            if let Err(e) = notifier.notify(&notification).await {
+                annotate(Annotation::Warning, &format!("{:#}", e));
+            }
+        }
     } else {
-        let output = ActionOutput::no_update();
-        set_github_actions_output(&output)?;
+        let output = ActionOutput::no_update()
+            .with_release_check(&release_check)
+            .with_promote_time(config.version.promote_time)
+            .with_build_event(build_event)
+            .with_download_stats(&download_stats);
+        sink.write(&output)?;
+        sink.write_summary(&output, &manifest)?;
         log_release_decision(false, &release_check.reason, &version);
 
         // Clean up artifact file since no release will be created
         safe_remove_file(&artifact_path).await;
+        safe_remove_file(&manifest_path).await;
     }
 
     Ok(())
 }
 
+/// Loads the previous build's manifest from `--previous-manifest` or the
+/// latest GitHub release
+async fn load_previous_manifest(args: &Args, github: &Octocrab) -> Result<Option<Manifest>> {
+    if let Some(path) = &args.previous_manifest {
+        return Manifest::load(Path::new(path)).map(Some);
+    }
+
+    let asset = download_latest_release_asset(
+        github,
+        args.github_token(),
+        &args.user_agent,
+        &args.github_owner,
+        &args.github_repo,
+        &args.manifest_name(),
+    )
+    .await?;
+
+    asset
+        .map(|json| Manifest::from_json(&json).context("Invalid manifest in latest release"))
+        .transpose()
+}
+
 /// Downloads and packages files into a ZIP archive
 ///
 /// # Arguments
 ///
-/// * `repo` - Repository identifier (e.g., "osrs-win")
+/// * `downloader` - Downloader for the repository and output directory
 /// * `build` - Build identifier (e.g., "production")
-/// * `output_dir` - Directory to download files to
+/// * `output_dir` - Directory the downloader extracts the build into
 /// * `artifact_name` - Name of the resulting ZIP archive
 ///
 /// # Returns
 ///
-/// Returns the path to the created ZIP archive.
+/// Returns the download result and the path to the created ZIP archive.
 async fn download_files(
-    repo: &str,
+    downloader: Downloader,
     build: &str,
     output_dir: &Path,
     artifact_name: &str,
-) -> Result<PathBuf> {
-    log::info!("Downloading files for {}.{}", repo, build);
-    download(repo, build, &output_dir.to_path_buf())
+) -> Result<(DownloadResult, PathBuf)> {
+    let download = downloader
+        .download_build(build)
         .await
         .context("Failed to download files")?;
 
     log::info!("Compressing files into artifact archive...");
     let artifact_path = output_dir.join(artifact_name);
-    zip_directory(output_dir, &artifact_path).context("Failed to create ZIP archive")?;
+    let names: Vec<String> = download
+        .config
+        .metafile
+        .files
+        .iter()
+        .map(|file| file.name.clone())
+        .collect();
+    zip_files(output_dir, &names, &artifact_path).context("Failed to create ZIP archive")?;
 
     log::info!("Successfully created artifact archive: {}", artifact_name);
-    Ok(artifact_path)
+    Ok((download, artifact_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::BuildEvent;
+    use crate::test_support::{FixtureBuild, MockGitHub, MockWebhook};
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(
+            ["release-updater", "--github-token", "token"]
+                .iter()
+                .chain(args),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_watch_records_history_by_default() {
+        let args = parse(&["watch", "--state-file", "state/watch.json"]);
+        assert_eq!(
+            args.history_path(),
+            Some(PathBuf::from("state/watch.history.jsonl"))
+        );
+
+        let args = parse(&["--history", "builds.jsonl", "watch"]);
+        assert_eq!(args.history_path(), Some(PathBuf::from("builds.jsonl")));
+
+        assert_eq!(parse(&[]).history_path(), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_application_replays_snapshot_offline() {
+        let build = FixtureBuild::new(&[
+            ("osclient.exe", b"client data, long enough to span pieces"),
+            ("data/readme.txt", b"hello"),
+        ]);
+        let snapshot = tempfile::tempdir().unwrap();
+        build.write_snapshot(snapshot.path());
+        let github = MockGitHub::start().await;
+        let webhook = MockWebhook::start(Vec::new()).await;
+        let work = tempfile::tempdir().unwrap();
+        let output_dir = work.path().join("downloads");
+        let outputs = work.path().join("outputs.json");
+        let history = work.path().join("history.jsonl");
+
+        let args = parse(&[
+            "--replay",
+            snapshot.path().to_str().unwrap(),
+            "--github-api-url",
+            &github.url(),
+            "--output-dir",
+            output_dir.to_str().unwrap(),
+            "--version-source",
+            "config",
+            "--output-format",
+            "json",
+            "--output-file",
+            outputs.to_str().unwrap(),
+            "--history",
+            history.to_str().unwrap(),
+            "--publish",
+            "--notify",
+            &webhook.url(),
+        ]);
+
+        let mut report = RunReport::new(&args.repo, &args.build);
+        run_application(&args, &mut report).await.unwrap();
+        let decision = report.decision.unwrap();
+        assert!(decision.should_create, "{}", decision.reason);
+        assert_eq!(report.build_event, Some(BuildEvent::New));
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&outputs).unwrap()).unwrap();
+        assert_eq!(written["update_available"], true);
+        assert_eq!(written["version"], "225.1");
+
+        let releases = github.releases();
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].tag, "225.1");
+        let assets: Vec<_> = releases[0]
+            .assets
+            .iter()
+            .map(|(_, name, _)| name.clone())
+            .collect();
+        assert_eq!(
+            assets,
+            vec![args.artifact_name.clone(), args.manifest_name()]
+        );
+
+        // The notification links the release on the configured GitHub API
+        let notifications = webhook.requests();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(
+            notifications[0].1["build"]["release_url"],
+            format!(
+                "{}repos/{}/{}/releases/{}",
+                github.url(),
+                args.github_owner,
+                args.github_repo,
+                releases[0].id
+            )
+        );
+
+        // The next run finds its own release and publishes nothing
+        let mut report = RunReport::new(&args.repo, &args.build);
+        run_application(&args, &mut report).await.unwrap();
+        let decision = report.decision.unwrap();
+        assert!(!decision.should_create, "{}", decision.reason);
+        assert_eq!(report.build_event, Some(BuildEvent::Unchanged));
+        assert_eq!(github.releases().len(), 1);
+        assert!(!output_dir.join(&args.artifact_name).exists());
+    }
 }
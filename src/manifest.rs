@@ -0,0 +1,417 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::actions::{annotate, Annotation};
+use crate::delta::BUILD_INDEX_FILE;
+use crate::signature::{inspect_signature, SignatureInfo};
+use crate::version::extract_file_version;
+
+/// Version of the manifest JSON layout, bumped on incompatible changes
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// Describes the files of an archived build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub repo: String,
+    pub build: String,
+    pub version: String,
+    pub files: Vec<ManifestFile>,
+}
+
+/// A single file of an archived build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the build root, using `/` as separator
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<SignatureInfo>,
+    /// Why the signature could not be inspected; `signature` is then unknown
+    /// rather than absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_error: Option<String>,
+}
+
+impl Manifest {
+    /// Builds a manifest from the files below `dir`
+    ///
+    /// `generated` lists the files this tool wrote next to the build, such
+    /// as the artifact and the manifest itself; they are skipped like the
+    /// build index, see [`list_files`].
+    pub fn from_directory(
+        dir: &Path,
+        repo: &str,
+        build: &str,
+        version: &str,
+        generated: &[&str],
+    ) -> Result<Self> {
+        let mut files = Vec::new();
+
+        for path in list_files(dir, generated)? {
+            let name = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.push(ManifestFile::from_path(&path, name)?);
+        }
+
+        Ok(Self {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            repo: repo.to_string(),
+            build: build.to_string(),
+            version: version.to_string(),
+            files,
+        })
+    }
+
+    /// Reads a manifest from a JSON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read(path)
+            .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("Invalid manifest: {}", path.display()))
+    }
+
+    /// Parses a manifest from JSON bytes
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        let manifest: Self = serde_json::from_slice(json)?;
+        if manifest.schema_version > MANIFEST_SCHEMA_VERSION {
+            bail!(
+                "Unsupported manifest schema version {}",
+                manifest.schema_version
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Writes the manifest as pretty-printed JSON
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize manifest")?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))?;
+
+        log::info!("Wrote manifest: {}", path.display());
+        Ok(())
+    }
+
+    /// Looks up a file by its relative name
+    pub fn file(&self, name: &str) -> Option<&ManifestFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+}
+
+impl ManifestFile {
+    fn from_path(path: &Path, name: String) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)
+            .with_context(|| format!("Failed to hash file: {}", path.display()))?;
+
+        let mut entry = Self {
+            name,
+            size,
+            sha256: hex::encode(hasher.finalize()),
+            file_version: None,
+            signature: None,
+            signature_error: None,
+        };
+
+        if is_executable(path) {
+            entry.file_version = extract_file_version(path).unwrap_or_else(|e| {
+                log::debug!("Failed to read version info from {}: {}", entry.name, e);
+                None
+            });
+            match inspect_signature(path) {
+                Ok(signature) => entry.signature = signature,
+                Err(e) => {
+                    log::warn!("Failed to inspect signature of {}: {:?}", entry.name, e);
+                    entry.signature_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Returns true if the file is a PE executable
+    pub fn is_executable(&self) -> bool {
+        is_executable(Path::new(&self.name))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("exe") || extension.eq_ignore_ascii_case("dll")
+    })
+}
+
+/// Lists all regular files below `dir` in sorted order
+///
+/// Skips the build index and the `generated` files, given as paths relative
+/// to `dir`. Every other file belongs to the build, whatever its name.
+pub fn list_files(dir: &Path, generated: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let entries = std::fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+
+        for entry in entries {
+            let path = entry
+                .with_context(|| {
+                    format!("Failed to read directory entry in: {}", current.display())
+                })?
+                .path();
+
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.is_file() && !is_generated(dir, &path, generated) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn is_generated(dir: &Path, path: &Path, generated: &[&str]) -> bool {
+    let name = path.strip_prefix(dir).unwrap_or(path);
+    name == Path::new(BUILD_INDEX_FILE) || generated.iter().any(|file| name == Path::new(file))
+}
+
+/// Compares executable signatures against the previous build's manifest
+///
+/// # Errors
+///
+/// Returns an error if an executable the previous manifest recorded as
+/// signed is now unsigned, or its signature cannot be inspected or does not
+/// match the file. The same findings for executables that were not signed
+/// before, and a changed signer, are only reported as warnings.
+pub fn check_signatures(previous: Option<&Manifest>, current: &Manifest) -> Result<()> {
+    let mut problems = Vec::new();
+
+    for file in current.files.iter().filter(|file| file.is_executable()) {
+        let previous_signature = previous
+            .and_then(|manifest| manifest.file(&file.name))
+            .and_then(|file| file.signature.as_ref());
+        let mut report = |problem: String| {
+            if previous_signature.is_some() {
+                problems.push(problem);
+            } else {
+                annotate(Annotation::Warning, &problem);
+            }
+        };
+
+        // An unreadable signature is neither signed nor unsigned
+        if let Some(error) = &file.signature_error {
+            report(format!(
+                "{} has a signature that could not be inspected: {}",
+                file.name, error
+            ));
+            continue;
+        }
+
+        match (&file.signature, previous_signature) {
+            (None, Some(previous)) => problems.push(format!(
+                "{} was signed by {} but is now unsigned",
+                file.name, previous.signer_subject
+            )),
+            (None, None) => log::debug!("{} is not signed", file.name),
+            (Some(signature), previous) => {
+                if !signature.digest_valid {
+                    report(format!(
+                        "{} has a signature whose digest does not match the file",
+                        file.name
+                    ));
+                }
+
+                log::info!(
+                    "{} signed by {} (issuer: {}, time: {})",
+                    file.name,
+                    signature.signer_subject,
+                    signature.signer_issuer,
+                    signature.signing_time.as_deref().unwrap_or("unknown")
+                );
+
+                if let Some(previous) = previous {
+                    if previous.signer_fingerprint() != signature.signer_fingerprint() {
+                        annotate(
+                            Annotation::Warning,
+                            &format!(
+                                "Signing certificate of {} changed: {} -> {}",
+                                file.name, previous.signer_subject, signature.signer_subject
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("Signature check failed:\n{}", problems.join("\n"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::CertificateInfo;
+    use crate::test_support::pe_image;
+
+    fn signature(subject: &str, fingerprint: &str, digest_valid: bool) -> SignatureInfo {
+        SignatureInfo {
+            signer_subject: subject.to_string(),
+            signer_issuer: "CN=Issuer".to_string(),
+            signer_serial: "01".to_string(),
+            signing_time: None,
+            digest_algorithm: "sha256".to_string(),
+            digest_valid,
+            certificate_chain: vec![CertificateInfo {
+                subject: subject.to_string(),
+                issuer: "CN=Issuer".to_string(),
+                serial: "01".to_string(),
+                not_before: String::new(),
+                not_after: String::new(),
+                sha256_fingerprint: fingerprint.to_string(),
+            }],
+        }
+    }
+
+    fn manifest(signature: Option<SignatureInfo>) -> Manifest {
+        Manifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: "1.0.0.0".to_string(),
+            files: vec![ManifestFile {
+                name: "osclient.exe".to_string(),
+                size: 1,
+                sha256: String::new(),
+                file_version: None,
+                signature,
+                signature_error: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_check_signatures_fails_when_signature_removed() {
+        let previous = manifest(Some(signature("CN=Jagex", "aa", true)));
+        let current = manifest(None);
+        assert!(check_signatures(Some(&previous), &current).is_err());
+        assert!(check_signatures(None, &current).is_ok());
+    }
+
+    #[test]
+    fn test_check_signatures_allows_signer_change_but_not_bad_digest() {
+        let previous = manifest(Some(signature("CN=Jagex", "aa", true)));
+        let current = manifest(Some(signature("CN=Jagex Ltd", "bb", true)));
+        assert!(check_signatures(Some(&previous), &current).is_ok());
+
+        let current = manifest(Some(signature("CN=Jagex", "aa", false)));
+        assert!(check_signatures(Some(&previous), &current).is_err());
+    }
+
+    #[test]
+    fn test_unreadable_signature_is_not_unsigned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("osclient.exe"), b"not a PE file").unwrap();
+
+        let current = Manifest::from_directory(temp_dir.path(), "r", "b", "1", &[]).unwrap();
+        assert!(current.files[0].signature.is_none());
+        assert!(current.files[0].signature_error.is_some());
+
+        let previous = manifest(Some(signature("CN=Jagex", "aa", true)));
+        let error = check_signatures(Some(&previous), &current).unwrap_err();
+        assert!(error.to_string().contains("could not be inspected"));
+        assert!(!error.to_string().contains("now unsigned"));
+        assert!(check_signatures(None, &current).is_ok());
+    }
+
+    #[test]
+    fn test_check_signatures_only_warns_for_executables_not_signed_before() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            temp_dir.path().join("osclient.exe"),
+            pe_image(&[(".text", Some(b"code"))]),
+        )
+        .unwrap();
+        std::fs::write(temp_dir.path().join("helper.exe"), b"not a PE file").unwrap();
+
+        let current = Manifest::from_directory(temp_dir.path(), "r", "b", "1", &[]).unwrap();
+        let unsigned = current.file("osclient.exe").unwrap();
+        assert!(unsigned.signature.is_none() && unsigned.signature_error.is_none());
+        assert!(current
+            .file("helper.exe")
+            .unwrap()
+            .signature_error
+            .is_some());
+
+        let previous = manifest(None);
+        assert!(check_signatures(Some(&previous), &current).is_ok());
+        assert!(check_signatures(None, &current).is_ok());
+
+        let current = manifest(Some(signature("CN=Jagex", "aa", false)));
+        assert!(check_signatures(Some(&previous), &current).is_ok());
+    }
+
+    #[test]
+    fn test_manifest_from_directory_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        std::fs::write(temp_dir.path().join("sub").join("a.txt"), b"abc").unwrap();
+        std::fs::write(temp_dir.path().join("artifact.zip"), b"zip").unwrap();
+        std::fs::write(temp_dir.path().join("artifact.manifest.json"), b"{}").unwrap();
+        std::fs::write(temp_dir.path().join(BUILD_INDEX_FILE), b"{}").unwrap();
+
+        let generated = ["artifact.zip", "artifact.manifest.json"];
+        let manifest =
+            Manifest::from_directory(temp_dir.path(), "r", "b", "1", &generated).unwrap();
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(manifest.files[0].name, "sub/a.txt");
+        assert_eq!(manifest.files[0].size, 3);
+
+        let path = temp_dir.path().join("out.manifest.json");
+        manifest.save(&path).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.files[0].sha256, manifest.files[0].sha256);
+    }
+
+    #[test]
+    fn test_list_files_keeps_build_files_named_like_generated_ones() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        std::fs::write(temp_dir.path().join("resources.zip"), b"zip").unwrap();
+        std::fs::write(temp_dir.path().join("sub/artifact.zip"), b"zip").unwrap();
+        std::fs::write(temp_dir.path().join("app.manifest.json"), b"{}").unwrap();
+        std::fs::write(temp_dir.path().join("artifact.zip"), b"zip").unwrap();
+
+        let files = list_files(temp_dir.path(), &["artifact.zip"]).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|path| path.strip_prefix(temp_dir.path()).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                Path::new("app.manifest.json"),
+                Path::new("resources.zip"),
+                Path::new("sub/artifact.zip"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,547 @@
+use anyhow::{bail, Context, Result};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::cdn::DEFAULT_USER_AGENT;
+use crate::diff::{BuildDiff, BuildSnapshot};
+use crate::manifest::Manifest;
+use crate::tag::fill_placeholders;
+use crate::throttle::parse_retry_after;
+
+/// Message sent unless `--notify-template` overrides it
+pub const DEFAULT_TEMPLATE: &str = "New {repo} {build} build {version} (previous: {previous_version}): {changed_files} files changed, SHA-256 {checksum}\n{release_url}";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest `Retry-After` delay a notification waits for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Discord rejects messages with more characters than this
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+/// Payload flavour of a notification endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifierKind {
+    /// Generic JSON webhook receiving every field of the notification
+    Webhook,
+    /// Discord webhook (`content` and an embed)
+    Discord,
+    /// Slack incoming webhook (`text`)
+    Slack,
+}
+
+impl NotifierKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Webhook => "webhook",
+            Self::Discord => "discord",
+            Self::Slack => "slack",
+        }
+    }
+}
+
+/// An endpoint notifications are posted to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationTarget {
+    pub kind: NotifierKind,
+    pub url: Url,
+}
+
+impl NotificationTarget {
+    /// Parses `kind=url` or a bare URL
+    ///
+    /// Bare Discord (`discord.com/api/webhooks/...`) and Slack
+    /// (`hooks.slack.com`) URLs are recognised; any other bare URL is a
+    /// generic webhook.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unknown kind or an invalid `http(s)` URL.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (kind, url) = match value.split_once('=') {
+            Some((kind, url)) if !kind.contains(':') => {
+                let kind = match kind.trim().to_ascii_lowercase().as_str() {
+                    "webhook" | "json" => NotifierKind::Webhook,
+                    "discord" => NotifierKind::Discord,
+                    "slack" => NotifierKind::Slack,
+                    other => bail!("Unknown notification kind: {:?}", other),
+                };
+                (Some(kind), url)
+            }
+            _ => (None, value),
+        };
+
+        let url =
+            Url::parse(url.trim()).with_context(|| format!("Invalid notification URL: {}", url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("Notification URL must use http or https: {}", url);
+        }
+
+        let kind = kind.unwrap_or_else(|| match url.host_str() {
+            Some("discord.com" | "discordapp.com") if url.path().starts_with("/api/webhooks/") => {
+                NotifierKind::Discord
+            }
+            Some("hooks.slack.com") => NotifierKind::Slack,
+            _ => NotifierKind::Webhook,
+        });
+        Ok(Self { kind, url })
+    }
+}
+
+impl fmt::Display for NotificationTarget {
+    /// Shows the kind and host only; webhook paths carry secrets
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.kind.as_str(),
+            self.url.host_str().unwrap_or("unknown host")
+        )
+    }
+}
+
+/// Facts about a new build that notifications are rendered from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BuildNotification {
+    pub repo: String,
+    pub build: String,
+    pub version: String,
+    pub previous_version: Option<String>,
+    pub checksum: String,
+    /// Web page of the GitHub release, empty if there is none yet
+    pub release_url: String,
+    /// When the CDN promoted the build, formatted as UTC
+    pub promote_time: String,
+    /// Number of files added, removed or modified since the previous build
+    pub changed_files: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    pub rollback: bool,
+    pub prerelease: bool,
+}
+
+impl BuildNotification {
+    /// Counts the file changes between the previous and the new manifest
+    ///
+    /// Without a previous manifest every file counts as added.
+    pub fn with_changes(mut self, previous: Option<&Manifest>, current: &Manifest) -> Self {
+        let snapshot = |manifest: &Manifest| {
+            BuildSnapshot::from_manifest(manifest.version.clone(), manifest.clone())
+        };
+        let from = previous.map(snapshot).unwrap_or_else(|| BuildSnapshot {
+            label: "none".to_string(),
+            version: String::new(),
+            files: Vec::new(),
+            pieces: None,
+        });
+        let diff = BuildDiff::compare(&from, &snapshot(current));
+
+        self.added = diff.added.len();
+        self.removed = diff.removed.len();
+        self.modified = diff.modified.len();
+        self.changed_files = self.added + self.removed + self.modified;
+        self
+    }
+
+    /// Renders `template`, e.g. `New build {version}: {release_url}`
+    ///
+    /// Placeholders are `repo`, `build`, `version`, `previous_version`
+    /// (`none` for a first release), `checksum`, `release_url`,
+    /// `promote_time`, `changed_files`, `added`, `removed` and `modified`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for unknown or unclosed placeholders.
+    pub fn render(&self, template: &str) -> Result<String> {
+        fill_placeholders(template, |name| {
+            Ok(match name {
+                "repo" => self.repo.clone(),
+                "build" => self.build.clone(),
+                "version" => self.version.clone(),
+                "previous_version" => self
+                    .previous_version
+                    .clone()
+                    .unwrap_or_else(|| "none".to_string()),
+                "checksum" => self.checksum.clone(),
+                "release_url" => self.release_url.clone(),
+                "promote_time" => self.promote_time.clone(),
+                "changed_files" => self.changed_files.to_string(),
+                "added" => self.added.to_string(),
+                "removed" => self.removed.to_string(),
+                "modified" => self.modified.to_string(),
+                _ => bail!("Unknown placeholder {{{}}} in notification template", name),
+            })
+        })
+    }
+
+    /// Builds the request body for an endpoint of `kind`
+    pub fn payload(&self, kind: NotifierKind, message: &str) -> Value {
+        match kind {
+            NotifierKind::Webhook => json!({
+                "event": "new_build",
+                "message": message,
+                "build": self,
+            }),
+            NotifierKind::Discord => json!({
+                "content": truncate(message, DISCORD_MAX_CONTENT),
+                "embeds": [{
+                    "title": format!("{} {} {}", self.repo, self.build, self.version),
+                    "url": (!self.release_url.is_empty()).then_some(&self.release_url),
+                    "fields": [
+                        {
+                            "name": "Previous version",
+                            "value": self.previous_version.as_deref().unwrap_or("none"),
+                            "inline": true
+                        },
+                        {
+                            "name": "Promoted",
+                            "value": self.promote_time,
+                            "inline": true
+                        },
+                        {
+                            "name": "Changed files",
+                            "value": self.changed_files.to_string(),
+                            "inline": true
+                        },
+                        { "name": "SHA-256", "value": self.checksum }
+                    ]
+                }]
+            }),
+            NotifierKind::Slack => json!({ "text": message }),
+        }
+    }
+}
+
+/// Posts build notifications to webhooks and chat services
+///
+/// Every target is tried independently; failed requests are retried with
+/// exponential backoff, honouring `Retry-After` on `429` and `503`.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    client: Client,
+    targets: Vec<NotificationTarget>,
+    template: String,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Notifier {
+    /// Creates a notifier posting to `targets`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the template has unknown placeholders, so a typo
+    /// surfaces before a build is processed, or if the HTTP client cannot be
+    /// configured.
+    pub fn new(targets: Vec<NotificationTarget>, template: &str) -> Result<Self> {
+        BuildNotification::default()
+            .render(template)
+            .context("Invalid notification template")?;
+        let client = Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .user_agent(DEFAULT_USER_AGENT)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self {
+            client,
+            targets,
+            template: template.to_string(),
+            retries: 3,
+            retry_delay: Duration::from_secs(2),
+        })
+    }
+
+    /// Retries a failed request up to `retries` times, waiting `delay`
+    /// before the first retry and doubling it for each further one
+    pub fn with_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Sends `notification` to every target
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming every target that still failed after all
+    /// retries; the other targets are notified regardless.
+    pub async fn notify(&self, notification: &BuildNotification) -> Result<()> {
+        let message = notification.render(&self.template)?;
+        let mut failures = Vec::new();
+
+        for target in &self.targets {
+            let payload = notification.payload(target.kind, &message);
+            match self.send(target, &payload).await {
+                Ok(()) => log::info!("Sent notification to {}", target),
+                Err(e) => {
+                    log::warn!("Failed to notify {}: {:#}", target, e);
+                    failures.push(format!("{}: {:#}", target, e));
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            bail!("Failed to send notifications:\n{}", failures.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Posts `payload` to `target`, retrying server errors and timeouts
+    async fn send(&self, target: &NotificationTarget, payload: &Value) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) = match self
+                .client
+                .post(target.url.clone())
+                .json(payload)
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                        bail!("HTTP error {}", status);
+                    }
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| parse_retry_after(value, SystemTime::now()));
+                    (anyhow::anyhow!("HTTP error {}", status), retry_after)
+                }
+                // The URL is the secret for Discord and Slack webhooks
+                Err(e) => (
+                    anyhow::Error::new(e.without_url()).context("Request failed"),
+                    None,
+                ),
+            };
+
+            if attempt >= self.retries {
+                return Err(error)
+                    .with_context(|| format!("Gave up after {} attempts", attempt + 1));
+            }
+            let delay = retry_after
+                .map(|delay| delay.min(MAX_RETRY_AFTER))
+                .unwrap_or(self.retry_delay * 2u32.saturating_pow(attempt));
+            log::warn!(
+                "Notification to {} failed ({:#}), retrying in {}s",
+                target,
+                error,
+                delay.as_secs_f32()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Shortens `text` to at most `max` characters, marking the cut with `…`
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestFile;
+    use crate::test_support::MockWebhook;
+
+    fn notification() -> BuildNotification {
+        BuildNotification {
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: "226.1".to_string(),
+            previous_version: Some("225.1".to_string()),
+            checksum: "abc123".to_string(),
+            release_url: "https://github.com/o/r/releases/tag/226.1".to_string(),
+            changed_files: 2,
+            added: 1,
+            removed: 0,
+            modified: 1,
+            ..Default::default()
+        }
+    }
+
+    fn manifest(version: &str, files: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            schema_version: 1,
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: version.to_string(),
+            files: files
+                .iter()
+                .map(|(name, sha256)| ManifestFile {
+                    name: name.to_string(),
+                    size: 1,
+                    sha256: sha256.to_string(),
+                    file_version: None,
+                    signature: None,
+                    signature_error: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        let target = NotificationTarget::parse("https://example.com/hook").unwrap();
+        assert_eq!(target.kind, NotifierKind::Webhook);
+
+        let target =
+            NotificationTarget::parse("https://discord.com/api/webhooks/1/secret").unwrap();
+        assert_eq!(target.kind, NotifierKind::Discord);
+        assert_eq!(target.to_string(), "discord (discord.com)");
+
+        let target = NotificationTarget::parse("https://hooks.slack.com/services/T/B/x").unwrap();
+        assert_eq!(target.kind, NotifierKind::Slack);
+
+        let target = NotificationTarget::parse("slack=http://127.0.0.1:9000/x?a=b").unwrap();
+        assert_eq!(target.kind, NotifierKind::Slack);
+        assert_eq!(target.url.as_str(), "http://127.0.0.1:9000/x?a=b");
+
+        assert!(NotificationTarget::parse("teams=https://example.com").is_err());
+        assert!(NotificationTarget::parse("ftp://example.com").is_err());
+        assert!(NotificationTarget::parse("not a url").is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        assert_eq!(
+            notification().render(DEFAULT_TEMPLATE).unwrap(),
+            "New osrs-win production build 226.1 (previous: 225.1): 2 files changed, SHA-256 abc123\nhttps://github.com/o/r/releases/tag/226.1"
+        );
+        let first = BuildNotification {
+            previous_version: None,
+            ..notification()
+        };
+        assert_eq!(
+            first.render("{version} after {previous_version}").unwrap(),
+            "226.1 after none"
+        );
+        assert!(notification().render("{nope}").is_err());
+        assert!(Notifier::new(Vec::new(), "{version").is_err());
+    }
+
+    #[test]
+    fn test_count_changes() {
+        let previous = manifest("225.1", &[("a.dll", "1"), ("b.dll", "2"), ("c.dll", "3")]);
+        let current = manifest("226.1", &[("a.dll", "1"), ("b.dll", "9"), ("d.dll", "4")]);
+
+        let counted = BuildNotification::default().with_changes(Some(&previous), &current);
+        assert_eq!(
+            (
+                counted.added,
+                counted.removed,
+                counted.modified,
+                counted.changed_files
+            ),
+            (1, 1, 1, 3)
+        );
+
+        let first = BuildNotification::default().with_changes(None, &current);
+        assert_eq!((first.added, first.changed_files), (3, 3));
+    }
+
+    #[test]
+    fn test_payloads() {
+        let notification = notification();
+        let webhook = notification.payload(NotifierKind::Webhook, "hi");
+        assert_eq!(webhook["event"], "new_build");
+        assert_eq!(webhook["build"]["version"], "226.1");
+        assert_eq!(webhook["build"]["changed_files"], 2);
+
+        let discord = notification.payload(NotifierKind::Discord, &"x".repeat(3000));
+        assert_eq!(
+            discord["content"].as_str().unwrap().chars().count(),
+            DISCORD_MAX_CONTENT
+        );
+        assert_eq!(discord["embeds"][0]["fields"][0]["value"], "225.1");
+
+        let slack = notification.payload(NotifierKind::Slack, "hi");
+        assert_eq!(slack, json!({ "text": "hi" }));
+    }
+
+    #[tokio::test]
+    async fn test_notify_posts_to_every_target() {
+        let server = MockWebhook::start(Vec::new()).await;
+        let targets = vec![
+            NotificationTarget::parse(&format!("{}hook", server.url())).unwrap(),
+            NotificationTarget::parse(&format!("discord={}discord", server.url())).unwrap(),
+            NotificationTarget::parse(&format!("slack={}slack", server.url())).unwrap(),
+        ];
+        let notifier = Notifier::new(targets, "{version} is out").unwrap();
+        notifier.notify(&notification()).await.unwrap();
+
+        let requests = server.requests();
+        let paths: Vec<&str> = requests.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["/hook", "/discord", "/slack"]);
+        assert_eq!(requests[0].1["message"], "226.1 is out");
+        assert_eq!(requests[1].1["content"], "226.1 is out");
+        assert_eq!(requests[2].1["text"], "226.1 is out");
+    }
+
+    #[tokio::test]
+    async fn test_notify_retries_server_errors() {
+        let server = MockWebhook::start(vec![500, 503]).await;
+        let target = NotificationTarget::parse(&format!("{}hook", server.url())).unwrap();
+        let notifier = Notifier::new(vec![target], DEFAULT_TEMPLATE)
+            .unwrap()
+            .with_retries(2, Duration::from_millis(10));
+
+        notifier.notify(&notification()).await.unwrap();
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_notify_reports_failures_without_stopping() {
+        let failing = MockWebhook::start(vec![500; 5]).await;
+        let rejecting = MockWebhook::start(vec![404]).await;
+        let working = MockWebhook::start(Vec::new()).await;
+        let targets = [&failing, &rejecting, &working]
+            .iter()
+            .map(|server| NotificationTarget::parse(&server.url()).unwrap())
+            .collect();
+        let notifier = Notifier::new(targets, DEFAULT_TEMPLATE)
+            .unwrap()
+            .with_retries(1, Duration::from_millis(10));
+
+        let error = notifier.notify(&notification()).await.unwrap_err();
+        assert!(error.to_string().contains("Gave up after 2 attempts"));
+        assert!(error.to_string().contains("404"));
+        assert_eq!(failing.requests().len(), 2);
+        assert_eq!(rejecting.requests().len(), 1);
+        assert_eq!(working.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_errors_do_not_leak_the_url() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "discord=http://{}/api/webhooks/1/secret-token",
+            listener.local_addr().unwrap()
+        );
+        drop(listener);
+        let notifier = Notifier::new(vec![NotificationTarget::parse(&url).unwrap()], "{version}")
+            .unwrap()
+            .with_retries(0, Duration::from_millis(10));
+
+        let error = notifier.notify(&notification()).await.unwrap_err();
+        assert!(error.to_string().contains("Request failed"));
+        assert!(!format!("{:?}", error).contains("secret-token"));
+    }
+}
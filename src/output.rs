@@ -0,0 +1,254 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::actions::{set_github_actions_output, write_step_summary, ActionOutput};
+use crate::manifest::Manifest;
+
+/// Default file written by the dotenv and env-file sinks
+pub const DEFAULT_ENV_FILE: &str = "release-updater.env";
+
+/// Default file written by the JSON sink
+pub const DEFAULT_JSON_FILE: &str = "release-updater.json";
+
+/// CI system the run outputs are written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Detect the CI system from the environment
+    Auto,
+    /// `$GITHUB_OUTPUT` and `$GITHUB_STEP_SUMMARY`
+    Github,
+    /// GitLab CI `artifacts:reports:dotenv` file
+    Gitlab,
+    /// Plain `KEY=value` file, e.g. for Jenkins `readProperties`
+    EnvFile,
+    /// JSON object with the output fields
+    Json,
+}
+
+/// Destination for the outputs of a run
+pub trait OutputSink {
+    /// Writes the release decision and build details
+    fn write(&self, output: &ActionOutput) -> Result<()>;
+
+    /// Writes a human-readable summary, if the CI system supports one
+    fn write_summary(&self, _output: &ActionOutput, _manifest: &Manifest) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes GitHub Actions step outputs and the job summary
+pub struct GitHubActionsSink;
+
+impl OutputSink for GitHubActionsSink {
+    fn write(&self, output: &ActionOutput) -> Result<()> {
+        set_github_actions_output(output)
+    }
+
+    fn write_summary(&self, output: &ActionOutput, manifest: &Manifest) -> Result<()> {
+        write_step_summary(output, manifest)
+    }
+}
+
+/// Writes a GitLab dotenv report
+///
+/// GitLab does not support multiline dotenv values, so those outputs are
+/// left out.
+pub struct GitLabDotenvSink {
+    pub path: PathBuf,
+}
+
+impl OutputSink for GitLabDotenvSink {
+    fn write(&self, output: &ActionOutput) -> Result<()> {
+        let mut content = String::new();
+        for (name, value) in output.outputs() {
+            if value.contains(['\n', '\r']) {
+                log::debug!("Skipping multiline output {} in dotenv report", name);
+                continue;
+            }
+            content.push_str(&format!("{}={}\n", env_name(name), value));
+        }
+
+        write_file(&self.path, &content)
+    }
+}
+
+/// Writes a plain `KEY=value` file
+///
+/// Multiline values are written with `\n` escapes so every output stays on
+/// one line.
+pub struct EnvFileSink {
+    pub path: PathBuf,
+}
+
+impl OutputSink for EnvFileSink {
+    fn write(&self, output: &ActionOutput) -> Result<()> {
+        let content: String = output
+            .outputs()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}\n", env_name(name), escape_env_value(&value)))
+            .collect();
+
+        write_file(&self.path, &content)
+    }
+}
+
+/// Writes the outputs as a JSON object
+pub struct JsonSink {
+    pub path: PathBuf,
+}
+
+impl OutputSink for JsonSink {
+    fn write(&self, output: &ActionOutput) -> Result<()> {
+        let mut value = serde_json::to_value(output).context("Failed to serialize outputs")?;
+        if output.update_available {
+            value["release_notes"] = output.release_notes().into();
+        }
+        let json = serde_json::to_string_pretty(&value).context("Failed to serialize outputs")?;
+
+        write_file(&self.path, &json)
+    }
+}
+
+/// Creates the sink for `format`, resolving [`OutputFormat::Auto`] from the
+/// environment
+///
+/// # Arguments
+///
+/// * `format` - Requested output format
+/// * `output_file` - File for the file-based sinks; defaults to
+///   [`DEFAULT_ENV_FILE`] or [`DEFAULT_JSON_FILE`]
+pub fn create_sink(format: OutputFormat, output_file: Option<&Path>) -> Box<dyn OutputSink> {
+    let format = match format {
+        OutputFormat::Auto => detect_format(|name| env::var(name).ok()),
+        format => format,
+    };
+    log::debug!("Writing outputs as {:?}", format);
+
+    let path = |default: &str| {
+        output_file
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(default))
+    };
+
+    match format {
+        OutputFormat::Auto | OutputFormat::Github => Box::new(GitHubActionsSink),
+        OutputFormat::Gitlab => Box::new(GitLabDotenvSink {
+            path: path(DEFAULT_ENV_FILE),
+        }),
+        OutputFormat::EnvFile => Box::new(EnvFileSink {
+            path: path(DEFAULT_ENV_FILE),
+        }),
+        OutputFormat::Json => Box::new(JsonSink {
+            path: path(DEFAULT_JSON_FILE),
+        }),
+    }
+}
+
+/// Picks the output format for the CI system described by `var`
+///
+/// Falls back to GitHub Actions, whose sink only writes when
+/// `GITHUB_OUTPUT` is set.
+fn detect_format(var: impl Fn(&str) -> Option<String>) -> OutputFormat {
+    if var("GITHUB_ACTIONS").as_deref() == Some("true") {
+        OutputFormat::Github
+    } else if var("GITLAB_CI").as_deref() == Some("true") {
+        OutputFormat::Gitlab
+    } else if var("JENKINS_URL").is_some() {
+        OutputFormat::EnvFile
+    } else {
+        OutputFormat::Github
+    }
+}
+
+/// Converts an output name to an environment variable name
+fn env_name(name: &str) -> String {
+    name.to_ascii_uppercase()
+}
+
+fn escape_env_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write outputs: {}", path.display()))?;
+    log::info!("Wrote outputs to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn output() -> ActionOutput {
+        ActionOutput::update_available(
+            "1.0.0".to_string(),
+            "abc123".to_string(),
+            Path::new("/test/artifact.zip"),
+        )
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let detect = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            detect_format(|name| vars.get(name).cloned())
+        };
+
+        assert_eq!(detect(&[("GITHUB_ACTIONS", "true")]), OutputFormat::Github);
+        assert_eq!(detect(&[("GITLAB_CI", "true")]), OutputFormat::Gitlab);
+        assert_eq!(
+            detect(&[("JENKINS_URL", "http://ci/")]),
+            OutputFormat::EnvFile
+        );
+        assert_eq!(detect(&[]), OutputFormat::Github);
+    }
+
+    #[test]
+    fn test_gitlab_sink_skips_multiline_values() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("build.env");
+        GitLabDotenvSink { path: path.clone() }
+            .write(&output())
+            .unwrap();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert!(content.contains("UPDATE_AVAILABLE=true\n"));
+        assert!(content.contains("VERSION=1.0.0\n"));
+        assert!(!content.contains("RELEASE_NOTES"));
+    }
+
+    #[test]
+    fn test_env_file_sink_escapes_multiline_values() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("out.env");
+        EnvFileSink { path: path.clone() }.write(&output()).unwrap();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        assert!(content.contains("CHECKSUM=abc123\n"));
+        assert!(content.contains("RELEASE_NOTES=Automated release created"));
+        assert_eq!(content.lines().count(), output().outputs().len());
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("out.json");
+        JsonSink { path: path.clone() }.write(&output()).unwrap();
+
+        let value: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(value["update_available"], true);
+        assert_eq!(value["version"], "1.0.0");
+        assert!(value["release_notes"].as_str().unwrap().contains("abc123"));
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{bail, Context, Result};
+use pelite::image::GUID;
+use pelite::pe64::debug::CodeView;
+use pelite::pe64::imports::Import;
+use pelite::{FileMap, PeFile};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// Structural metadata of a PE executable, used to diff binaries between builds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeInfo {
+    pub machine: String,
+    pub pe64: bool,
+    /// Link timestamp from the COFF file header (seconds since the Unix epoch)
+    pub link_timestamp: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_view: Option<CodeViewInfo>,
+    pub sections: Vec<SectionInfo>,
+    pub imports: Vec<ImportedDll>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exports: Option<ExportInfo>,
+}
+
+/// CodeView debug record pointing at the PDB the binary was built with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeViewInfo {
+    /// `RSDS` (PDB 7.0) or `NB10` (PDB 2.0)
+    pub format: String,
+    pub pdb_path: String,
+    /// PDB GUID, only present for `RSDS` records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    pub age: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionInfo {
+    pub name: String,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    pub raw_size: u32,
+    pub characteristics: u32,
+    /// SHA-256 of the section's raw data in the file; that of no data for
+    /// uninitialized sections
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedDll {
+    pub dll: String,
+    /// Imported symbol names, or `#<ordinal>` for imports by ordinal
+    pub functions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportInfo {
+    pub dll_name: String,
+    pub functions: Vec<ExportedFunction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedFunction {
+    pub ordinal: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Target of a forwarded export, e.g. `NTDLL.RtlAllocateHeap`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward: Option<String>,
+}
+
+/// Extracts header, section, import and export metadata from a PE file
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the PE file (.exe or .dll)
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not a valid PE file.
+/// Missing optional directories (debug, imports, exports) are not errors.
+pub fn extract_pe_info(file_path: &Path) -> Result<PeInfo> {
+    let map = FileMap::open(file_path)
+        .with_context(|| format!("Failed to open PE file: {}", file_path.display()))?;
+    let pe = PeFile::from_bytes(&map)
+        .with_context(|| format!("Failed to parse PE file: {}", file_path.display()))?;
+
+    let file_header = pe.file_header();
+
+    Ok(PeInfo {
+        machine: machine_name(file_header.Machine),
+        pe64: matches!(pe, PeFile::T64(_)),
+        link_timestamp: file_header.TimeDateStamp,
+        code_view: code_view_info(pe),
+        sections: section_info(pe)?,
+        imports: import_info(pe)?,
+        exports: export_info(pe)?,
+    })
+}
+
+/// Writes the PE metadata of every executable in `executables` as one JSON
+/// file per executable below `output_dir`
+///
+/// # Arguments
+///
+/// * `root` - Directory the executables are relative to
+/// * `executables` - Executables to describe
+/// * `output_dir` - Directory receiving `<relative path>.json` files
+pub fn write_pe_info(
+    root: &Path,
+    executables: &[std::path::PathBuf],
+    output_dir: &Path,
+) -> Result<()> {
+    for path in executables {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let info = match extract_pe_info(path) {
+            Ok(info) => info,
+            Err(e) => {
+                log::warn!("Skipping PE info for {}: {:?}", relative.display(), e);
+                continue;
+            }
+        };
+
+        let mut json_name = relative.as_os_str().to_owned();
+        json_name.push(".json");
+        let json_path = output_dir.join(json_name);
+        if let Some(parent) = json_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let json = serde_json::to_vec_pretty(&info).context("Failed to serialize PE info")?;
+        fs::write(&json_path, json)
+            .with_context(|| format!("Failed to write PE info: {}", json_path.display()))?;
+        log::info!("Wrote PE info for {}", relative.display());
+    }
+
+    Ok(())
+}
+
+/// Returns a readable name for an `IMAGE_FILE_MACHINE_*` value
+fn machine_name(machine: u16) -> String {
+    match machine {
+        0x014c => "i386".to_string(),
+        0x8664 => "amd64".to_string(),
+        0xaa64 => "arm64".to_string(),
+        0x01c4 => "armnt".to_string(),
+        other => format!("{:#06x}", other),
+    }
+}
+
+fn format_guid(guid: &GUID) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        guid.Data1,
+        guid.Data2,
+        guid.Data3,
+        hex::encode_upper(&guid.Data4[..2]),
+        hex::encode_upper(&guid.Data4[2..])
+    )
+}
+
+fn code_view_info(pe: PeFile) -> Option<CodeViewInfo> {
+    let debug = pe.debug().ok()?;
+
+    debug.iter().find_map(|dir| {
+        let entry = dir.entry().ok()?;
+        let code_view = entry.as_code_view()?;
+        let guid = match code_view {
+            CodeView::Cv70 { image, .. } => Some(format_guid(&image.Signature)),
+            CodeView::Cv20 { .. } => None,
+        };
+
+        Some(CodeViewInfo {
+            format: code_view.format().to_string(),
+            pdb_path: code_view.pdb_file_name().to_string(),
+            guid,
+            age: code_view.age(),
+        })
+    })
+}
+
+fn section_info(pe: PeFile) -> Result<Vec<SectionInfo>> {
+    pe.section_headers()
+        .iter()
+        .map(|section| {
+            let name = match section.name() {
+                Ok(name) => name.to_string(),
+                Err(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            };
+            let bytes = match pe.get_section_bytes(section) {
+                Ok(bytes) => bytes,
+                // Uninitialized data such as `.bss` has no bytes in the file
+                Err(pelite::Error::Null) => &[],
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read section {}", name))
+                }
+            };
+
+            Ok(SectionInfo {
+                virtual_address: section.VirtualAddress,
+                virtual_size: section.VirtualSize,
+                raw_size: section.SizeOfRawData,
+                characteristics: section.Characteristics,
+                sha256: hex::encode(Sha256::digest(bytes)),
+                name,
+            })
+        })
+        .collect()
+}
+
+fn import_info(pe: PeFile) -> Result<Vec<ImportedDll>> {
+    let imports = match pe.imports() {
+        Ok(imports) => imports,
+        Err(pelite::Error::Null) => return Ok(Vec::new()),
+        Err(e) => bail!("Failed to read import directory: {}", e),
+    };
+
+    let mut dlls = Vec::new();
+    for desc in imports {
+        let dll = desc
+            .dll_name()
+            .context("Invalid import DLL name")?
+            .to_string();
+        let functions = desc
+            .int()
+            .with_context(|| format!("Invalid import table for {}", dll))?
+            .map(|import| match import {
+                Ok(Import::ByName { name, .. }) => name.to_string(),
+                Ok(Import::ByOrdinal { ord }) => format!("#{}", ord),
+                Err(e) => format!("<invalid: {}>", e),
+            })
+            .collect();
+
+        dlls.push(ImportedDll { dll, functions });
+    }
+
+    Ok(dlls)
+}
+
+fn export_info(pe: PeFile) -> Result<Option<ExportInfo>> {
+    let exports = match pe.exports() {
+        Ok(exports) => exports,
+        Err(pelite::Error::Null) => return Ok(None),
+        Err(e) => bail!("Failed to read export directory: {}", e),
+    };
+
+    let by = exports.by().context("Invalid export directory")?;
+    let base = by.ordinal_base();
+
+    let functions = by
+        .iter()
+        .enumerate()
+        .filter_map(|(index, export)| {
+            let export = export.ok()?;
+            if export.symbol() == Some(0) {
+                return None;
+            }
+
+            let name = match by.name_lookup(index) {
+                Ok(Import::ByName { name, .. }) => Some(name.to_string()),
+                _ => None,
+            };
+
+            Some(ExportedFunction {
+                ordinal: base.wrapping_add(index as u16),
+                name,
+                forward: export.forward().map(|forward| forward.to_string()),
+            })
+        })
+        .collect();
+
+    Ok(Some(ExportInfo {
+        dll_name: exports
+            .dll_name()
+            .map(|name| name.to_string())
+            .unwrap_or_default(),
+        functions,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::pe_image;
+
+    #[test]
+    fn test_machine_name() {
+        assert_eq!(machine_name(0x8664), "amd64");
+        assert_eq!(machine_name(0x014c), "i386");
+        assert_eq!(machine_name(0x1234), "0x1234");
+    }
+
+    #[test]
+    fn test_format_guid() {
+        let guid = GUID {
+            Data1: 0x12345678,
+            Data2: 0x9abc,
+            Data3: 0xdef0,
+            Data4: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        assert_eq!(format_guid(&guid), "12345678-9ABC-DEF0-0102-030405060708");
+    }
+
+    #[test]
+    fn test_extract_pe_info_with_uninitialized_section() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("client.exe");
+        let image = pe_image(&[(".text", Some(b"\xc3".as_slice())), (".bss", None)]);
+        fs::write(&path, image).unwrap();
+
+        let info = extract_pe_info(&path).unwrap();
+        assert_eq!(info.machine, "amd64");
+        assert!(info.pe64);
+        assert!(info.imports.is_empty());
+        assert!(info.exports.is_none());
+
+        let names: Vec<_> = info.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![".text", ".bss"]);
+        let bss = &info.sections[1];
+        assert_eq!(bss.raw_size, 0);
+        assert_eq!(bss.virtual_size, 0x100);
+        assert_eq!(bss.sha256, hex::encode(Sha256::digest(b"")));
+
+        let output = temp_dir.path().join("pe-info");
+        write_pe_info(temp_dir.path(), &[path], &output).unwrap();
+        assert!(output.join("client.exe.json").is_file());
+    }
+
+    #[test]
+    fn test_extract_pe_info_rejects_non_pe() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("fake.dll");
+        fs::write(&path, b"not a pe file").unwrap();
+        assert!(extract_pe_info(&path).is_err());
+    }
+}
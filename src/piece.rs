@@ -0,0 +1,836 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::config::Config;
+
+/// Number of bytes preceding the compressed payload of a `.solidpiece`
+pub const PIECE_HEADER_LEN: usize = 6;
+
+/// Payload bytes needed to recognise every compression magic
+const SNIFF_LEN: usize = 4;
+
+/// Catalog `type` values this updater has been used with
+const KNOWN_CATALOG_TYPES: &[&str] = &["remote"];
+
+/// Catalog `flags` this updater has been used with; none observed so far
+const KNOWN_CATALOG_FLAGS: &[&str] = &[];
+
+/// The 6-byte header at the start of every `.solidpiece`
+///
+/// The meaning of these bytes has not been confirmed against recorded CDN
+/// pieces, so they are kept verbatim for `inspect-piece` and not
+/// interpreted. The payload compression comes from the catalog and is
+/// checked against the payload's magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceHeader {
+    pub bytes: [u8; PIECE_HEADER_LEN],
+}
+
+impl PieceHeader {
+    /// Reads the header at the start of `body`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `body` is shorter than the header.
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let Some(bytes) = body.first_chunk() else {
+            bail!(
+                "Invalid piece data: {} bytes is shorter than the {}-byte header",
+                body.len(),
+                PIECE_HEADER_LEN
+            );
+        };
+        Ok(Self { bytes: *bytes })
+    }
+}
+
+/// Compression of the payload of a piece, from the catalog's `pieceFormat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceCompression {
+    Gzip,
+    Zlib,
+    Zstd,
+    Raw,
+}
+
+impl PieceCompression {
+    /// Parses the catalog's `pieceFormat`
+    ///
+    /// `solidpiece(s)` is the format the CDN has always served, which is
+    /// gzip.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the value if the format is not supported.
+    pub fn from_catalog(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "gzip" | "solidpiece" | "solidpieces" => Ok(Self::Gzip),
+            "zlib" | "deflate" => Ok(Self::Zlib),
+            "zstd" | "zstandard" => Ok(Self::Zstd),
+            "raw" | "none" | "uncompressed" => Ok(Self::Raw),
+            _ => bail!("Unsupported piece format: {:?}", value),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zlib => "zlib",
+            Self::Zstd => "zstd",
+            Self::Raw => "raw",
+        }
+    }
+
+    /// Identifies the compression of a payload from its magic bytes
+    ///
+    /// Returns `None` if the payload does not start with a known magic,
+    /// which is expected for raw payloads.
+    pub fn sniff(payload: &[u8]) -> Option<Self> {
+        match payload {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+            {
+                Some(Self::Zlib)
+            }
+            _ => None,
+        }
+    }
+
+    /// Decompresses a piece payload (without its header)
+    pub fn decompress(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            Self::Gzip => {
+                GzDecoder::new(payload).read_to_end(&mut decompressed)?;
+            }
+            Self::Zlib => {
+                ZlibDecoder::new(payload).read_to_end(&mut decompressed)?;
+            }
+            Self::Zstd => {
+                decompressed = zstd::decode_all(payload)?;
+            }
+            Self::Raw => decompressed.extend_from_slice(payload),
+        }
+        Ok(decompressed)
+    }
+}
+
+impl fmt::Display for PieceCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Hash algorithm of piece digests, from the metafile's `pieces.algorithm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha1,
+}
+
+impl DigestAlgorithm {
+    /// Parses an algorithm name such as `SHA-256`, `sha256` or `SHA1`
+    ///
+    /// An empty value defaults to SHA-256.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the value if the algorithm is not supported.
+    pub fn from_name(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "" | "sha256" => Ok(Self::Sha256),
+            "sha1" => Ok(Self::Sha1),
+            _ => bail!("Unsupported digest algorithm: {:?}", value),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha1 => "sha1",
+        }
+    }
+
+    /// Returns the digest length in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha1 => 20,
+        }
+    }
+
+    /// Hashes `data` and returns the lowercase hex digest
+    pub fn hex_digest(&self, data: &[u8]) -> String {
+        match self {
+            Self::Sha256 => hex::encode(Sha256::digest(data)),
+            Self::Sha1 => hex::encode(Sha1::digest(data)),
+        }
+    }
+
+    /// Returns a hasher for data that arrives in parts
+    pub fn hasher(&self) -> PieceHasher {
+        match self {
+            Self::Sha256 => PieceHasher::Sha256(Sha256::new()),
+            Self::Sha1 => PieceHasher::Sha1(Sha1::new()),
+        }
+    }
+}
+
+/// Incremental form of [`DigestAlgorithm::hex_digest`]
+#[derive(Debug, Clone)]
+pub enum PieceHasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+}
+
+impl PieceHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha1(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the lowercase hex digest of all data passed to `update`
+    pub fn finish(self) -> String {
+        match self {
+            Self::Sha256(hasher) => hex::encode(hasher.finalize()),
+            Self::Sha1(hasher) => hex::encode(hasher.finalize()),
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the pieces of a build are encoded, as advertised by its catalog and
+/// metafile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceFormat {
+    pub compression: PieceCompression,
+    pub digest: DigestAlgorithm,
+}
+
+impl Default for PieceFormat {
+    fn default() -> Self {
+        Self {
+            compression: PieceCompression::Gzip,
+            digest: DigestAlgorithm::Sha256,
+        }
+    }
+}
+
+impl PieceFormat {
+    /// Determines the piece format from the loaded config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the piece format or either digest algorithm is
+    /// unsupported, so a CDN change fails before any piece is downloaded.
+    ///
+    /// # Notes
+    ///
+    /// An unknown catalog `type` or flag is logged as a warning by name,
+    /// since the piece digests still catch a format this updater cannot
+    /// read. A missing `pieceFormat` falls back to gzip with a warning.
+    /// Pieces are always downloaded in full, so `deltaFormat` is only
+    /// checked to be a known compression and a warning is logged otherwise.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let catalog = &config.catalog;
+        for value in unknown_catalog_values(&catalog.piece_type, &catalog.flags) {
+            log::warn!("Unknown catalog {}, downloading pieces as usual", value);
+        }
+
+        let compression = if catalog.piece_format.is_empty() {
+            log::warn!("Catalog does not name a piece format, assuming gzip");
+            PieceCompression::Gzip
+        } else {
+            PieceCompression::from_catalog(&catalog.piece_format)
+                .context("Catalog advertises an unsupported piece format")?
+        };
+        if !catalog.delta_format.is_empty()
+            && PieceCompression::from_catalog(&catalog.delta_format).is_err()
+        {
+            log::warn!(
+                "Ignoring unknown catalog delta format {:?}; pieces are downloaded in full",
+                catalog.delta_format
+            );
+        }
+
+        let digest = DigestAlgorithm::from_name(&config.metafile.pieces_algorithm)
+            .context("Metafile uses an unsupported piece digest algorithm")?;
+        DigestAlgorithm::from_name(&config.metafile.algorithm)
+            .context("Metafile uses an unsupported digest algorithm")?;
+
+        log::debug!(
+            "Piece format: {} (type {:?}, delta format {:?}), digest {}",
+            compression,
+            catalog.piece_type,
+            catalog.delta_format,
+            digest
+        );
+
+        Ok(Self {
+            compression,
+            digest,
+        })
+    }
+
+    /// Reads the header of a `.solidpiece` body and determines the
+    /// compression of its payload
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for a body shorter than the header, or a payload
+    /// whose magic does not match the advertised compression.
+    pub fn check(&self, body: &[u8]) -> Result<(PieceHeader, PieceCompression)> {
+        let header = PieceHeader::parse(body)?;
+        let compression = self.payload_compression(&body[PIECE_HEADER_LEN..])?;
+        Ok((header, compression))
+    }
+
+    /// Determines the compression of a payload from its first bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the magic does not match the advertised
+    /// compression.
+    fn payload_compression(&self, payload: &[u8]) -> Result<PieceCompression> {
+        let compression = match (PieceCompression::sniff(payload), self.compression) {
+            (_, PieceCompression::Raw) => PieceCompression::Raw,
+            (Some(detected), expected) if detected == expected => detected,
+            (Some(detected), expected) => bail!(
+                "Piece payload is {} but the catalog advertises {}",
+                detected,
+                expected
+            ),
+            (None, expected) => bail!(
+                "Piece payload does not start with a {} header (starts with {})",
+                expected,
+                hex::encode(&payload[..payload.len().min(SNIFF_LEN)])
+            ),
+        };
+        Ok(compression)
+    }
+
+    /// Decodes a `.solidpiece` body into the piece data
+    pub fn decode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let (_, compression) = self.check(body)?;
+        compression
+            .decompress(&body[PIECE_HEADER_LEN..])
+            .with_context(|| format!("Failed to decompress {} piece payload", compression))
+    }
+}
+
+/// Describes the catalog `type` and `flags` values not in
+/// [`KNOWN_CATALOG_TYPES`] and [`KNOWN_CATALOG_FLAGS`]
+///
+/// Flags may be separated by commas or whitespace.
+fn unknown_catalog_values(piece_type: &str, flags: &str) -> Vec<String> {
+    let mut unknown = Vec::new();
+    if !KNOWN_CATALOG_TYPES.contains(&piece_type) {
+        unknown.push(format!("type {:?}", piece_type));
+    }
+    for flag in flags
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|flag| !flag.is_empty())
+    {
+        if !KNOWN_CATALOG_FLAGS.contains(&flag) {
+            unknown.push(format!("flag {:?}", flag));
+        }
+    }
+    unknown
+}
+
+/// Streaming decoder of `.solidpiece` bodies
+///
+/// Body chunks are fed in as they arrive and the decompressed data comes
+/// out in pieces, so neither the compressed nor the decompressed piece is
+/// ever held in memory as a whole. The compression is checked once the
+/// header and the payload magic have arrived, and the digest once the body
+/// is complete.
+pub struct PieceDecoder {
+    format: PieceFormat,
+    /// Body bytes held back until the header and compression magic are known
+    pending: Vec<u8>,
+    decompressor: Option<Decompressor>,
+    hasher: PieceHasher,
+    decompressed_len: u64,
+}
+
+/// Digest and size of a piece decoded by a [`PieceDecoder`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedPiece {
+    pub digest: String,
+    pub size: u64,
+}
+
+/// Push-based decompressor writing into a buffer drained after every chunk
+enum Decompressor {
+    Gzip(write::GzDecoder<Vec<u8>>),
+    Zlib(write::ZlibDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+    Raw(Vec<u8>),
+}
+
+impl Decompressor {
+    fn new(compression: PieceCompression) -> Result<Self> {
+        Ok(match compression {
+            PieceCompression::Gzip => Self::Gzip(write::GzDecoder::new(Vec::new())),
+            PieceCompression::Zlib => Self::Zlib(write::ZlibDecoder::new(Vec::new())),
+            PieceCompression::Zstd => Self::Zstd(zstd::stream::write::Decoder::new(Vec::new())?),
+            PieceCompression::Raw => Self::Raw(Vec::new()),
+        })
+    }
+
+    /// Decompresses `data` and returns the output produced so far
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let output = match self {
+            Self::Gzip(decoder) => {
+                decoder.write_all(data)?;
+                decoder.get_mut()
+            }
+            Self::Zlib(decoder) => {
+                decoder.write_all(data)?;
+                decoder.get_mut()
+            }
+            Self::Zstd(decoder) => {
+                decoder.write_all(data)?;
+                decoder.get_mut()
+            }
+            Self::Raw(output) => {
+                output.extend_from_slice(data);
+                output
+            }
+        };
+        Ok(std::mem::take(output))
+    }
+
+    /// Flushes the end of the stream and returns the remaining output
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(decoder) => decoder.finish(),
+            Self::Zlib(decoder) => decoder.finish(),
+            Self::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            Self::Raw(output) => Ok(output),
+        }
+    }
+}
+
+impl PieceDecoder {
+    pub fn new(format: PieceFormat) -> Self {
+        Self {
+            format,
+            pending: Vec::new(),
+            decompressor: None,
+            hasher: format.digest.hasher(),
+            decompressed_len: 0,
+        }
+    }
+
+    /// Feeds the next chunk of the body and returns the data it decompressed
+    ///
+    /// The returned data may be empty, e.g. while the header is buffered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an unexpected compression or corrupt payload.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if self.decompressor.is_some() {
+            return self.decompress(chunk);
+        }
+
+        self.pending.extend_from_slice(chunk);
+        if self.pending.len() < PIECE_HEADER_LEN + SNIFF_LEN {
+            return Ok(Vec::new());
+        }
+        self.start()
+    }
+
+    /// Completes decoding and returns the remaining data with the digest and
+    /// size of the whole piece
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is shorter than the header or the
+    /// payload ends prematurely.
+    pub fn finish(mut self) -> Result<(Vec<u8>, DecodedPiece)> {
+        let mut data = if self.decompressor.is_none() {
+            if self.pending.len() < PIECE_HEADER_LEN {
+                PieceHeader::parse(&self.pending)?;
+            }
+            self.start()?
+        } else {
+            Vec::new()
+        };
+
+        if let Some(decompressor) = self.decompressor.take() {
+            let compression = self.format.compression;
+            let tail = decompressor
+                .finish()
+                .with_context(|| format!("Failed to decompress {} piece payload", compression))?;
+            self.hasher.update(&tail);
+            self.decompressed_len += tail.len() as u64;
+            data.extend_from_slice(&tail);
+        }
+
+        Ok((
+            data,
+            DecodedPiece {
+                digest: self.hasher.finish(),
+                size: self.decompressed_len,
+            },
+        ))
+    }
+
+    /// Skips the buffered header and decompresses the buffered payload
+    fn start(&mut self) -> Result<Vec<u8>> {
+        let pending = std::mem::take(&mut self.pending);
+        let payload = &pending[PIECE_HEADER_LEN..];
+        let compression = self.format.payload_compression(payload)?;
+
+        self.decompressor = Some(Decompressor::new(compression)?);
+        self.decompress(payload)
+    }
+
+    fn decompress(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let compression = self.format.compression;
+        let data = self
+            .decompressor
+            .as_mut()
+            .expect("decompressor is created before payload is decompressed")
+            .write(payload)
+            .with_context(|| format!("Failed to decompress {} piece payload", compression))?;
+        self.hasher.update(&data);
+        self.decompressed_len += data.len() as u64;
+        Ok(data)
+    }
+}
+
+/// Result of decoding a single `.solidpiece` file for `inspect-piece`
+#[derive(Debug, Clone)]
+pub struct PieceInspection {
+    pub size: usize,
+    pub header: Option<PieceHeader>,
+    pub detected: Option<PieceCompression>,
+    pub decompressed_len: Option<usize>,
+    pub digest: Option<String>,
+    /// Digest encoded in the file name
+    pub expected_digest: Option<String>,
+    pub problems: Vec<String>,
+}
+
+impl PieceInspection {
+    /// Returns true if the piece decoded and matched its file name digest
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for PieceInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        writeln!(f, "size:             {}", self.size)?;
+        match &self.header {
+            Some(header) => writeln!(f, "header:           {}", hex::encode(header.bytes))?,
+            None => writeln!(f, "header:           invalid")?,
+        }
+        writeln!(
+            f,
+            "compression:      {}",
+            show(self.detected.map(|c| c.to_string()))
+        )?;
+        writeln!(
+            f,
+            "decompressed:     {}",
+            show(self.decompressed_len.map(|len| len.to_string()))
+        )?;
+        writeln!(f, "digest:           {}", show(self.digest.clone()))?;
+        writeln!(
+            f,
+            "expected digest:  {}",
+            show(self.expected_digest.clone())
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "problem:          {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes and validates a `.solidpiece` file
+///
+/// # Arguments
+///
+/// * `path` - Piece file; a `<digest>.solidpiece` name is checked against
+///   the decoded data
+/// * `format` - Expected piece format
+///
+/// # Errors
+///
+/// Returns an error only if the file cannot be read; decoding problems are
+/// reported in [`PieceInspection::problems`].
+pub fn inspect_piece(path: &std::path::Path, format: PieceFormat) -> Result<PieceInspection> {
+    let body =
+        std::fs::read(path).with_context(|| format!("Failed to read piece: {}", path.display()))?;
+
+    let mut inspection = PieceInspection {
+        size: body.len(),
+        header: None,
+        detected: None,
+        decompressed_len: None,
+        digest: None,
+        expected_digest: path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".solidpiece"))
+            .map(str::to_string),
+        problems: Vec::new(),
+    };
+
+    match PieceHeader::parse(&body) {
+        Ok(header) => inspection.header = Some(header),
+        Err(e) => inspection.problems.push(e.to_string()),
+    }
+    if body.len() >= PIECE_HEADER_LEN {
+        inspection.detected = PieceCompression::sniff(&body[PIECE_HEADER_LEN..]);
+    }
+
+    match format.decode(&body) {
+        Ok(data) => {
+            inspection.decompressed_len = Some(data.len());
+            let digest = format.digest.hex_digest(&data);
+            if let Some(expected) = &inspection.expected_digest {
+                if !expected.eq_ignore_ascii_case(&digest) {
+                    inspection
+                        .problems
+                        .push(format!("{} digest does not match file name", format.digest));
+                }
+            }
+            inspection.digest = Some(digest);
+        }
+        Err(e) if inspection.header.is_some() => inspection.problems.push(format!("{:#}", e)),
+        Err(_) => {}
+    }
+
+    Ok(inspection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode_piece;
+
+    #[test]
+    fn test_piece_compression_from_catalog() {
+        assert_eq!(
+            PieceCompression::from_catalog("solidpieces").unwrap(),
+            PieceCompression::Gzip
+        );
+        assert_eq!(
+            PieceCompression::from_catalog("ZSTD").unwrap(),
+            PieceCompression::Zstd
+        );
+        let error = PieceCompression::from_catalog("brotli").unwrap_err();
+        assert!(error.to_string().contains("\"brotli\""));
+        assert!(PieceCompression::from_catalog("").is_err());
+    }
+
+    #[test]
+    fn test_piece_format_from_config() {
+        let mut config = Config::new("osrs-win", "production");
+        config.catalog.piece_type = "remote".to_string();
+        config.catalog.piece_format = "zstd".to_string();
+        config.catalog.delta_format = "gzip".to_string();
+        let format = PieceFormat::from_config(&config).unwrap();
+        assert_eq!(format.compression, PieceCompression::Zstd);
+
+        config.catalog.piece_format.clear();
+        config.catalog.delta_format = "bsdiff".to_string();
+        let format = PieceFormat::from_config(&config).unwrap();
+        assert_eq!(format.compression, PieceCompression::Gzip);
+
+        // Unknown types and flags are only warned about
+        config.catalog.piece_type = "local".to_string();
+        config.catalog.flags = "encrypted".to_string();
+        assert!(PieceFormat::from_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_catalog_values() {
+        assert!(unknown_catalog_values("remote", "").is_empty());
+        assert_eq!(
+            unknown_catalog_values("local", "encrypted, signed"),
+            vec!["type \"local\"", "flag \"encrypted\"", "flag \"signed\""]
+        );
+    }
+
+    #[test]
+    fn test_digest_algorithm_from_name() {
+        assert_eq!(
+            DigestAlgorithm::from_name("SHA-256").unwrap(),
+            DigestAlgorithm::Sha256
+        );
+        assert_eq!(
+            DigestAlgorithm::from_name("sha1").unwrap(),
+            DigestAlgorithm::Sha1
+        );
+        assert!(DigestAlgorithm::from_name("md5").is_err());
+        assert_eq!(DigestAlgorithm::Sha1.hex_digest(b"abc").len(), 40);
+    }
+
+    #[test]
+    fn test_decode_round_trip_for_every_compression() {
+        for compression in [
+            PieceCompression::Gzip,
+            PieceCompression::Zlib,
+            PieceCompression::Zstd,
+            PieceCompression::Raw,
+        ] {
+            let format = PieceFormat {
+                compression,
+                digest: DigestAlgorithm::Sha256,
+            };
+            let body = encode_piece(b"piece data", compression);
+            assert_eq!(format.decode(&body).unwrap(), b"piece data");
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_wrong_compression() {
+        let format = PieceFormat {
+            compression: PieceCompression::Zstd,
+            digest: DigestAlgorithm::Sha256,
+        };
+        let body = encode_piece(b"piece data", PieceCompression::Gzip);
+        assert!(format.decode(&body).is_err());
+        assert!(format.decode(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let body = encode_piece(b"piece data", PieceCompression::Gzip);
+        let header = PieceHeader::parse(&body).unwrap();
+        assert_eq!(header.bytes, body[..PIECE_HEADER_LEN]);
+        assert!(PieceHeader::parse(&body[..4]).is_err());
+    }
+
+    #[test]
+    fn test_sniff_compression() {
+        let sniff = |compression| {
+            let body = encode_piece(b"piece data", compression);
+            PieceCompression::sniff(&body[PIECE_HEADER_LEN..])
+        };
+        assert_eq!(sniff(PieceCompression::Gzip), Some(PieceCompression::Gzip));
+        assert_eq!(sniff(PieceCompression::Zlib), Some(PieceCompression::Zlib));
+        assert_eq!(sniff(PieceCompression::Zstd), Some(PieceCompression::Zstd));
+        assert_eq!(sniff(PieceCompression::Raw), None);
+    }
+
+    #[test]
+    fn test_check_reports_mismatched_compression() {
+        let format = PieceFormat::default();
+        let body = encode_piece(b"piece data", PieceCompression::Zstd);
+        let error = format.check(&body).unwrap_err();
+        assert!(error.to_string().contains("zstd"));
+    }
+
+    fn decode_in_chunks(
+        format: PieceFormat,
+        body: &[u8],
+        chunk_len: usize,
+    ) -> Result<(Vec<u8>, DecodedPiece)> {
+        let mut decoder = PieceDecoder::new(format);
+        let mut data = Vec::new();
+        for chunk in body.chunks(chunk_len) {
+            data.extend(decoder.feed(chunk)?);
+        }
+        let (tail, piece) = decoder.finish()?;
+        data.extend(tail);
+        Ok((data, piece))
+    }
+
+    #[test]
+    fn test_stream_decode_for_every_compression() {
+        let data = b"streamed piece data ".repeat(100);
+        for compression in [
+            PieceCompression::Gzip,
+            PieceCompression::Zlib,
+            PieceCompression::Zstd,
+            PieceCompression::Raw,
+        ] {
+            let format = PieceFormat {
+                compression,
+                digest: DigestAlgorithm::Sha1,
+            };
+            let body = encode_piece(&data, compression);
+            for chunk_len in [1, 7, body.len()] {
+                let (decoded, piece) = decode_in_chunks(format, &body, chunk_len).unwrap();
+                assert_eq!(
+                    decoded, data,
+                    "{} in {}-byte chunks",
+                    compression, chunk_len
+                );
+                assert_eq!(piece.digest, DigestAlgorithm::Sha1.hex_digest(&data));
+                assert_eq!(piece.size, data.len() as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_decode_bad_bodies() {
+        let format = PieceFormat::default();
+        let body = encode_piece(b"piece data", PieceCompression::Gzip);
+
+        // A truncated payload still fails to decompress
+        assert!(decode_in_chunks(format, &body[..body.len() - 3], 5).is_err());
+
+        // The header bytes are not interpreted
+        let mut relabelled = body.clone();
+        relabelled[..PIECE_HEADER_LEN].copy_from_slice(&[0, 7, 0, 0, 0, 1]);
+        let (data, _) = decode_in_chunks(format, &relabelled, 5).unwrap();
+        assert_eq!(data, b"piece data");
+        assert_eq!(format.decode(&relabelled).unwrap(), b"piece data");
+
+        assert!(decode_in_chunks(format, &body[..3], 5).is_err());
+
+        let zstd = encode_piece(b"piece data", PieceCompression::Zstd);
+        let error = decode_in_chunks(format, &zstd, 2).unwrap_err();
+        assert!(error.to_string().contains("zstd"));
+    }
+
+    #[test]
+    fn test_inspect_piece() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data = b"piece data";
+        let digest = DigestAlgorithm::Sha256.hex_digest(data);
+        let path = temp_dir.path().join(format!("{}.solidpiece", digest));
+        std::fs::write(&path, encode_piece(data, PieceCompression::Gzip)).unwrap();
+
+        let inspection = inspect_piece(&path, PieceFormat::default()).unwrap();
+        assert!(inspection.is_valid(), "{}", inspection);
+        assert_eq!(inspection.decompressed_len, Some(data.len()));
+        assert_eq!(inspection.detected, Some(PieceCompression::Gzip));
+
+        let renamed = temp_dir
+            .path()
+            .join(format!("{}.solidpiece", "00".repeat(32)));
+        std::fs::rename(&path, &renamed).unwrap();
+        let inspection = inspect_piece(&renamed, PieceFormat::default()).unwrap();
+        assert!(!inspection.is_valid());
+    }
+}
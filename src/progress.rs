@@ -0,0 +1,344 @@
+use clap::ValueEnum;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+const BAR_WIDTH: usize = 30;
+const BAR_INTERVAL: Duration = Duration::from_millis(250);
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How download progress is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ProgressMode {
+    /// A progress bar on an interactive terminal, log lines otherwise
+    #[default]
+    Auto,
+    /// A progress bar redrawn on stderr
+    Bar,
+    /// A summary log line every few seconds
+    Log,
+    /// No progress output
+    Off,
+}
+
+impl ProgressMode {
+    /// Resolves `Auto` to `Bar` or `Log` depending on where stderr goes
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Auto => Self::detect(std::io::stderr().is_terminal(), |name| {
+                std::env::var(name).ok()
+            }),
+            mode => mode,
+        }
+    }
+
+    /// Picks the bar for terminals outside of CI and log lines everywhere else
+    fn detect(is_terminal: bool, var: impl Fn(&str) -> Option<String>) -> Self {
+        let in_ci = ["CI", "GITHUB_ACTIONS", "GITLAB_CI", "JENKINS_URL"]
+            .iter()
+            .any(|name| var(name).is_some_and(|value| !value.is_empty()));
+        if is_terminal && !in_ci {
+            Self::Bar
+        } else {
+            Self::Log
+        }
+    }
+}
+
+/// Progress counters of a download, updated concurrently by piece tasks
+#[derive(Debug)]
+pub struct Progress {
+    started: Instant,
+    pieces_total: usize,
+    /// Expected decompressed size of the build, from the metafile
+    bytes_total: u64,
+    pieces_done: AtomicUsize,
+    bytes_downloaded: AtomicU64,
+    bytes_decompressed: AtomicU64,
+}
+
+/// Point-in-time view of a [`Progress`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressSnapshot {
+    pub pieces_total: usize,
+    pub pieces_done: usize,
+    pub bytes_total: u64,
+    pub bytes_downloaded: u64,
+    pub bytes_decompressed: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn new(pieces_total: usize, bytes_total: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            pieces_total,
+            bytes_total,
+            pieces_done: AtomicUsize::new(0),
+            bytes_downloaded: AtomicU64::new(0),
+            bytes_decompressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a finished piece
+    pub fn piece_done(&self, downloaded: u64, decompressed: u64) {
+        self.bytes_downloaded
+            .fetch_add(downloaded, Ordering::Relaxed);
+        self.bytes_decompressed
+            .fetch_add(decompressed, Ordering::Relaxed);
+        self.pieces_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            pieces_total: self.pieces_total,
+            pieces_done: self.pieces_done.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total,
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            bytes_decompressed: self.bytes_decompressed.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+impl ProgressSnapshot {
+    /// Completed fraction of the build, by decompressed bytes
+    pub fn fraction(&self) -> f64 {
+        if self.bytes_total == 0 {
+            if self.pieces_total == 0 {
+                return 1.0;
+            }
+            return self.pieces_done as f64 / self.pieces_total as f64;
+        }
+        (self.bytes_decompressed as f64 / self.bytes_total as f64).min(1.0)
+    }
+
+    /// Download throughput in bytes per second
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes_downloaded as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimated time until all pieces are done, once there is a rate to go by
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction();
+        if fraction <= 0.0 {
+            return None;
+        }
+        let remaining = self.elapsed.as_secs_f64() * (1.0 - fraction) / fraction;
+        Some(Duration::from_secs_f64(remaining))
+    }
+
+    /// Renders a one-line summary, e.g. for CI logs
+    pub fn summary(&self) -> String {
+        format!(
+            "{}/{} pieces, {} of {} ({:.0}%), {} downloaded at {}/s, ETA {}",
+            self.pieces_done,
+            self.pieces_total,
+            format_bytes(self.bytes_decompressed),
+            format_bytes(self.bytes_total),
+            self.fraction() * 100.0,
+            format_bytes(self.bytes_downloaded),
+            format_bytes(self.throughput() as u64),
+            self.eta()
+                .map(format_duration)
+                .unwrap_or_else(|| "unknown".to_string())
+        )
+    }
+
+    /// Renders a progress bar line for a terminal
+    pub fn bar(&self) -> String {
+        let filled = (self.fraction() * BAR_WIDTH as f64).round() as usize;
+        format!(
+            "[{}{}] {:>3.0}% {}/{} pieces {}/s ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.fraction() * 100.0,
+            self.pieces_done,
+            self.pieces_total,
+            format_bytes(self.throughput() as u64),
+            self.eta()
+                .map(format_duration)
+                .unwrap_or_else(|| "--".to_string())
+        )
+    }
+}
+
+/// Periodically renders a [`Progress`] until finished
+#[derive(Debug)]
+pub struct ProgressReporter {
+    progress: Arc<Progress>,
+    mode: ProgressMode,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ProgressReporter {
+    /// Starts rendering `progress` in the background
+    pub fn start(progress: Arc<Progress>, mode: ProgressMode) -> Self {
+        let mode = mode.resolve();
+        let task = match mode {
+            ProgressMode::Bar | ProgressMode::Log => {
+                let progress = progress.clone();
+                Some(tokio::spawn(async move {
+                    let period = if mode == ProgressMode::Bar {
+                        BAR_INTERVAL
+                    } else {
+                        LOG_INTERVAL
+                    };
+                    let mut interval =
+                        tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    loop {
+                        interval.tick().await;
+                        render(mode, &progress.snapshot());
+                    }
+                }))
+            }
+            ProgressMode::Auto | ProgressMode::Off => None,
+        };
+
+        Self {
+            progress,
+            mode,
+            task,
+        }
+    }
+
+    /// Stops rendering and shows the final state
+    pub fn finish(mut self) -> ProgressSnapshot {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        let snapshot = self.progress.snapshot();
+        match self.mode {
+            ProgressMode::Bar => {
+                render(ProgressMode::Bar, &snapshot);
+                eprintln!();
+            }
+            ProgressMode::Log => render(ProgressMode::Log, &snapshot),
+            ProgressMode::Auto | ProgressMode::Off => {}
+        }
+        snapshot
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+fn render(mode: ProgressMode, snapshot: &ProgressSnapshot) {
+    match mode {
+        ProgressMode::Bar => {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r{}\x1b[K", snapshot.bar());
+            let _ = stderr.flush();
+        }
+        ProgressMode::Log => log::info!("Progress: {}", snapshot.summary()),
+        ProgressMode::Auto | ProgressMode::Off => {}
+    }
+}
+
+/// Formats a byte count with binary units, e.g. `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a duration as `1h02m03s`, `2m05s` or `7s`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(done: usize, decompressed: u64, elapsed: u64) -> ProgressSnapshot {
+        ProgressSnapshot {
+            pieces_total: 4,
+            pieces_done: done,
+            bytes_total: 4096,
+            bytes_downloaded: decompressed / 2,
+            bytes_decompressed: decompressed,
+            elapsed: Duration::from_secs(elapsed),
+        }
+    }
+
+    #[test]
+    fn test_fraction_throughput_and_eta() {
+        let start = snapshot(0, 0, 0);
+        assert_eq!(start.fraction(), 0.0);
+        assert_eq!(start.throughput(), 0.0);
+        assert!(start.eta().is_none());
+
+        let half = snapshot(2, 2048, 10);
+        assert_eq!(half.fraction(), 0.5);
+        assert_eq!(half.throughput(), 102.4);
+        assert_eq!(half.eta(), Some(Duration::from_secs(10)));
+        assert_eq!(
+            half.summary(),
+            "2/4 pieces, 2.0 KiB of 4.0 KiB (50%), 1.0 KiB downloaded at 102 B/s, ETA 10s"
+        );
+        assert!(half
+            .bar()
+            .starts_with(&format!("[{}{}]  50%", "#".repeat(15), "-".repeat(15))));
+    }
+
+    #[test]
+    fn test_progress_counts_pieces() {
+        let progress = Progress::new(2, 10);
+        progress.piece_done(3, 6);
+        progress.piece_done(0, 4);
+
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.pieces_done, 2);
+        assert_eq!(snapshot.bytes_downloaded, 3);
+        assert_eq!(snapshot.bytes_decompressed, 10);
+        assert_eq!(snapshot.fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_detect_mode() {
+        let none = |_: &str| None;
+        assert_eq!(ProgressMode::detect(true, none), ProgressMode::Bar);
+        assert_eq!(ProgressMode::detect(false, none), ProgressMode::Log);
+        assert_eq!(
+            ProgressMode::detect(true, |name| (name == "CI").then(|| "true".to_string())),
+            ProgressMode::Log
+        );
+        assert_eq!(ProgressMode::Off.resolve(), ProgressMode::Off);
+    }
+
+    #[test]
+    fn test_format_bytes_and_duration() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_duration(Duration::from_secs(7)), "7s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m05s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+}
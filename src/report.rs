@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::downloader::DownloadStats;
+use crate::github::ReleaseCheck;
+use crate::history::BuildEvent;
+use crate::manifest::ManifestFile;
+
+/// Version of the run report JSON layout
+///
+/// Fields may be added without bumping the version; renaming, removing or
+/// changing the meaning of a field requires a bump.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Structured record of a single updater run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub schema_version: u32,
+    pub tool_version: String,
+    /// Start of the run (seconds since the Unix epoch)
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub repo: String,
+    pub build: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ConfigSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadStats>,
+    pub timings: Vec<PhaseTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<ArtifactSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<VersionSummary>,
+    pub files: Vec<ManifestFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<DecisionSummary>,
+    /// How the build relates to earlier runs; only known with `--history`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_event: Option<BuildEvent>,
+    pub errors: Vec<String>,
+    #[serde(skip, default = "Instant::now")]
+    started: Instant,
+}
+
+/// Identifiers of the remote config documents the build was resolved from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigSummary {
+    pub version_id: String,
+    pub version: String,
+    pub promote_time: u64,
+    pub scan_time: u64,
+    pub alias: String,
+    pub catalog_id: String,
+    pub piece_format: String,
+    pub delta_format: String,
+    pub metafile_id: String,
+    pub metafile_version: String,
+    pub piece_count: usize,
+    pub file_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactSummary {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionSummary {
+    /// Version source used to resolve `version` (`pe`, `config` or `metafile`)
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_version: Option<String>,
+    pub version: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecisionSummary {
+    pub should_create: bool,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_version: Option<String>,
+    pub change: String,
+    pub prerelease: bool,
+}
+
+impl From<&Config> for ConfigSummary {
+    fn from(config: &Config) -> Self {
+        Self {
+            version_id: config.version.id.clone(),
+            version: config.version.version.clone(),
+            promote_time: config.version.promote_time,
+            scan_time: config.version.scan_time,
+            alias: config.alias.clone(),
+            catalog_id: config.catalog.id.clone(),
+            piece_format: config.catalog.piece_format.clone(),
+            delta_format: config.catalog.delta_format.clone(),
+            metafile_id: config.metafile.id.clone(),
+            metafile_version: config.metafile.version.clone(),
+            piece_count: config.metafile.pieces.len(),
+            file_count: config.metafile.files.len(),
+        }
+    }
+}
+
+impl From<&ReleaseCheck> for DecisionSummary {
+    fn from(check: &ReleaseCheck) -> Self {
+        Self {
+            should_create: check.should_create,
+            reason: check.reason.clone(),
+            previous_version: check.previous_version.clone(),
+            change: check.change.to_string(),
+            prerelease: check.prerelease,
+        }
+    }
+}
+
+impl RunReport {
+    /// Starts a report for a run of `repo`.`build`
+    pub fn new(repo: &str, build: &str) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at,
+            duration_ms: 0,
+            success: false,
+            repo: repo.to_string(),
+            build: build.to_string(),
+            config: None,
+            download: None,
+            timings: Vec::new(),
+            artifact: None,
+            version: None,
+            files: Vec::new(),
+            decision: None,
+            build_event: None,
+            errors: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records how long `phase` took, measured from `started`
+    pub fn record_phase(&mut self, phase: &str, started: Instant) {
+        self.timings.push(PhaseTiming {
+            phase: phase.to_string(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+
+    /// Completes the report with the outcome of the run
+    pub fn finish(&mut self, error: Option<&anyhow::Error>) {
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.success = error.is_none();
+        if let Some(error) = error {
+            self.errors.push(format!("{:#}", error));
+        }
+    }
+
+    /// Serialises the report as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize run report")
+    }
+
+    /// Writes the report to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("Failed to write run report: {}", path.display()))?;
+
+        log::info!("Wrote run report: {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_round_trip() {
+        let mut report = RunReport::new("osrs-win", "production");
+        report.config = Some(ConfigSummary::from(&Config::new("osrs-win", "production")));
+        report.record_phase("download", Instant::now());
+        report.finish(Some(&anyhow::anyhow!("inner").context("outer")));
+
+        let json = report.to_json().unwrap();
+        let parsed: RunReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.schema_version, REPORT_SCHEMA_VERSION);
+        assert!(!parsed.success);
+        assert_eq!(parsed.errors, vec!["outer: inner".to_string()]);
+        assert_eq!(parsed.timings[0].phase, "download");
+        assert!(parsed.decision.is_none());
+    }
+
+    #[test]
+    fn test_report_omits_missing_sections() {
+        let mut report = RunReport::new("osrs-win", "production");
+        report.finish(None);
+
+        let value: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(value["success"], true);
+        assert!(value.get("download").is_none());
+        assert!(value["files"].as_array().unwrap().is_empty());
+    }
+}
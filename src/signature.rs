@@ -0,0 +1,485 @@
+use anyhow::{bail, Context, Result};
+use cms::cert::CertificateChoices;
+use cms::content_info::ContentInfo;
+use cms::signed_data::{SignedData, SignerIdentifier, SignerInfo};
+use der::asn1::{GeneralizedTime, OctetString};
+use der::oid::db::rfc5280::ID_CE_SUBJECT_KEY_IDENTIFIER;
+use der::oid::db::rfc5911::{ID_COUNTERSIGNATURE, ID_SIGNED_DATA, ID_SIGNING_TIME};
+use der::oid::db::rfc5912::{ID_SHA_1, ID_SHA_256};
+use der::oid::ObjectIdentifier;
+use der::{Any, Decode, Encode, Sequence, SliceReader, Tag, Tagged};
+use pelite::pe32::Pe as Pe32;
+use pelite::pe64::Pe as Pe64;
+use pelite::FileMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::path::Path;
+use x509_cert::attr::Attributes;
+use x509_cert::ext::pkix::SubjectKeyIdentifier;
+use x509_cert::ext::Extension;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::time::Time;
+use x509_cert::Certificate;
+
+/// `WIN_CERT_TYPE_PKCS_SIGNED_DATA`
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+/// Microsoft RFC 3161 timestamp counter-signature attribute
+const ID_RFC3161_TIMESTAMP: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.3.3.1");
+
+/// Authenticode signature embedded in a PE executable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureInfo {
+    pub signer_subject: String,
+    pub signer_issuer: String,
+    pub signer_serial: String,
+    /// Time reported by the signing-time attribute or timestamp counter-signature
+    pub signing_time: Option<String>,
+    pub digest_algorithm: String,
+    /// Whether the file's Authenticode digest matches the signed digest
+    pub digest_valid: bool,
+    /// Embedded certificates, starting with the signer and following issuers
+    pub certificate_chain: Vec<CertificateInfo>,
+}
+
+impl SignatureInfo {
+    /// Returns the SHA-256 fingerprint of the signing certificate
+    pub fn signer_fingerprint(&self) -> Option<&str> {
+        self.certificate_chain
+            .first()
+            .map(|cert| cert.sha256_fingerprint.as_str())
+    }
+}
+
+/// Summary of a single X.509 certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub serial: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub sha256_fingerprint: String,
+}
+
+impl CertificateInfo {
+    fn from_certificate(cert: &Certificate) -> Result<Self> {
+        let tbs = &cert.tbs_certificate;
+        let der = cert.to_der().context("Failed to encode certificate")?;
+
+        Ok(Self {
+            subject: tbs.subject.to_string(),
+            issuer: tbs.issuer.to_string(),
+            serial: hex::encode(tbs.serial_number.as_bytes()),
+            not_before: tbs.validity.not_before.to_string(),
+            not_after: tbs.validity.not_after.to_string(),
+            sha256_fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+/// `SpcIndirectDataContent`, the Authenticode content signed by the PKCS#7 envelope
+#[derive(Sequence)]
+struct SpcIndirectDataContent {
+    data: Any,
+    message_digest: DigestInfo,
+}
+
+#[derive(Sequence)]
+struct DigestInfo {
+    digest_algorithm: AlgorithmIdentifierOwned,
+    digest: OctetString,
+}
+
+/// Inspects the Authenticode signature of a PE executable
+///
+/// # Arguments
+///
+/// * `file_path` - Path to the PE file (.exe or .dll)
+///
+/// # Returns
+///
+/// Returns `Ok(None)` if the file is not signed, or the parsed signature
+/// including whether the file's digest matches the signed digest.
+///
+/// # Notes
+///
+/// Only the file digest is checked; the signer's RSA/ECDSA signature and
+/// the certificate chain's trust are not cryptographically verified.
+pub fn inspect_signature(file_path: &Path) -> Result<Option<SignatureInfo>> {
+    let map = FileMap::open(file_path)
+        .with_context(|| format!("Failed to open PE file: {}", file_path.display()))?;
+
+    let security = match pelite::pe64::PeFile::from_bytes(&map) {
+        Ok(pe) => pe.security(),
+        Err(pelite::Error::PeMagic) => pelite::pe32::PeFile::from_bytes(&map)
+            .context("File is neither a valid PE32 nor PE64 executable")?
+            .security(),
+        Err(e) => bail!("Failed to parse PE file: {}", e),
+    };
+
+    let security = match security {
+        Ok(security) => security,
+        Err(pelite::Error::Null) => return Ok(None),
+        Err(e) => bail!("Failed to read security directory: {}", e),
+    };
+
+    if security.certificate_type() != WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+        bail!(
+            "Unsupported certificate type {:#06x} in {}",
+            security.certificate_type(),
+            file_path.display()
+        );
+    }
+
+    // The mapping is rounded up to the page size; only hash the actual file
+    let file_len = std::fs::metadata(file_path)
+        .with_context(|| format!("Failed to stat PE file: {}", file_path.display()))?
+        .len() as usize;
+    let image = &map.as_ref()[..file_len.min(map.as_ref().len())];
+
+    parse_signature(image, security.certificate_data())
+        .with_context(|| format!("Failed to parse signature of {}", file_path.display()))
+        .map(Some)
+}
+
+/// Parses a PKCS#7 Authenticode signature and checks it against `image`
+fn parse_signature(image: &[u8], pkcs7: &[u8]) -> Result<SignatureInfo> {
+    let signed_data = decode_signed_data(pkcs7)?;
+
+    let signer = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .context("Signature has no signer")?;
+
+    let certificates: Vec<&Certificate> = signed_data
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(cert) => Some(cert),
+            _ => None,
+        })
+        .collect();
+
+    let signer_cert = certificates
+        .iter()
+        .copied()
+        .find(|cert| is_signer_certificate(cert, &signer.sid))
+        .context("Signer certificate not found in signature")?;
+
+    let content = signed_data
+        .encap_content_info
+        .econtent
+        .as_ref()
+        .context("Signature has no signed content")?;
+    let indirect: SpcIndirectDataContent = decode_content(content)?;
+    let digest_algorithm = indirect.message_digest.digest_algorithm.oid;
+
+    let digest_valid = match authenticode_digest(image, digest_algorithm)? {
+        Some(digest) => digest == indirect.message_digest.digest.as_bytes(),
+        None => false,
+    };
+
+    Ok(SignatureInfo {
+        signer_subject: signer_cert.tbs_certificate.subject.to_string(),
+        signer_issuer: signer_cert.tbs_certificate.issuer.to_string(),
+        signer_serial: hex::encode(signer_cert.tbs_certificate.serial_number.as_bytes()),
+        signing_time: signing_time(signer),
+        digest_algorithm: digest_algorithm_name(digest_algorithm),
+        digest_valid,
+        certificate_chain: certificate_chain(signer_cert, &certificates)?,
+    })
+}
+
+fn decode_signed_data(bytes: &[u8]) -> Result<SignedData> {
+    // The certificate table is padded to 8 bytes, so ignore trailing data
+    let mut reader = SliceReader::new(bytes).context("Invalid PKCS#7 ContentInfo")?;
+    let content_info = ContentInfo::decode(&mut reader).context("Invalid PKCS#7 ContentInfo")?;
+    if content_info.content_type != ID_SIGNED_DATA {
+        bail!(
+            "Unexpected PKCS#7 content type: {}",
+            content_info.content_type
+        );
+    }
+
+    content_info
+        .content
+        .decode_as::<SignedData>()
+        .context("Invalid PKCS#7 SignedData")
+}
+
+/// Decodes encapsulated content, which may be wrapped in an OCTET STRING
+fn decode_content<T: for<'a> Decode<'a>>(content: &Any) -> Result<T> {
+    let decoded = if content.tag() == Tag::OctetString {
+        T::from_der(content.value())
+    } else {
+        T::from_der(&content.to_der()?)
+    };
+
+    decoded.context("Invalid signed content")
+}
+
+fn is_signer_certificate(cert: &Certificate, sid: &SignerIdentifier) -> bool {
+    match sid {
+        SignerIdentifier::IssuerAndSerialNumber(id) => {
+            cert.tbs_certificate.issuer == id.issuer
+                && cert.tbs_certificate.serial_number == id.serial_number
+        }
+        SignerIdentifier::SubjectKeyIdentifier(ski) => has_subject_key_identifier(
+            cert.tbs_certificate
+                .extensions
+                .as_deref()
+                .unwrap_or_default(),
+            ski,
+        ),
+    }
+}
+
+/// Returns true if `extensions` hold a subject key identifier equal to `ski`
+fn has_subject_key_identifier(extensions: &[Extension], ski: &SubjectKeyIdentifier) -> bool {
+    extensions
+        .iter()
+        .filter(|ext| ext.extn_id == ID_CE_SUBJECT_KEY_IDENTIFIER)
+        .any(|ext| {
+            SubjectKeyIdentifier::from_der(ext.extn_value.as_bytes())
+                .is_ok_and(|decoded| decoded == *ski)
+        })
+}
+
+/// Orders the embedded certificates from the signer up through its issuers
+fn certificate_chain(
+    signer: &Certificate,
+    certificates: &[&Certificate],
+) -> Result<Vec<CertificateInfo>> {
+    let mut chain = vec![CertificateInfo::from_certificate(signer)?];
+    let mut current = signer;
+
+    while current.tbs_certificate.issuer != current.tbs_certificate.subject
+        && chain.len() <= certificates.len()
+    {
+        let Some(issuer) = certificates
+            .iter()
+            .find(|cert| cert.tbs_certificate.subject == current.tbs_certificate.issuer)
+        else {
+            break;
+        };
+        chain.push(CertificateInfo::from_certificate(issuer)?);
+        current = issuer;
+    }
+
+    Ok(chain)
+}
+
+/// Finds the signing time in the signer's own attributes, a PKCS#9
+/// counter-signature or an RFC 3161 timestamp token
+fn signing_time(signer: &SignerInfo) -> Option<String> {
+    if let Some(time) = signer.signed_attrs.as_ref().and_then(signing_time_attr) {
+        return Some(time);
+    }
+
+    let unsigned = signer.unsigned_attrs.as_ref()?;
+    for attr in unsigned.iter() {
+        for value in attr.values.iter() {
+            let time = if attr.oid == ID_COUNTERSIGNATURE {
+                value
+                    .decode_as::<SignerInfo>()
+                    .ok()
+                    .and_then(|counter| counter.signed_attrs.as_ref().and_then(signing_time_attr))
+            } else if attr.oid == ID_RFC3161_TIMESTAMP {
+                value.to_der().ok().and_then(|der| timestamp_time(&der))
+            } else {
+                None
+            };
+
+            if time.is_some() {
+                return time;
+            }
+        }
+    }
+
+    None
+}
+
+fn signing_time_attr(attrs: &Attributes) -> Option<String> {
+    attrs
+        .iter()
+        .find(|attr| attr.oid == ID_SIGNING_TIME)
+        .and_then(|attr| attr.values.iter().next())
+        .and_then(|value| Time::from_der(&value.to_der().ok()?).ok())
+        .map(|time| time.to_string())
+}
+
+/// Extracts `genTime` from an RFC 3161 timestamp token
+fn timestamp_time(token: &[u8]) -> Option<String> {
+    let signed_data = decode_signed_data(token).ok()?;
+    let content = signed_data.encap_content_info.econtent.as_ref()?;
+
+    // TSTInfo ::= SEQUENCE { version, policy, messageImprint, serialNumber, genTime, ... }
+    let tst_info: Vec<Any> = decode_content(content).ok()?;
+    let gen_time = tst_info.get(4)?.decode_as::<GeneralizedTime>().ok()?;
+
+    Some(gen_time.to_date_time().to_string())
+}
+
+fn digest_algorithm_name(oid: ObjectIdentifier) -> String {
+    if oid == ID_SHA_256 {
+        "sha256".to_string()
+    } else if oid == ID_SHA_1 {
+        "sha1".to_string()
+    } else {
+        oid.to_string()
+    }
+}
+
+/// Computes the Authenticode digest of a PE image
+///
+/// Returns `Ok(None)` for digest algorithms that are not supported.
+fn authenticode_digest(image: &[u8], algorithm: ObjectIdentifier) -> Result<Option<Vec<u8>>> {
+    let ranges = authenticode_ranges(image)?;
+
+    let digest = if algorithm == ID_SHA_256 {
+        let mut hasher = Sha256::new();
+        ranges.into_iter().for_each(|r| hasher.update(&image[r]));
+        hasher.finalize().to_vec()
+    } else if algorithm == ID_SHA_1 {
+        let mut hasher = sha1::Sha1::new();
+        ranges.into_iter().for_each(|r| hasher.update(&image[r]));
+        hasher.finalize().to_vec()
+    } else {
+        log::warn!("Unsupported Authenticode digest algorithm: {}", algorithm);
+        return Ok(None);
+    };
+
+    Ok(Some(digest))
+}
+
+/// Returns the byte ranges of a PE image covered by its Authenticode digest
+///
+/// The digest covers the whole file except the optional header checksum,
+/// the certificate table directory entry and the certificate table itself.
+fn authenticode_ranges(image: &[u8]) -> Result<Vec<Range<usize>>> {
+    let read_u32 = |offset: usize| -> Result<usize> {
+        let bytes = image
+            .get(offset..offset + 4)
+            .context("PE headers are truncated")?;
+        Ok(u32::from_le_bytes(bytes.try_into()?) as usize)
+    };
+
+    let pe_offset = read_u32(0x3c)?;
+    let optional_header = pe_offset + 24;
+    let magic = image
+        .get(optional_header..optional_header + 2)
+        .context("PE headers are truncated")?;
+
+    let data_directories = match u16::from_le_bytes([magic[0], magic[1]]) {
+        0x10b => optional_header + 96,
+        0x20b => optional_header + 112,
+        other => bail!("Unknown optional header magic: {:#06x}", other),
+    };
+
+    let checksum = optional_header + 64;
+    let security_entry = data_directories + 4 * 8;
+    let table_offset = read_u32(security_entry)?;
+    let table_size = read_u32(security_entry + 4)?;
+
+    let table_end = table_offset
+        .checked_add(table_size)
+        .filter(|&end| end <= image.len() && table_offset >= security_entry + 8)
+        .context("Certificate table lies outside the file")?;
+
+    let mut ranges = vec![
+        0..checksum,
+        checksum + 4..security_entry,
+        security_entry + 8..table_offset,
+    ];
+    if table_end < image.len() {
+        ranges.push(table_end..image.len());
+    }
+
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a minimal PE32+ header with a certificate table directory entry
+    fn fake_image(table_offset: u32, table_size: u32, len: usize) -> Vec<u8> {
+        let mut image = vec![0u8; len];
+        image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x80 + 24..0x80 + 26].copy_from_slice(&0x20bu16.to_le_bytes());
+        let entry = 0x80 + 24 + 112 + 32;
+        image[entry..entry + 4].copy_from_slice(&table_offset.to_le_bytes());
+        image[entry + 4..entry + 8].copy_from_slice(&table_size.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn test_authenticode_ranges_skip_signature_fields() {
+        let image = fake_image(0x300, 0x100, 0x400);
+        let ranges = authenticode_ranges(&image).unwrap();
+
+        let checksum = 0x80 + 24 + 64;
+        let entry = 0x80 + 24 + 112 + 32;
+        assert_eq!(
+            ranges,
+            vec![0..checksum, checksum + 4..entry, entry + 8..0x300]
+        );
+    }
+
+    #[test]
+    fn test_authenticode_ranges_include_trailing_data() {
+        let image = fake_image(0x200, 0x100, 0x400);
+        let ranges = authenticode_ranges(&image).unwrap();
+        assert_eq!(ranges.last().unwrap(), &(0x300..0x400));
+    }
+
+    #[test]
+    fn test_authenticode_ranges_reject_out_of_bounds_table() {
+        let image = fake_image(0x300, 0x200, 0x400);
+        assert!(authenticode_ranges(&image).is_err());
+    }
+
+    #[test]
+    fn test_subject_key_identifier_matches_exactly() {
+        let key_id = |bytes: &[u8]| SubjectKeyIdentifier(OctetString::new(bytes).unwrap());
+        let extension = |oid: ObjectIdentifier, ski: &SubjectKeyIdentifier| Extension {
+            extn_id: oid,
+            critical: false,
+            extn_value: OctetString::new(ski.to_der().unwrap()).unwrap(),
+        };
+        let ski = key_id(&[1, 2, 3, 4]);
+
+        assert!(has_subject_key_identifier(
+            &[extension(ID_CE_SUBJECT_KEY_IDENTIFIER, &ski)],
+            &ski
+        ));
+        // Another extension whose value merely ends with the identifier
+        let authority_key_id = ObjectIdentifier::new_unwrap("2.5.29.35");
+        assert!(!has_subject_key_identifier(
+            &[extension(authority_key_id, &ski)],
+            &ski
+        ));
+        // A longer identifier sharing the same suffix
+        assert!(!has_subject_key_identifier(
+            &[extension(
+                ID_CE_SUBJECT_KEY_IDENTIFIER,
+                &key_id(&[0, 1, 2, 3, 4])
+            )],
+            &ski
+        ));
+    }
+
+    #[test]
+    fn test_inspect_signature_rejects_non_pe() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("fake.exe");
+        std::fs::write(&path, b"not a pe file").unwrap();
+        assert!(inspect_signature(&path).is_err());
+    }
+}
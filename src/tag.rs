@@ -0,0 +1,217 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+
+/// Where the build version used for release tags comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VersionSource {
+    /// FileVersion resource of the selected PE executable
+    Pe,
+    /// `version` field of the versions document (`Config.version.version`)
+    Config,
+    /// `version` field of the metafile
+    Metafile,
+}
+
+/// Values available to a release tag template
+///
+/// Placeholders are written as `{name}`, e.g. `{repo}-{build}-{file_version}`.
+#[derive(Debug, Clone, Default)]
+pub struct TagContext {
+    values: BTreeMap<&'static str, String>,
+}
+
+impl TagContext {
+    /// Builds the template context from the loaded remote config and the
+    /// PE file version, resolving `{version}` according to `source`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the selected version source has no value, so a
+    /// release is never tagged with a placeholder version.
+    pub fn new(config: &Config, file_version: Option<&str>, source: VersionSource) -> Result<Self> {
+        let version = match source {
+            VersionSource::Pe => file_version.unwrap_or_default(),
+            VersionSource::Config => config.version.version.as_str(),
+            VersionSource::Metafile => config.metafile.version.as_str(),
+        };
+        if version.trim().is_empty() {
+            bail!("No version available from {:?} version source", source);
+        }
+
+        let mut values = BTreeMap::new();
+        values.insert("version", version.to_string());
+        values.insert("repo", config.repo.clone());
+        values.insert("build", config.build.clone());
+        values.insert("file_version", file_version.unwrap_or_default().to_string());
+        values.insert("config.version", config.version.version.clone());
+        values.insert("version.id", config.version.id.clone());
+        values.insert("metafile.version", config.metafile.version.clone());
+        values.insert("metafile.id", config.metafile.id.clone());
+        values.insert("catalog.id", config.catalog.id.clone());
+
+        Ok(Self { values })
+    }
+
+    /// Returns the resolved build version
+    pub fn version(&self) -> &str {
+        &self.values["version"]
+    }
+
+    /// Renders `template` and sanitises the result into a valid git ref name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for unknown or empty placeholders, unbalanced braces,
+    /// or a template that renders to an empty tag.
+    pub fn render(&self, template: &str) -> Result<String> {
+        let rendered = fill_placeholders(template, |name| {
+            let value = self
+                .values
+                .get(name)
+                .with_context(|| format!("Unknown placeholder {{{}}} in tag template", name))?;
+            if value.is_empty() {
+                bail!("Placeholder {{{}}} has no value", name);
+            }
+            Ok(value.clone())
+        })?;
+
+        let tag = sanitize_ref_name(&rendered);
+        if tag.is_empty() {
+            bail!("Tag template {} rendered an empty tag", template);
+        }
+        if tag != rendered {
+            log::info!("Sanitised release tag {} to {}", rendered, tag);
+        }
+
+        Ok(tag)
+    }
+}
+
+/// Replaces every `{name}` placeholder in `template` with `lookup(name)`
+///
+/// # Errors
+///
+/// Returns an error for an unclosed placeholder, or the error of `lookup`.
+pub fn fill_placeholders(
+    template: &str,
+    mut lookup: impl FnMut(&str) -> Result<String>,
+) -> Result<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("Unclosed placeholder in template: {}", template))?;
+        rendered.push_str(&lookup(&rest[start + 1..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Rewrites `name` so that it satisfies `git check-ref-format`
+///
+/// Forbidden characters become `-` and `..` runs are collapsed. Each
+/// `/`-separated component loses leading and trailing dots and a trailing
+/// `.lock`, and empty components are dropped.
+pub fn sanitize_ref_name(name: &str) -> String {
+    let mapped: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_control() => '-',
+            ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\' => '-',
+            c => c,
+        })
+        .collect();
+    let mapped = mapped.replace("@{", "@-");
+
+    let sanitized = mapped
+        .split('/')
+        .map(sanitize_ref_component)
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if sanitized == "@" {
+        return String::new();
+    }
+    sanitized
+}
+
+/// Sanitizes one `/`-separated component of a ref name
+fn sanitize_ref_component(component: &str) -> String {
+    let mut sanitized = component.to_string();
+    while sanitized.contains("..") {
+        sanitized = sanitized.replace("..", ".");
+    }
+
+    loop {
+        let trimmed = sanitized.trim_matches('.').trim_end_matches(".lock");
+        if trimmed.len() == sanitized.len() {
+            break;
+        }
+        sanitized = trimmed.to_string();
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::new("osrs-win", "production");
+        config.version.version = "225.1".to_string();
+        config.metafile.version = "225.1-meta".to_string();
+        config
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let context = TagContext::new(&config(), Some("1.2.3.4"), VersionSource::Pe).unwrap();
+        assert_eq!(context.render("{version}").unwrap(), "1.2.3.4");
+    }
+
+    #[test]
+    fn test_render_composite_template() {
+        let context = TagContext::new(&config(), Some("1.2.3.4"), VersionSource::Metafile).unwrap();
+        assert_eq!(
+            context.render("{repo}-{build}-{version}").unwrap(),
+            "osrs-win-production-225.1-meta"
+        );
+        assert_eq!(context.render("v{file_version}").unwrap(), "v1.2.3.4");
+    }
+
+    #[test]
+    fn test_render_rejects_unknown_and_empty_placeholders() {
+        let context = TagContext::new(&config(), Some("1.2.3.4"), VersionSource::Pe).unwrap();
+        assert!(context.render("{nope}").is_err());
+        assert!(context.render("{metafile.id}").is_err());
+        assert!(context.render("{version").is_err());
+    }
+
+    #[test]
+    fn test_missing_version_is_an_error() {
+        assert!(TagContext::new(&config(), None, VersionSource::Pe).is_err());
+        assert!(TagContext::new(&Config::new("a", "b"), None, VersionSource::Config).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_ref_name() {
+        assert_eq!(sanitize_ref_name("1.2.3.4"), "1.2.3.4");
+        assert_eq!(sanitize_ref_name("a b:c?d"), "a-b-c-d");
+        assert_eq!(sanitize_ref_name("..a..b//c/.d."), "a.b/c/d");
+        assert_eq!(sanitize_ref_name("build.lock"), "build");
+        assert_eq!(sanitize_ref_name("a/./b"), "a/b");
+        assert_eq!(sanitize_ref_name("a.lock/b"), "a/b");
+        assert_eq!(sanitize_ref_name("a/.hidden"), "a/hidden");
+        assert_eq!(sanitize_ref_name("x@{y}"), "x@-y}");
+        assert_eq!(sanitize_ref_name("@"), "");
+    }
+}
@@ -0,0 +1,971 @@
+//! Synthetic CDN snapshots for hermetic tests
+//!
+//! [`FixtureBuild`] lays out the JWT config documents and `.solidpiece`
+//! files of a fake build exactly like the CDN does, so it can be served via
+//! [`Cdn::replay`](crate::cdn::Cdn::replay) or a file mirror, and
+//! [`MockCdn`] serves it over HTTP with optional fault injection.
+//! [`MockWebhook`] stands in for notification endpoints and [`MockGitHub`]
+//! for the releases API. [`pe_image`] builds minimal PE executables.
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use jsonwebtoken::{EncodingKey, Header};
+use octocrab::Octocrab;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::cdn::DEFAULT_CDN_URL;
+use crate::piece::{DigestAlgorithm, PieceCompression};
+
+/// A fake client build
+#[derive(Debug, Clone)]
+pub struct FixtureBuild {
+    pub repo: String,
+    pub build: String,
+    pub version: String,
+    /// `id` of the build in the versions document
+    pub version_id: String,
+    /// `scanTime` of the build in the versions document
+    pub scan_time: u64,
+    pub alias: String,
+    pub metafile_id: String,
+    pub files: Vec<(String, Vec<u8>)>,
+    /// Size of the uncompressed data stored in each piece
+    pub piece_size: usize,
+    /// Catalog `pieceFormat` value
+    pub piece_format: String,
+    /// Metafile `pieces.algorithm` value
+    pub pieces_algorithm: String,
+    /// Extra piece data after the last file that the metafile does not list
+    pub trailing_bytes: Vec<u8>,
+}
+
+impl FixtureBuild {
+    pub fn new(files: &[(&str, &[u8])]) -> Self {
+        Self {
+            repo: "osrs-win".to_string(),
+            build: "production".to_string(),
+            version: "225.1".to_string(),
+            version_id: "version-1".to_string(),
+            scan_time: 1_699_999_000_000,
+            alias: "catalog-1".to_string(),
+            metafile_id: "metafile-1".to_string(),
+            files: files
+                .iter()
+                .map(|(name, data)| (name.to_string(), data.to_vec()))
+                .collect(),
+            piece_size: 16,
+            piece_format: "solidpieces".to_string(),
+            pieces_algorithm: "SHA-256".to_string(),
+            trailing_bytes: Vec::new(),
+        }
+    }
+
+    fn compression(&self) -> PieceCompression {
+        PieceCompression::from_catalog(&self.piece_format).unwrap_or(PieceCompression::Gzip)
+    }
+
+    fn digest(&self) -> DigestAlgorithm {
+        DigestAlgorithm::from_name(&self.pieces_algorithm).unwrap_or(DigestAlgorithm::Sha256)
+    }
+
+    /// Returns the decompressed contents of each piece in order
+    pub fn piece_data(&self) -> Vec<Vec<u8>> {
+        let combined: Vec<u8> = self
+            .files
+            .iter()
+            .flat_map(|(_, data)| data.iter().copied())
+            .chain(self.trailing_bytes.iter().copied())
+            .collect();
+        combined
+            .chunks(self.piece_size.max(1))
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    /// Returns the CDN path of each piece, relative to the CDN root
+    pub fn piece_paths(&self) -> Vec<String> {
+        self.piece_data()
+            .iter()
+            .map(|data| {
+                let digest = self.digest().hex_digest(data);
+                format!(
+                    "{}/pieces/{}/{}.solidpiece",
+                    self.repo,
+                    &digest[..2],
+                    digest
+                )
+            })
+            .collect()
+    }
+
+    /// Returns every CDN resource of the build as (relative path, body)
+    pub fn resources(&self) -> Vec<(String, Vec<u8>)> {
+        let mut resources = vec![
+            (
+                format!("{0}/{0}.json", self.repo),
+                encode_jwt(&self.versions_claims()),
+            ),
+            (
+                format!("{}/alias.json", self.repo),
+                encode_jwt(&json!({ format!("{}.{}", self.repo, self.build): self.alias })),
+            ),
+            (
+                format!("{}/catalog/{}/catalog.json", self.repo, self.alias),
+                encode_jwt(&self.catalog_claims()),
+            ),
+            (self.metafile_path(), encode_jwt(&self.metafile_claims())),
+        ];
+
+        for (path, data) in self.piece_paths().into_iter().zip(self.piece_data()) {
+            resources.push((path, encode_piece(&data, self.compression())));
+        }
+
+        resources
+    }
+
+    /// Writes the build below `dir` in the CDN directory layout
+    pub fn write_snapshot(&self, dir: &Path) {
+        for (relative, body) in self.resources() {
+            let path = dir.join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, body).unwrap();
+        }
+    }
+
+    fn metafile_path(&self) -> String {
+        format!("{}/metafile/{}/metafile.json", self.repo, self.metafile_id)
+    }
+
+    fn versions_claims(&self) -> Value {
+        json!({
+            "environments": {
+                &self.build: {
+                    "id": self.version_id,
+                    "promoteTime": 1_700_000_000_000u64,
+                    "scanTime": self.scan_time,
+                    "version": self.version,
+                }
+            }
+        })
+    }
+
+    fn catalog_claims(&self) -> Value {
+        json!({
+            "id": self.alias,
+            "metafile": format!("{}{}", DEFAULT_CDN_URL, self.metafile_path()),
+            "config": {
+                "remote": {
+                    "baseUrl": format!("{}{}/", DEFAULT_CDN_URL, self.repo),
+                    "deltaFormat": "gzip",
+                    "flags": "",
+                    "pieceFormat": self.piece_format,
+                    "type": "remote",
+                }
+            }
+        })
+    }
+
+    fn metafile_claims(&self) -> Value {
+        let digests: Vec<String> = self
+            .piece_data()
+            .iter()
+            .map(|data| {
+                let digest = hex::decode(self.digest().hex_digest(data)).unwrap();
+                general_purpose::STANDARD.encode(digest)
+            })
+            .collect();
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|(name, data)| json!({ "attr": 0, "name": name, "size": data.len() }))
+            .collect();
+
+        json!({
+            "id": self.metafile_id,
+            "files": files,
+            "pad": [],
+            "pieces": {
+                "digests": digests,
+                "algorithm": self.pieces_algorithm,
+                "hashPadding": false,
+            },
+            "version": self.version,
+            "scanTime": 1_699_999_000_000u64,
+            "algorithm": "SHA-256",
+        })
+    }
+}
+
+/// Encodes claims as an (HS256-signed) JWT; the updater does not verify signatures
+pub fn encode_jwt(claims: &Value) -> Vec<u8> {
+    jsonwebtoken::encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(b"fixture"),
+    )
+    .unwrap()
+    .into_bytes()
+}
+
+/// Encodes piece data as a `.solidpiece` body: a 6-byte header followed by
+/// the compressed data
+pub fn encode_piece(data: &[u8], compression: PieceCompression) -> Vec<u8> {
+    let compressed = match compression {
+        PieceCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        PieceCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        PieceCompression::Zstd => zstd::encode_all(data, 0).unwrap(),
+        PieceCompression::Raw => data.to_vec(),
+    };
+
+    let mut piece = vec![0u8, 0u8];
+    piece.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    piece.extend_from_slice(&compressed);
+    piece
+}
+
+/// Builds a minimal PE32+ (amd64) image with the given sections
+///
+/// A section without data is uninitialized, like `.bss`: it takes 0x100
+/// bytes of memory but has no raw data in the file (`PointerToRawData` 0).
+pub fn pe_image(sections: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+    const FILE_ALIGNMENT: usize = 0x200;
+    const SECTION_ALIGNMENT: u32 = 0x1000;
+    let align = |len: usize| len.div_ceil(FILE_ALIGNMENT) * FILE_ALIGNMENT;
+    let put_u16 = |image: &mut Vec<u8>, value: u16| image.extend_from_slice(&value.to_le_bytes());
+    let put_u32 = |image: &mut Vec<u8>, value: u32| image.extend_from_slice(&value.to_le_bytes());
+    let put_u64 = |image: &mut Vec<u8>, value: u64| image.extend_from_slice(&value.to_le_bytes());
+
+    let headers_len = align(0x40 + 4 + 20 + 240 + 40 * sections.len());
+    let image_size = SECTION_ALIGNMENT * (sections.len() as u32 + 1);
+
+    // DOS header pointing at the NT headers right after it
+    let mut image = vec![0u8; 0x40];
+    image[..2].copy_from_slice(b"MZ");
+    image[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+
+    // COFF file header
+    image.extend_from_slice(b"PE\0\0");
+    put_u16(&mut image, 0x8664);
+    put_u16(&mut image, sections.len() as u16);
+    put_u32(&mut image, 0x6500_0000);
+    put_u32(&mut image, 0);
+    put_u32(&mut image, 0);
+    put_u16(&mut image, 240);
+    put_u16(&mut image, 0x0022);
+
+    // PE32+ optional header without data directories in use
+    put_u16(&mut image, 0x20b);
+    image.extend_from_slice(&[14, 0]);
+    for _ in 0..5 {
+        put_u32(&mut image, 0);
+    }
+    put_u64(&mut image, 0x1_4000_0000);
+    put_u32(&mut image, SECTION_ALIGNMENT);
+    put_u32(&mut image, FILE_ALIGNMENT as u32);
+    for version in [6, 0, 0, 0, 6, 0] {
+        put_u16(&mut image, version);
+    }
+    put_u32(&mut image, 0);
+    put_u32(&mut image, image_size);
+    put_u32(&mut image, headers_len as u32);
+    put_u32(&mut image, 0);
+    put_u16(&mut image, 3);
+    put_u16(&mut image, 0x8160);
+    for size in [0x10_0000, 0x1000, 0x10_0000, 0x1000] {
+        put_u64(&mut image, size);
+    }
+    put_u32(&mut image, 0);
+    put_u32(&mut image, 16);
+    image.extend_from_slice(&[0u8; 16 * 8]);
+
+    let mut raw_offset = headers_len;
+    let mut raw_data = Vec::new();
+    for (index, (name, data)) in sections.iter().enumerate() {
+        let mut section_name = [0u8; 8];
+        section_name[..name.len()].copy_from_slice(name.as_bytes());
+        image.extend_from_slice(&section_name);
+
+        let (virtual_size, raw_size, pointer, characteristics) = match data {
+            Some(data) => {
+                let raw_size = align(data.len());
+                let pointer = raw_offset;
+                raw_offset += raw_size;
+                raw_data.extend_from_slice(data);
+                raw_data.resize(raw_data.len() + raw_size - data.len(), 0);
+                (
+                    data.len() as u32,
+                    raw_size as u32,
+                    pointer as u32,
+                    0x4000_0040,
+                )
+            }
+            None => (0x100, 0, 0, 0xc000_0080),
+        };
+        put_u32(&mut image, virtual_size);
+        put_u32(&mut image, SECTION_ALIGNMENT * (index as u32 + 1));
+        put_u32(&mut image, raw_size);
+        put_u32(&mut image, pointer);
+        image.extend_from_slice(&[0u8; 12]);
+        put_u32(&mut image, characteristics);
+    }
+
+    image.resize(headers_len, 0);
+    image.extend_from_slice(&raw_data);
+    image
+}
+
+/// Failure a [`MockCdn`] applies to responses for a path
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Respond with this HTTP status and an empty body
+    Status(u16),
+    /// Announce the full length but close after this many body bytes
+    Truncate(usize),
+    /// Serve a piece whose data no longer matches its digest
+    BadChecksum,
+    /// Wait before responding
+    Delay(Duration),
+    /// Respond with this HTTP status and a `Retry-After` header
+    RetryAfter(u16, String),
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    resources: HashMap<String, Vec<u8>>,
+    /// Faults per path with the number of responses they still apply to
+    faults: HashMap<String, (Fault, Option<usize>)>,
+    requests: Vec<String>,
+    user_agents: Vec<String>,
+    signed: bool,
+}
+
+/// In-process HTTP server that serves a [`FixtureBuild`] like the CDN
+///
+/// Requests are answered with `Connection: close`, one per connection.
+pub struct MockCdn {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockCdn {
+    /// Starts a server for `build`; `signed` controls whether the config
+    /// documents carry a signature
+    pub async fn start(build: &FixtureBuild, signed: bool) -> Self {
+        let state = Arc::new(Mutex::new(MockState {
+            resources: mock_resources(build, signed),
+            signed,
+            ..Default::default()
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_state = Arc::clone(&state);
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, Arc::clone(&server_state)));
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            server,
+        }
+    }
+
+    /// Returns the CDN root URL of the server
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Serves `build` instead of the build the server was started with
+    pub fn serve(&self, build: &FixtureBuild) {
+        let mut state = self.state.lock().unwrap();
+        state.resources = mock_resources(build, state.signed);
+    }
+
+    /// Applies `fault` to every request for `path`
+    pub fn inject(&self, path: &str, fault: Fault) {
+        self.state
+            .lock()
+            .unwrap()
+            .faults
+            .insert(path.to_string(), (fault, None));
+    }
+
+    /// Applies `fault` to the next `times` requests for `path`
+    pub fn inject_times(&self, path: &str, fault: Fault, times: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .faults
+            .insert(path.to_string(), (fault, Some(times)));
+    }
+
+    /// Returns the paths requested so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the `User-Agent` of each request so far, in order
+    pub fn user_agents(&self) -> Vec<String> {
+        self.state.lock().unwrap().user_agents.clone()
+    }
+}
+
+impl Drop for MockCdn {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Returns the resources of `build` keyed by path, optionally unsigned
+fn mock_resources(build: &FixtureBuild, signed: bool) -> HashMap<String, Vec<u8>> {
+    let mut resources: HashMap<String, Vec<u8>> = build.resources().into_iter().collect();
+    if !signed {
+        for (path, body) in resources.iter_mut() {
+            if path.ends_with(".json") {
+                *body = strip_signature(body);
+            }
+        }
+    }
+    resources
+}
+
+/// Replaces the signature segment of a JWT with an empty one
+fn strip_signature(token: &[u8]) -> Vec<u8> {
+    let token = String::from_utf8_lossy(token);
+    let unsigned = token.rsplit_once('.').map(|(message, _)| message).unwrap();
+    format!("{}.", unsigned).into_bytes()
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await.is_err() {
+        return;
+    }
+    let mut user_agent = String::new();
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) if header == "\r\n" => break,
+            Ok(_) => {
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("user-agent") {
+                        user_agent = value.trim().to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .trim_start_matches('/')
+        .to_string();
+
+    let (body, fault) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(path.clone());
+        state.user_agents.push(user_agent);
+        let fault = match state.faults.get_mut(&path) {
+            Some((_, Some(0))) => None,
+            Some((fault, remaining)) => {
+                if let Some(remaining) = remaining {
+                    *remaining -= 1;
+                }
+                Some(fault.clone())
+            }
+            None => None,
+        };
+        (state.resources.get(&path).cloned(), fault)
+    };
+
+    let response = match (body, fault) {
+        (_, Some(Fault::Status(status))) => response_head(status, 0),
+        (_, Some(Fault::RetryAfter(status, retry_after))) => format!(
+            "HTTP/1.1 {} Mock\r\nRetry-After: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status, retry_after
+        )
+        .into_bytes(),
+        (None, _) => response_head(404, 0),
+        (Some(body), Some(Fault::Truncate(length))) => {
+            let mut response = response_head(200, body.len());
+            response.extend_from_slice(&body[..length.min(body.len())]);
+            response
+        }
+        (Some(_), Some(Fault::BadChecksum)) => {
+            let body = encode_piece(b"corrupted piece data", PieceCompression::Gzip);
+            let mut response = response_head(200, body.len());
+            response.extend_from_slice(&body);
+            response
+        }
+        (Some(body), fault) => {
+            if let Some(Fault::Delay(delay)) = fault {
+                tokio::time::sleep(delay).await;
+            }
+            let mut response = response_head(200, body.len());
+            response.extend_from_slice(&body);
+            response
+        }
+    };
+
+    let _ = stream.write_all(&response).await;
+    let _ = stream.shutdown().await;
+}
+
+fn response_head(status: u16, content_length: usize) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_length
+    )
+    .into_bytes()
+}
+
+/// In-process HTTP server that records JSON posts like a webhook endpoint
+///
+/// Requests are answered with the given statuses in order, then with `200`.
+pub struct MockWebhook {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+    server: JoinHandle<()>,
+}
+
+impl MockWebhook {
+    pub async fn start(statuses: Vec<u16>) -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_requests = Arc::clone(&requests);
+        let server = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Some((path, body)) = read_post(&mut stream).await else {
+                    continue;
+                };
+                server_requests.lock().unwrap().push((path, body));
+                let status = statuses.lock().unwrap().next().unwrap_or(200);
+                let _ = stream.write_all(&response_head(status, 0)).await;
+            }
+        });
+
+        Self {
+            addr,
+            requests,
+            server,
+        }
+    }
+
+    /// Returns the root URL of the server
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Returns the path and JSON body of each request so far, in order
+    pub fn requests(&self) -> Vec<(String, Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockWebhook {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A request received by [`MockWebhook`] or [`MockGitHub`]
+struct MockRequest {
+    method: String,
+    /// Path including the query string
+    path: String,
+    user_agent: String,
+    body: Vec<u8>,
+}
+
+/// Reads a request with an optional `Content-Length` body
+async fn read_request(stream: &mut TcpStream) -> Option<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.ok()?;
+    let mut content_length = 0;
+    let mut user_agent = String::new();
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header).await.ok()? {
+            0 => break,
+            _ if header == "\r\n" => break,
+            _ => {
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().ok()?;
+                    } else if name.eq_ignore_ascii_case("user-agent") {
+                        user_agent = value.trim().to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    let mut parts = request_line.split_whitespace();
+    Some(MockRequest {
+        method: parts.next()?.to_string(),
+        path: parts.next()?.to_string(),
+        user_agent,
+        body,
+    })
+}
+
+/// Reads a request with a `Content-Length` body and returns its path and JSON
+async fn read_post(stream: &mut TcpStream) -> Option<(String, Value)> {
+    let request = read_request(stream).await?;
+    let body = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+    Some((request.path, body))
+}
+
+/// A release held by [`MockGitHub`]
+#[derive(Debug, Clone, Default)]
+pub struct MockRelease {
+    pub id: u64,
+    pub tag: String,
+    pub name: String,
+    pub body: String,
+    pub prerelease: bool,
+    /// Asset ids, names and contents
+    pub assets: Vec<(u64, String, Vec<u8>)>,
+}
+
+#[derive(Debug, Default)]
+struct MockGitHubState {
+    releases: Vec<MockRelease>,
+    next_id: u64,
+    /// `METHOD path` of each request
+    requests: Vec<String>,
+    user_agents: Vec<String>,
+}
+
+impl MockGitHubState {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+/// In-process HTTP server implementing the part of the GitHub releases API
+/// the updater uses
+///
+/// Like GitHub, `releases/latest` skips pre-releases and returns the most
+/// recently created release. Every repository path shares one release list.
+pub struct MockGitHub {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockGitHubState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockGitHub {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockGitHubState::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_state = Arc::clone(&state);
+        let base = format!("http://{}/", addr);
+        let server = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Some(request) = read_request(&mut stream).await else {
+                    continue;
+                };
+                let (status, content_type, body) = {
+                    let mut state = server_state.lock().unwrap();
+                    state
+                        .requests
+                        .push(format!("{} {}", request.method, request.path));
+                    state.user_agents.push(request.user_agent.clone());
+                    github_response(&mut state, &base, &request)
+                };
+                let head = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content_type,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            server,
+        }
+    }
+
+    /// Returns the API root URL of the server
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// Returns a client talking to the server
+    pub fn client(&self) -> Octocrab {
+        Octocrab::builder()
+            .base_uri(self.url())
+            .unwrap()
+            .personal_token("token".to_string())
+            .build()
+            .unwrap()
+    }
+
+    /// Adds a release with `assets` and returns its id
+    pub fn add_release(
+        &self,
+        tag: &str,
+        body: &str,
+        prerelease: bool,
+        assets: &[(&str, &[u8])],
+    ) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id();
+        let assets = assets
+            .iter()
+            .map(|(name, data)| (state.next_id(), name.to_string(), data.to_vec()))
+            .collect();
+        state.releases.push(MockRelease {
+            id,
+            tag: tag.to_string(),
+            name: tag.to_string(),
+            body: body.to_string(),
+            prerelease,
+            assets,
+        });
+        id
+    }
+
+    /// Returns the releases in the order they were created
+    pub fn releases(&self) -> Vec<MockRelease> {
+        self.state.lock().unwrap().releases.clone()
+    }
+
+    /// Returns `METHOD path` of each request so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the `User-Agent` of each request so far, in order
+    pub fn user_agents(&self) -> Vec<String> {
+        self.state.lock().unwrap().user_agents.clone()
+    }
+}
+
+impl Drop for MockGitHub {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Answers a releases API request, returning status, content type and body
+fn github_response(
+    state: &mut MockGitHubState,
+    base: &str,
+    request: &MockRequest,
+) -> (u16, &'static str, Vec<u8>) {
+    let (path, query) = request
+        .path
+        .trim_start_matches('/')
+        .split_once('?')
+        .unwrap_or((request.path.trim_start_matches('/'), ""));
+    let segments: Vec<&str> = path.split('/').collect();
+    let json =
+        |status: u16, value: Value| (status, "application/json", value.to_string().into_bytes());
+    let not_found = || {
+        json(
+            404,
+            json!({ "message": "Not Found", "documentation_url": "https://docs.github.com" }),
+        )
+    };
+    let [_repos, owner, repo, _releases, rest @ ..] = segments.as_slice() else {
+        return not_found();
+    };
+    let prefix = format!("{}repos/{}/{}/releases", base, owner, repo);
+    let find = |state: &MockGitHubState, id: &str| {
+        state
+            .releases
+            .iter()
+            .position(|release| id.parse() == Ok(release.id))
+    };
+    let request_json: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+
+    match (request.method.as_str(), rest) {
+        ("GET", ["latest"]) => match state.releases.iter().rev().find(|r| !r.prerelease) {
+            Some(release) => json(200, release_json(&prefix, release)),
+            None => not_found(),
+        },
+        ("GET", ["tags", tag]) => match state.releases.iter().find(|r| r.tag == *tag) {
+            Some(release) => json(200, release_json(&prefix, release)),
+            None => not_found(),
+        },
+        ("GET", []) => json(
+            200,
+            Value::Array(
+                state
+                    .releases
+                    .iter()
+                    .rev()
+                    .map(|release| release_json(&prefix, release))
+                    .collect(),
+            ),
+        ),
+        ("GET", ["assets", id]) => {
+            let asset = state
+                .releases
+                .iter()
+                .flat_map(|release| &release.assets)
+                .find(|(asset_id, _, _)| id.parse() == Ok(*asset_id));
+            match asset {
+                Some((_, _, data)) => (200, "application/octet-stream", data.clone()),
+                None => not_found(),
+            }
+        }
+        ("DELETE", ["assets", id]) => {
+            for release in &mut state.releases {
+                release
+                    .assets
+                    .retain(|(asset_id, _, _)| id.parse() != Ok(*asset_id));
+            }
+            (204, "application/json", Vec::new())
+        }
+        ("GET", [id]) => match find(state, id) {
+            Some(index) => json(200, release_json(&prefix, &state.releases[index])),
+            None => not_found(),
+        },
+        ("POST", []) => {
+            let tag = request_json["tag_name"].as_str().unwrap_or_default();
+            if state.releases.iter().any(|release| release.tag == tag) {
+                return json(422, json!({ "message": "Validation Failed" }));
+            }
+            let release = MockRelease {
+                id: state.next_id(),
+                tag: tag.to_string(),
+                name: request_json["name"].as_str().unwrap_or(tag).to_string(),
+                body: request_json["body"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                prerelease: request_json["prerelease"].as_bool().unwrap_or_default(),
+                assets: Vec::new(),
+            };
+            let response = release_json(&prefix, &release);
+            state.releases.push(release);
+            json(201, response)
+        }
+        ("PATCH", [id]) => {
+            let Some(index) = find(state, id) else {
+                return not_found();
+            };
+            let release = &mut state.releases[index];
+            if let Some(body) = request_json["body"].as_str() {
+                release.body = body.to_string();
+            }
+            if let Some(name) = request_json["name"].as_str() {
+                release.name = name.to_string();
+            }
+            if let Some(prerelease) = request_json["prerelease"].as_bool() {
+                release.prerelease = prerelease;
+            }
+            json(200, release_json(&prefix, release))
+        }
+        ("POST", [id, "assets"]) => {
+            let Some(index) = find(state, id) else {
+                return not_found();
+            };
+            let name = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("name="))
+                .unwrap_or_default()
+                .to_string();
+            let asset_id = state.next_id();
+            let release = &mut state.releases[index];
+            release
+                .assets
+                .push((asset_id, name.clone(), request.body.clone()));
+            json(
+                201,
+                asset_json(&prefix, asset_id, &name, request.body.len()),
+            )
+        }
+        _ => not_found(),
+    }
+}
+
+/// Renders `release` like the GitHub API, with URLs below `prefix`
+fn release_json(prefix: &str, release: &MockRelease) -> Value {
+    json!({
+        "url": format!("{}/{}", prefix, release.id),
+        "html_url": format!("{}/{}", prefix, release.id),
+        "assets_url": format!("{}/{}/assets", prefix, release.id),
+        "upload_url": format!("{}/{}/assets{{?name,label}}", prefix, release.id),
+        "tarball_url": null,
+        "zipball_url": null,
+        "id": release.id,
+        "node_id": format!("R_{}", release.id),
+        "tag_name": release.tag,
+        "target_commitish": "main",
+        "name": release.name,
+        "body": release.body,
+        "draft": false,
+        "prerelease": release.prerelease,
+        "created_at": "2024-01-01T00:00:00Z",
+        "published_at": "2024-01-01T00:00:00Z",
+        "author": null,
+        "assets": release
+            .assets
+            .iter()
+            .map(|(id, name, data)| asset_json(prefix, *id, name, data.len()))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn asset_json(prefix: &str, id: u64, name: &str, size: usize) -> Value {
+    json!({
+        "url": format!("{}/assets/{}", prefix, id),
+        "browser_download_url": format!("{}/assets/{}", prefix, id),
+        "id": id,
+        "node_id": format!("RA_{}", id),
+        "name": name,
+        "label": null,
+        "state": "uploaded",
+        "content_type": "application/octet-stream",
+        "size": size,
+        "download_count": 0,
+        "created_at": "2024-01-01T00:00:00Z",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "uploader": null,
+    })
+}
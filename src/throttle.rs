@@ -0,0 +1,156 @@
+use anyhow::{bail, Context, Result};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+use crate::history::days_from_civil;
+
+/// Limits the combined throughput of all transfers sharing it
+///
+/// Every transfer reserves time for the bytes it received; once the
+/// reservations run ahead of the clock, callers sleep until their bytes
+/// are "paid for". This keeps the average rate at the limit across any
+/// number of concurrent downloads.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Accounts for `bytes` received and waits until they fit into the limit
+    pub async fn acquire(&self, bytes: usize) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let ready_at = {
+            let mut next_free = self.next_free.lock().unwrap();
+            let start = (*next_free).max(Instant::now());
+            *next_free = start + cost;
+            *next_free
+        };
+        tokio::time::sleep_until(ready_at).await;
+    }
+}
+
+/// Parses a byte rate such as `500000`, `500K` or `2M` (binary multiples)
+pub fn parse_rate(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let (number, multiplier) = match text.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&text[..index], 1024),
+        Some((index, 'm' | 'M')) => (&text[..index], 1024 * 1024),
+        Some((index, 'g' | 'G')) => (&text[..index], 1024 * 1024 * 1024),
+        _ => (text, 1),
+    };
+    let rate = number
+        .trim()
+        .parse::<u64>()
+        .with_context(|| format!("Invalid byte rate: {:?}", text))?
+        .checked_mul(multiplier)
+        .with_context(|| format!("Byte rate too large: {:?}", text))?;
+    if rate == 0 {
+        bail!("Byte rate must be greater than zero");
+    }
+    Ok(rate)
+}
+
+/// Parses a `Retry-After` header: either delay seconds or an HTTP date
+///
+/// Dates in the past yield a zero delay. Returns `None` for values that are
+/// neither.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let target = UNIX_EPOCH + Duration::from_secs(parse_http_date(value)?);
+    Some(target.duration_since(now).unwrap_or_default())
+}
+
+/// Parses an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`) into Unix seconds
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (_, date) = value.split_once(", ")?;
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let [day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| name == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("1500").unwrap(), 1500);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("2m").unwrap(), 2 * 1024 * 1024);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("K").is_err());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_770);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            parse_retry_after("Sat, 05 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(
+            parse_retry_after("Sun, 06 Foo 1994 08:49:37 GMT", now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2000 12:00:00 GMT"),
+            Some(951_825_600)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spreads_transfers() {
+        let limiter = RateLimiter::new(10_000);
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(1000).await;
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(290), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+}
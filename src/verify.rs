@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use crate::delta::{BuildIndex, BUILD_INDEX_FILE};
+use crate::manifest::list_files;
+
+/// A file whose length differs from its metafile entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeMismatch {
+    pub name: String,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Result of checking an extracted build against its metafile
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Verification {
+    /// Sum of the file sizes and padding listed in the metafile
+    pub expected_bytes: u64,
+    /// Decompressed size of all pieces
+    pub piece_bytes: u64,
+    pub files_checked: usize,
+    pub missing: Vec<String>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    /// Files in the directory that are not part of the build
+    pub stray: Vec<String>,
+}
+
+impl Verification {
+    /// Returns true if the directory contains exactly the build's files
+    pub fn is_valid(&self) -> bool {
+        self.expected_bytes == self.piece_bytes
+            && self.missing.is_empty()
+            && self.size_mismatches.is_empty()
+            && self.stray.is_empty()
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Files checked:  {}", self.files_checked)?;
+        writeln!(
+            f,
+            "Piece bytes:    {} (metafile expects {})",
+            self.piece_bytes, self.expected_bytes
+        )?;
+        for name in &self.missing {
+            writeln!(f, "Missing:        {}", name)?;
+        }
+        for mismatch in &self.size_mismatches {
+            writeln!(
+                f,
+                "Wrong size:     {} ({} bytes, expected {})",
+                mismatch.name, mismatch.actual, mismatch.expected
+            )?;
+        }
+        for name in &self.stray {
+            writeln!(f, "Stray file:     {}", name)?;
+        }
+        writeln!(
+            f,
+            "Result:         {}",
+            if self.is_valid() { "valid" } else { "INVALID" }
+        )
+    }
+}
+
+/// Checks the build extracted in `dir` against its index
+///
+/// Verifies that the pieces added up to exactly the files plus padding, that
+/// every file exists with its metafile size, and that no other files remain.
+/// The build index and the `generated` files, relative to `dir`, are not
+/// considered stray.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read.
+pub fn verify_build(dir: &Path, index: &BuildIndex, generated: &[&str]) -> Result<Verification> {
+    let mut verification = Verification {
+        expected_bytes: index.files.iter().map(|file| file.size).sum::<u64>() + index.padding(),
+        piece_bytes: index.pieces.iter().map(|piece| piece.size).sum(),
+        files_checked: index.files.len(),
+        ..Default::default()
+    };
+
+    for file in &index.files {
+        let path = dir.join(&file.name);
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {
+                if metadata.len() != file.size {
+                    verification.size_mismatches.push(SizeMismatch {
+                        name: file.name.clone(),
+                        expected: file.size,
+                        actual: metadata.len(),
+                    });
+                }
+            }
+            _ => verification.missing.push(file.name.clone()),
+        }
+    }
+
+    let expected: HashSet<&str> = index.files.iter().map(|file| file.name.as_str()).collect();
+    for path in list_files(dir, generated)? {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        if !expected.contains(name.as_str()) {
+            verification.stray.push(name);
+        }
+    }
+
+    Ok(verification)
+}
+
+/// Loads the build index of `dir` and verifies the build against it
+///
+/// # Errors
+///
+/// Returns an error if `dir` has no build index or cannot be read.
+pub fn verify_directory(dir: &Path, generated: &[&str]) -> Result<Verification> {
+    let index = BuildIndex::load(dir).with_context(|| {
+        format!(
+            "No {} in {}; was the build downloaded by this tool?",
+            BUILD_INDEX_FILE,
+            dir.display()
+        )
+    })?;
+    verify_build(dir, &index, generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::{IndexedFile, IndexedPiece};
+
+    fn index() -> BuildIndex {
+        BuildIndex {
+            metafile_id: "m".to_string(),
+            version: "1".to_string(),
+            digest_algorithm: "SHA-256".to_string(),
+            files: vec![
+                IndexedFile {
+                    name: "a.bin".to_string(),
+                    size: 4,
+                },
+                IndexedFile {
+                    name: "data/b.bin".to_string(),
+                    size: 2,
+                },
+            ],
+            pieces: vec![IndexedPiece {
+                digest: "00".to_string(),
+                size: 6,
+            }],
+            pads: Vec::new(),
+        }
+    }
+
+    async fn write_build(dir: &Path) {
+        std::fs::create_dir(dir.join("data")).unwrap();
+        std::fs::write(dir.join("a.bin"), b"abcd").unwrap();
+        std::fs::write(dir.join("data/b.bin"), b"ef").unwrap();
+        std::fs::write(dir.join("build.zip"), b"zip").unwrap();
+        index().save(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_valid_build() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_build(temp_dir.path()).await;
+
+        let verification = verify_directory(temp_dir.path(), &["build.zip"]).unwrap();
+        assert!(verification.is_valid(), "{}", verification);
+        assert_eq!(verification.files_checked, 2);
+    }
+
+    #[tokio::test]
+    async fn test_verify_detects_missing_resized_and_stray_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_build(temp_dir.path()).await;
+        std::fs::write(temp_dir.path().join("a.bin"), b"abcde").unwrap();
+        std::fs::remove_file(temp_dir.path().join("data/b.bin")).unwrap();
+        std::fs::write(temp_dir.path().join("leftover.txt"), b"old").unwrap();
+        std::fs::write(temp_dir.path().join("old.zip"), b"zip").unwrap();
+
+        let verification = verify_directory(temp_dir.path(), &["build.zip"]).unwrap();
+        assert!(!verification.is_valid());
+        assert_eq!(verification.missing, vec!["data/b.bin"]);
+        assert_eq!(
+            verification.size_mismatches,
+            vec![SizeMismatch {
+                name: "a.bin".to_string(),
+                expected: 4,
+                actual: 5,
+            }]
+        );
+        assert_eq!(verification.stray, vec!["leftover.txt", "old.zip"]);
+    }
+
+    #[tokio::test]
+    async fn test_verify_detects_piece_size_mismatch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_build(temp_dir.path()).await;
+        let mut index = index();
+        index.pieces[0].size = 7;
+
+        let verification = verify_build(temp_dir.path(), &index, &["build.zip"]).unwrap();
+        assert!(!verification.is_valid());
+        assert!(verification.to_string().contains("7 (metafile expects 6)"));
+    }
+
+    #[test]
+    fn test_verify_requires_build_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert!(verify_directory(temp_dir.path(), &["build.zip"]).is_err());
+    }
+}
@@ -1,12 +1,11 @@
 use anyhow::{Context, Result};
 use pelite::pe32::Pe as Pe32;
 use pelite::pe64::Pe as Pe64;
-use pelite::resources::version_info::Language;
 use pelite::FileMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-const DEFAULT_VERSION: &str = "NONE";
+use crate::actions::{annotate, Annotation};
 
 /// Represents version information extracted from a PE executable
 #[derive(Debug, Clone)]
@@ -148,51 +147,341 @@ pub fn extract_product_version(file_path: &Path) -> Result<Option<String>> {
     }
 }
 
+/// A four-part PE file version (`major.minor.build.revision`) with ordering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BuildVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub revision: u32,
+}
+
+impl BuildVersion {
+    /// Parses a version such as `1.2.3.4` or `1, 2, 3, 4`
+    ///
+    /// Between one and four numeric parts are accepted; missing trailing
+    /// parts are treated as zero.
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts = text
+            .split(['.', ','])
+            .map(|part| part.trim().parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        if parts.is_empty() || parts.len() > 4 {
+            return None;
+        }
+
+        let part = |i: usize| parts.get(i).copied().unwrap_or(0);
+        Some(Self {
+            major: part(0),
+            minor: part(1),
+            build: part(2),
+            revision: part(3),
+        })
+    }
+
+    /// Finds the last dotted version embedded in `text`, e.g. the file
+    /// version inside a templated tag such as `osrs-win-1.2.3.4`
+    pub fn find_in(text: &str) -> Option<Self> {
+        text.rsplit(|c: char| !c.is_ascii_digit() && c != '.')
+            .map(|candidate| candidate.trim_matches('.'))
+            .filter(|candidate| candidate.contains('.'))
+            .find_map(Self::parse)
+    }
+}
+
+impl std::fmt::Display for BuildVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.build, self.revision
+        )
+    }
+}
+
+/// How a new build's version relates to the previously released one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionChange {
+    /// No previous release to compare against
+    Initial,
+    Upgrade,
+    Same,
+    /// The new build is older than the previously released one
+    Downgrade,
+    /// At least one of the versions could not be parsed
+    Unknown,
+}
+
+impl VersionChange {
+    /// Classifies the change from `previous` to `current`, either of which
+    /// may be a plain version or a tag containing one
+    pub fn classify(previous: Option<&str>, current: &str) -> Self {
+        let Some(previous) = previous else {
+            return Self::Initial;
+        };
+
+        let parse = |text| BuildVersion::parse(text).or_else(|| BuildVersion::find_in(text));
+        match (parse(previous), parse(current)) {
+            (Some(previous), Some(current)) => match current.cmp(&previous) {
+                std::cmp::Ordering::Greater => Self::Upgrade,
+                std::cmp::Ordering::Equal => Self::Same,
+                std::cmp::Ordering::Less => Self::Downgrade,
+            },
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Returns true if the change rolls back to an older build
+    pub fn is_rollback(&self) -> bool {
+        matches!(self, Self::Downgrade)
+    }
+
+    /// Returns the lowercase name used in outputs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Initial => "initial",
+            Self::Upgrade => "upgrade",
+            Self::Same => "same",
+            Self::Downgrade => "downgrade",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for VersionChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Policy used to pick the executable whose version identifies a build
+#[derive(Debug, Clone)]
+pub struct VersionSelection {
+    /// Executable that is always preferred when it carries a file version
+    pub primary: Option<String>,
+    /// Fallback executables, tried in order when the primary has no version
+    pub priority: Vec<String>,
+    /// Whether subdirectories are scanned as well
+    pub recursive: bool,
+}
+
+impl Default for VersionSelection {
+    fn default() -> Self {
+        Self {
+            primary: Some("osclient.exe".to_string()),
+            priority: Vec::new(),
+            recursive: true,
+        }
+    }
+}
+
+/// File version found in a single executable during a directory scan
+#[derive(Debug, Clone)]
+pub struct ExecutableVersion {
+    /// Path relative to the scanned directory, using `/` as separator
+    pub path: String,
+    pub file_version: Option<String>,
+}
+
+impl ExecutableVersion {
+    /// Returns true if `name` refers to this executable, either by its
+    /// relative path or by its file name (case-insensitive)
+    fn matches(&self, name: &str) -> bool {
+        let name = name.replace('\\', "/");
+        let file_name = self.path.rsplit('/').next().unwrap_or(&self.path);
+        self.path.eq_ignore_ascii_case(&name) || file_name.eq_ignore_ascii_case(&name)
+    }
+}
+
+/// Finds all .exe and .dll files below `dir`, sorted by relative path
+///
+/// # Arguments
+///
+/// * `dir` - Directory to scan
+/// * `recursive` - Whether to descend into subdirectories
+pub fn find_executables(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut executables = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+
+        for entry in entries {
+            let entry = entry.with_context(|| {
+                format!("Failed to read directory entry in: {}", current.display())
+            })?;
+            let path = entry.path();
+
+            if path.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if path.is_file() && is_executable(&path) {
+                executables.push(path);
+            }
+        }
+    }
+
+    executables.sort();
+    Ok(executables)
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("exe") || extension.eq_ignore_ascii_case("dll")
+    })
+}
+
+/// Extracts the file version of every executable below `dir`
+///
+/// Files that cannot be parsed are reported with no version rather than
+/// failing the scan.
+pub fn scan_executable_versions(dir: &Path, recursive: bool) -> Result<Vec<ExecutableVersion>> {
+    let executables = find_executables(dir, recursive)?;
+
+    Ok(executables
+        .iter()
+        .map(|path| {
+            let relative = path
+                .strip_prefix(dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            let file_version = match extract_file_version(path) {
+                Ok(version) => version,
+                Err(e) => {
+                    log::debug!("Failed to read version info from {}: {}", relative, e);
+                    None
+                }
+            };
+
+            ExecutableVersion {
+                path: relative,
+                file_version,
+            }
+        })
+        .collect())
+}
+
+/// Selects the executable whose file version identifies the build
+///
+/// The configured primary executable wins, then the priority list in order,
+/// then the first versioned executable in sorted path order. Executables
+/// without a file version are never selected.
+pub fn select_primary_executable<'a>(
+    executables: &'a [ExecutableVersion],
+    selection: &VersionSelection,
+) -> Option<&'a ExecutableVersion> {
+    let versioned = || executables.iter().filter(|e| e.file_version.is_some());
+
+    selection
+        .primary
+        .iter()
+        .chain(selection.priority.iter())
+        .find_map(|name| versioned().find(|e| e.matches(name)))
+        .or_else(|| versioned().next())
+}
+
+/// Logs the version of every executable and warns about any that disagree
+/// with the selected one
+///
+/// Differing versions are common in a build and only logged. A CI warning
+/// is raised when the `primary` executable's version could not be used.
+pub fn log_version_report(
+    executables: &[ExecutableVersion],
+    selected: &ExecutableVersion,
+    primary: Option<&str>,
+) {
+    for executable in executables {
+        match &executable.file_version {
+            Some(version) => log::info!("Found version {} in {}", version, executable.path),
+            None => log::debug!("No version info found in {}", executable.path),
+        }
+    }
+
+    let selected_version = selected.file_version.as_deref();
+    for executable in executables {
+        if let Some(version) = executable.file_version.as_deref() {
+            if Some(version) != selected_version {
+                log::warn!(
+                    "Version mismatch: {} has {} but {} has {}",
+                    executable.path,
+                    version,
+                    selected.path,
+                    selected_version.unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    if let Some(warning) = primary_fallback_warning(executables, selected, primary) {
+        annotate(Annotation::Warning, &warning);
+    }
+}
+
+/// Describes why the version comes from another executable than `primary`,
+/// or returns `None` if `primary` was selected or none is configured
+fn primary_fallback_warning(
+    executables: &[ExecutableVersion],
+    selected: &ExecutableVersion,
+    primary: Option<&str>,
+) -> Option<String> {
+    let primary = primary?;
+    if selected.matches(primary) {
+        return None;
+    }
+
+    let reason = if executables.iter().any(|e| e.matches(primary)) {
+        "has no readable version"
+    } else {
+        "was not found"
+    };
+    Some(format!(
+        "Main executable {} {}, using version {} from {}",
+        primary,
+        reason,
+        selected.file_version.as_deref().unwrap_or_default(),
+        selected.path
+    ))
+}
+
 /// Scans a directory for executable files and extracts version information
 ///
 /// # Arguments
 ///
 /// * `dir` - Directory path to scan for .exe and .dll files
+/// * `selection` - Policy deciding which executable's version is used
 ///
 /// # Returns
 ///
-/// Returns the first file version found, or "NONE" if no version information is available.
-pub fn extract_versions_from_directory(dir: &Path) -> Result<String> {
+/// Returns the file version of the selected executable, or `None` if no
+/// executable carries version information.
+pub fn extract_versions_from_directory(
+    dir: &Path,
+    selection: &VersionSelection,
+) -> Result<Option<String>> {
     log::info!("Scanning directory for executable files: {}", dir.display());
 
-    let entries = fs::read_dir(dir)
-        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
-
-    for entry in entries {
-        let entry = entry
-            .with_context(|| format!("Failed to read directory entry in: {}", dir.display()))?;
-        let path = entry.path();
-
-        if path.is_file() {
-            if let Some(extension) = path.extension() {
-                if extension.eq_ignore_ascii_case("exe") || extension.eq_ignore_ascii_case("dll") {
-                    if let Ok(Some(version_info)) = extract_version_info(&path) {
-                        if let Some(file_version) = version_info.file_version {
-                            log::info!(
-                                "Found version {} in {}",
-                                file_version,
-                                path.file_name().unwrap_or_default().to_string_lossy()
-                            );
-                            return Ok(file_version);
-                        }
-                    }
-                    // Continue searching if this file had no version info
-                    log::debug!(
-                        "No version info found in {}",
-                        path.file_name().unwrap_or_default().to_string_lossy()
-                    );
-                }
-            }
+    let executables = scan_executable_versions(dir, selection.recursive)?;
+
+    match select_primary_executable(&executables, selection) {
+        Some(selected) => {
+            log_version_report(&executables, selected, selection.primary.as_deref());
+            log::info!(
+                "Using version {} from {}",
+                selected.file_version.as_deref().unwrap_or_default(),
+                selected.path
+            );
+            Ok(selected.file_version.clone())
+        }
+        None => {
+            log::warn!("No version information found in any executable files");
+            Ok(None)
         }
     }
-
-    log::warn!("No version information found in any executable files");
-    Ok(DEFAULT_VERSION.to_string())
 }
 
 #[cfg(test)]
@@ -216,7 +505,145 @@ mod tests {
 
     #[test]
     fn test_extract_versions_from_nonexistent_directory() {
-        let result = extract_versions_from_directory(&PathBuf::from("nonexistent"));
+        let result = extract_versions_from_directory(
+            &PathBuf::from("nonexistent"),
+            &VersionSelection::default(),
+        );
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_build_version_parse_and_order() {
+        let v = BuildVersion::parse("1, 2, 3, 4").unwrap();
+        assert_eq!(v.to_string(), "1.2.3.4");
+        assert_eq!(BuildVersion::parse("7.1").unwrap().to_string(), "7.1.0.0");
+        assert!(BuildVersion::parse("1.2.3.4.5").is_none());
+        assert!(BuildVersion::parse("abc").is_none());
+
+        assert!(BuildVersion::parse("1.10.0.0") > BuildVersion::parse("1.9.9.9"));
+        assert!(BuildVersion::parse("2.0.0.1") > BuildVersion::parse("2.0.0.0"));
+    }
+
+    #[test]
+    fn test_build_version_find_in_tag() {
+        assert_eq!(
+            BuildVersion::find_in("osrs-win-production-1.2.3.4").unwrap(),
+            BuildVersion::parse("1.2.3.4").unwrap()
+        );
+        assert!(BuildVersion::find_in("no-version-here").is_none());
+    }
+
+    #[test]
+    fn test_version_change_classify() {
+        assert_eq!(VersionChange::classify(None, "1.0"), VersionChange::Initial);
+        assert_eq!(
+            VersionChange::classify(Some("1.0.0.1"), "1.0.0.2"),
+            VersionChange::Upgrade
+        );
+        assert_eq!(
+            VersionChange::classify(Some("v1.0.0.2"), "1.0.0.2"),
+            VersionChange::Same
+        );
+        assert_eq!(
+            VersionChange::classify(Some("1.0.0.2"), "1.0.0.1"),
+            VersionChange::Downgrade
+        );
+        assert!(VersionChange::Downgrade.is_rollback());
+        assert_eq!(
+            VersionChange::classify(Some("NONE"), "1.0.0.1"),
+            VersionChange::Unknown
+        );
+    }
+
+    fn executable(path: &str, version: Option<&str>) -> ExecutableVersion {
+        ExecutableVersion {
+            path: path.to_string(),
+            file_version: version.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_select_primary_executable_prefers_primary() {
+        let executables = vec![
+            executable("a.dll", Some("1.0.0.0")),
+            executable("bin/OSClient.exe", Some("2.0.0.0")),
+        ];
+
+        let selected =
+            select_primary_executable(&executables, &VersionSelection::default()).unwrap();
+        assert_eq!(selected.path, "bin/OSClient.exe");
+    }
+
+    #[test]
+    fn test_select_primary_executable_falls_back_to_priority_then_sorted() {
+        let executables = vec![
+            executable("a.dll", Some("1.0.0.0")),
+            executable("b.dll", Some("3.0.0.0")),
+            executable("osclient.exe", None),
+        ];
+
+        let selection = VersionSelection {
+            priority: vec!["missing.dll".to_string(), "b.dll".to_string()],
+            ..VersionSelection::default()
+        };
+        let selected = select_primary_executable(&executables, &selection).unwrap();
+        assert_eq!(selected.path, "b.dll");
+
+        let selected =
+            select_primary_executable(&executables, &VersionSelection::default()).unwrap();
+        assert_eq!(selected.path, "a.dll");
+    }
+
+    #[test]
+    fn test_primary_fallback_warning() {
+        let primary = Some("osclient.exe");
+        let executables = vec![
+            executable("a.dll", Some("1.0.0.0")),
+            executable("osclient.exe", Some("2.0.0.0")),
+        ];
+        assert!(primary_fallback_warning(&executables, &executables[1], primary).is_none());
+        assert!(primary_fallback_warning(&executables, &executables[0], None).is_none());
+
+        let executables = vec![
+            executable("a.dll", Some("1.0.0.0")),
+            executable("osclient.exe", None),
+        ];
+        assert_eq!(
+            primary_fallback_warning(&executables, &executables[0], primary).unwrap(),
+            "Main executable osclient.exe has no readable version, using version 1.0.0.0 from a.dll"
+        );
+        assert!(
+            primary_fallback_warning(&executables[..1], &executables[0], primary)
+                .unwrap()
+                .contains("osclient.exe was not found")
+        );
+    }
+
+    #[test]
+    fn test_find_executables_sorted_and_recursive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("b.exe"), b"").unwrap();
+        fs::write(temp_dir.path().join("a.DLL"), b"").unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), b"").unwrap();
+        fs::write(temp_dir.path().join("sub").join("c.dll"), b"").unwrap();
+
+        let names = |recursive| {
+            find_executables(temp_dir.path(), recursive)
+                .unwrap()
+                .iter()
+                .map(|p| p.strip_prefix(temp_dir.path()).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(true),
+            vec![
+                PathBuf::from("a.DLL"),
+                PathBuf::from("b.exe"),
+                PathBuf::from("sub").join("c.dll")
+            ]
+        );
+        assert_eq!(names(false).len(), 2);
+    }
 }
@@ -0,0 +1,693 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::cdn::Cdn;
+use crate::config::{Config, Version};
+use crate::file_ops::AtomicFile;
+use crate::progress::format_duration;
+
+/// Shortest delay between two checks, whatever the jitter
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of missed checks after which the health endpoint reports stale
+const STALE_CHECKS: u32 = 3;
+
+/// Settings of the `watch` mode
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Time between two checks of the versions document
+    pub interval: Duration,
+    /// Maximum random deviation from `interval`, so several watchers do not
+    /// poll the CDN in lockstep
+    pub jitter: Duration,
+    /// File the watch state is persisted in across restarts
+    pub state_file: PathBuf,
+    /// Address to serve the health endpoint on
+    pub health_addr: Option<SocketAddr>,
+}
+
+impl WatchOptions {
+    /// Age of the last successful check after which the watcher is stale
+    fn stale_after(&self) -> Duration {
+        (self.interval + self.jitter) * STALE_CHECKS
+    }
+}
+
+/// Persistent state of the `watch` mode
+///
+/// All times are seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchState {
+    pub repo: String,
+    pub build: String,
+    /// `Version.id` of the last build the pipeline completed for
+    #[serde(default)]
+    pub version_id: Option<String>,
+    /// `Version.scan_time` of the last build the pipeline completed for
+    #[serde(default)]
+    pub scan_time: Option<u64>,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Last time the versions document was fetched
+    #[serde(default)]
+    pub last_check: Option<u64>,
+    /// Last time the pipeline was started
+    #[serde(default)]
+    pub last_run: Option<u64>,
+    /// Last time the pipeline completed
+    #[serde(default)]
+    pub last_success: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    #[serde(default)]
+    pub checks: u64,
+    #[serde(default)]
+    pub runs: u64,
+}
+
+impl WatchState {
+    pub fn new(repo: &str, build: &str) -> Self {
+        Self {
+            repo: repo.to_string(),
+            build: build.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Loads the state of `repo`.`build` from `path`
+    ///
+    /// A missing file, or one written for another build, yields a fresh
+    /// state so the first check runs the pipeline.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn load(path: &Path, repo: &str, build: &str) -> Result<Self> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::new(repo, build));
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read watch state: {}", path.display()))
+            }
+        };
+        let state: Self = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid watch state: {}", path.display()))?;
+
+        if state.repo != repo || state.build != build {
+            log::warn!(
+                "Ignoring watch state of {}.{} in {}",
+                state.repo,
+                state.build,
+                path.display()
+            );
+            return Ok(Self::new(repo, build));
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to `path`, replacing the previous file atomically
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize watch state")?;
+        let mut file = AtomicFile::create(path).await?;
+        file.write_all(json.as_bytes()).await?;
+        file.commit()
+            .await
+            .with_context(|| format!("Failed to write watch state: {}", path.display()))
+    }
+
+    /// Returns true if `version` differs from the last processed build
+    pub fn has_changed(&self, version: &Version) -> bool {
+        self.version_id.as_deref() != Some(version.id.as_str())
+            || self.scan_time != Some(version.scan_time)
+    }
+
+    /// Summarises the state for the health endpoint
+    ///
+    /// The watcher is unhealthy once the last pipeline run failed, or when
+    /// no check succeeded within `stale_after`.
+    pub fn health(&self, now: u64, stale_after: Duration) -> Health {
+        let status = match self.last_check {
+            _ if self.consecutive_failures > 0 => HealthStatus::Failing,
+            None => HealthStatus::Starting,
+            Some(last_check) if now.saturating_sub(last_check) > stale_after.as_secs() => {
+                HealthStatus::Stale
+            }
+            Some(_) => HealthStatus::Ok,
+        };
+        Health {
+            status,
+            state: self.clone(),
+        }
+    }
+}
+
+/// Outcome reported by the health endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// No check has completed yet
+    Starting,
+    Ok,
+    /// The last check or pipeline run failed
+    Failing,
+    /// No check succeeded for several intervals
+    Stale,
+}
+
+impl HealthStatus {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Self::Starting | Self::Ok)
+    }
+}
+
+/// Body of the health endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    #[serde(flatten)]
+    pub state: WatchState,
+}
+
+/// Polls the versions document and runs the pipeline when the build changes
+///
+/// A build is identified by its `Version.id` and `scan_time`; the pipeline
+/// runs whenever either differs from the last build it completed for. A
+/// failed run leaves the recorded build untouched, so it is retried on the
+/// next check.
+#[derive(Debug)]
+pub struct Watcher {
+    repo: String,
+    build: String,
+    cdn: Cdn,
+    options: WatchOptions,
+    state: Arc<Mutex<WatchState>>,
+}
+
+impl Watcher {
+    /// Creates a watcher for `repo`.`build`, restoring its persisted state
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file exists but cannot be loaded.
+    pub fn new(repo: &str, build: &str, cdn: Cdn, options: WatchOptions) -> Result<Self> {
+        let state = WatchState::load(&options.state_file, repo, build)?;
+        if let Some(version_id) = &state.version_id {
+            log::info!(
+                "Last processed build: {} (id {})",
+                state.version.as_deref().unwrap_or("unknown"),
+                version_id
+            );
+        }
+
+        Ok(Self {
+            repo: repo.to_string(),
+            build: build.to_string(),
+            cdn,
+            options,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Returns a copy of the current state
+    pub fn state(&self) -> WatchState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Checks the versions document once and runs `pipeline` if the build
+    /// changed
+    ///
+    /// # Returns
+    ///
+    /// Returns true if the pipeline ran and succeeded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the versions document cannot be loaded, the
+    /// pipeline fails, or the state cannot be saved.
+    pub async fn check<F, Fut>(&self, pipeline: F) -> Result<bool>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut config = Config::new(&self.repo, &self.build);
+        let version = match config.load_versions(&self.cdn).await {
+            Ok(config) => config.version.clone(),
+            Err(e) => {
+                self.update(|state| state.record_failure(&e)).await?;
+                return Err(e).context("Failed to check the versions document");
+            }
+        };
+
+        let now = unix_now();
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            state.last_check = Some(now);
+            state.checks += 1;
+            state.has_changed(&version)
+        };
+        if !changed {
+            log::info!(
+                "Build {} is unchanged (id {}, scan time {})",
+                version.version,
+                version.id,
+                version.scan_time
+            );
+            self.update(|state| {
+                state.last_error = None;
+                state.consecutive_failures = 0;
+            })
+            .await?;
+            return Ok(false);
+        }
+
+        log::info!(
+            "Build changed to {} (id {}, scan time {}), running the updater",
+            version.version,
+            version.id,
+            version.scan_time
+        );
+        self.update(|state| {
+            state.last_run = Some(now);
+            state.runs += 1;
+        })
+        .await?;
+
+        let result = pipeline().await;
+        match &result {
+            Ok(()) => {
+                self.update(|state| {
+                    state.version_id = Some(version.id.clone());
+                    state.scan_time = Some(version.scan_time);
+                    state.version = Some(version.version.clone());
+                    state.last_success = Some(unix_now());
+                    state.last_error = None;
+                    state.consecutive_failures = 0;
+                })
+                .await?
+            }
+            Err(e) => self.update(|state| state.record_failure(e)).await?,
+        }
+        result.map(|()| true)
+    }
+
+    /// Checks for changes every interval until interrupted
+    ///
+    /// Serves the health endpoint if an address is configured. Errors of
+    /// individual checks are logged and do not stop the watcher. `SIGINT`
+    /// and `SIGTERM` stop it, also while the pipeline is running; downloads
+    /// are staged, so an interrupted run leaves the previous build intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the health endpoint cannot be bound.
+    pub async fn run<F, Fut>(&self, mut pipeline: F) -> Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let _health_server = match self.options.health_addr {
+            Some(addr) => Some(self.serve_health(addr).await?),
+            None => None,
+        };
+
+        log::info!(
+            "Watching {}.{} every {} (jitter {})",
+            self.repo,
+            self.build,
+            format_duration(self.options.interval),
+            format_duration(self.options.jitter)
+        );
+
+        let watch = async {
+            loop {
+                if let Err(e) = self.check(&mut pipeline).await {
+                    log::error!("Watch check failed: {:#}", e);
+                }
+                let delay = jittered(self.options.interval, self.options.jitter, random_seed());
+                log::info!("Next check in {}", format_duration(delay));
+                tokio::time::sleep(delay).await;
+            }
+        };
+
+        tokio::select! {
+            () = watch => {}
+            result = shutdown_signal() => {
+                result?;
+                log::info!("Stopping watch");
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts serving the health endpoint on `addr`
+    ///
+    /// The server stops when the returned handle is dropped.
+    pub async fn serve_health(&self, addr: SocketAddr) -> Result<HealthServer> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind health endpoint to {}", addr))?;
+        let addr = listener
+            .local_addr()
+            .context("Failed to read health address")?;
+        log::info!("Serving health endpoint on http://{}/health", addr);
+
+        let state = Arc::clone(&self.state);
+        let stale_after = self.options.stale_after();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let health = state.lock().unwrap().health(unix_now(), stale_after);
+                tokio::spawn(async move {
+                    if let Err(e) = respond_health(stream, &health).await {
+                        log::debug!("Health request failed: {:#}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(HealthServer { addr, task })
+    }
+
+    /// Applies `change` to the state and persists it
+    async fn update(&self, change: impl FnOnce(&mut WatchState)) -> Result<()> {
+        let state = {
+            let mut state = self.state.lock().unwrap();
+            change(&mut state);
+            state.clone()
+        };
+        state.save(&self.options.state_file).await
+    }
+}
+
+impl WatchState {
+    fn record_failure(&mut self, error: &anyhow::Error) {
+        self.last_error = Some(format!("{:#}", error));
+        self.consecutive_failures += 1;
+    }
+}
+
+/// Running health endpoint; aborted when dropped
+#[derive(Debug)]
+pub struct HealthServer {
+    addr: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl HealthServer {
+    /// Returns the address the endpoint is bound to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for HealthServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers one HTTP request with the health JSON
+///
+/// `GET /health` and `GET /` return the state with `200 OK` while healthy and
+/// `503 Service Unavailable` otherwise; other paths return `404`.
+async fn respond_health(mut stream: TcpStream, health: &Health) -> Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header).await? {
+            0 => break,
+            _ if header == "\r\n" || header == "\n" => break,
+            _ => {}
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = match path {
+        "/" | "/health" => {
+            let body = serde_json::to_string_pretty(health)?;
+            if health.status.is_healthy() {
+                ("200 OK", body)
+            } else {
+                ("503 Service Unavailable", body)
+            }
+        }
+        _ => ("404 Not Found", "{\"error\": \"not found\"}".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Waits for `SIGINT`, or `SIGTERM` on Unix
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .context("Failed to listen for SIGTERM")?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.context("Failed to listen for SIGINT"),
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .context("Failed to listen for Ctrl-C")
+    }
+}
+
+/// Returns `interval` shifted by up to `jitter` in either direction
+///
+/// `seed` picks the shift; the result is never shorter than a second.
+fn jittered(interval: Duration, jitter: Duration, seed: u64) -> Duration {
+    let jitter_ms = jitter.as_millis() as u64;
+    if jitter_ms == 0 {
+        return interval.max(MIN_INTERVAL);
+    }
+    let offset = Duration::from_millis(seed % (2 * jitter_ms + 1));
+    (interval + offset).saturating_sub(jitter).max(MIN_INTERVAL)
+}
+
+/// Returns a seed that differs between calls and processes
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default();
+
+    // SplitMix64 finaliser, so close timestamps give unrelated seeds
+    let mut z = nanos ^ (u64::from(std::process::id()) << 32);
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Fault, FixtureBuild, MockCdn};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn options(dir: &Path) -> WatchOptions {
+        WatchOptions {
+            interval: Duration::from_secs(60),
+            jitter: Duration::from_secs(10),
+            state_file: dir.join("watch-state.json"),
+            health_addr: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_runs_pipeline_only_when_build_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut build = FixtureBuild::new(&[("osclient.exe", b"data")]);
+        let server = MockCdn::start(&build, true).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let runs = AtomicUsize::new(0);
+        let pipeline = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        };
+
+        let watcher = Watcher::new(
+            &build.repo,
+            &build.build,
+            cdn.clone(),
+            options(temp_dir.path()),
+        )
+        .unwrap();
+        assert!(watcher.check(pipeline).await.unwrap());
+        assert!(!watcher.check(pipeline).await.unwrap());
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(watcher.state().version_id.as_deref(), Some("version-1"));
+
+        // The processed build survives a restart
+        let watcher = Watcher::new(
+            &build.repo,
+            &build.build,
+            cdn.clone(),
+            options(temp_dir.path()),
+        )
+        .unwrap();
+        assert!(!watcher.check(pipeline).await.unwrap());
+
+        build.scan_time += 1;
+        server.serve(&build);
+        assert!(watcher.check(pipeline).await.unwrap());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(watcher.state().checks, 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_pipeline_is_retried() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let build = FixtureBuild::new(&[("osclient.exe", b"data")]);
+        let server = MockCdn::start(&build, true).await;
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let watcher =
+            Watcher::new(&build.repo, &build.build, cdn, options(temp_dir.path())).unwrap();
+
+        let error = watcher
+            .check(|| async { anyhow::bail!("download failed") })
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("download failed"));
+        let state = watcher.state();
+        assert_eq!(state.version_id, None);
+        assert_eq!(state.consecutive_failures, 1);
+        assert_eq!(
+            state.health(unix_now(), Duration::from_secs(60)).status,
+            HealthStatus::Failing
+        );
+
+        assert!(watcher.check(|| async { Ok(()) }).await.unwrap());
+        let state = watcher.state();
+        assert_eq!(state.consecutive_failures, 0);
+        assert_eq!(state.last_error, None);
+        assert_eq!(state.runs, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_versions_document_is_recorded() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let build = FixtureBuild::new(&[("osclient.exe", b"data")]);
+        let server = MockCdn::start(&build, true).await;
+        server.inject("osrs-win/osrs-win.json", Fault::Status(503));
+        let cdn = Cdn::new(Some(&server.url())).unwrap();
+        let watcher =
+            Watcher::new(&build.repo, &build.build, cdn, options(temp_dir.path())).unwrap();
+
+        assert!(watcher.check(|| async { Ok(()) }).await.is_err());
+        let state = WatchState::load(
+            &temp_dir.path().join("watch-state.json"),
+            &build.repo,
+            &build.build,
+        )
+        .unwrap();
+        assert_eq!(state.consecutive_failures, 1);
+        assert_eq!(state.runs, 0);
+        assert!(state.last_error.unwrap().contains("503"));
+    }
+
+    #[test]
+    fn test_health_status() {
+        let stale_after = Duration::from_secs(300);
+        let mut state = WatchState::new("osrs-win", "production");
+        assert_eq!(
+            state.health(1000, stale_after).status,
+            HealthStatus::Starting
+        );
+
+        state.last_check = Some(900);
+        assert_eq!(state.health(1000, stale_after).status, HealthStatus::Ok);
+        assert_eq!(state.health(1300, stale_after).status, HealthStatus::Stale);
+        assert!(!HealthStatus::Stale.is_healthy());
+    }
+
+    #[test]
+    fn test_state_of_other_build_is_ignored() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("state.json");
+        let mut state = WatchState::new("osrs-win", "beta");
+        state.version_id = Some("version-1".to_string());
+        std::fs::write(&path, serde_json::to_string(&state).unwrap()).unwrap();
+
+        let loaded = WatchState::load(&path, "osrs-win", "production").unwrap();
+        assert_eq!(loaded, WatchState::new("osrs-win", "production"));
+        assert_eq!(WatchState::load(&path, "osrs-win", "beta").unwrap(), state);
+    }
+
+    #[test]
+    fn test_jittered_interval() {
+        let interval = Duration::from_secs(60);
+        let jitter = Duration::from_secs(10);
+        assert_eq!(jittered(interval, jitter, 0), Duration::from_secs(50));
+        assert_eq!(jittered(interval, jitter, 20_000), Duration::from_secs(70));
+        for _ in 0..100 {
+            let delay = jittered(interval, jitter, random_seed());
+            assert!((Duration::from_secs(50)..=Duration::from_secs(70)).contains(&delay));
+        }
+        assert_eq!(
+            jittered(Duration::ZERO, Duration::ZERO, 7),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cdn = Cdn::new(Some("http://127.0.0.1:9/")).unwrap();
+        let watcher =
+            Watcher::new("osrs-win", "production", cdn, options(temp_dir.path())).unwrap();
+        let server = watcher
+            .serve_health("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("http://{}/health", server.addr());
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "starting");
+        assert_eq!(body["repo"], "osrs-win");
+
+        watcher.state.lock().unwrap().consecutive_failures = 2;
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), 503);
+
+        let response = reqwest::get(format!("http://{}/other", server.addr()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}